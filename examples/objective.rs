@@ -0,0 +1,29 @@
+extern crate smartcrop;
+
+use smartcrop::{Objective, SmartCrop};
+use std::env;
+use std::path::Path;
+
+fn main() {
+    let file = if env::args().count() == 2 {
+        env::args().nth(1).unwrap()
+    } else {
+        println!("[usage] objective FILE");
+        return;
+    };
+
+    let path = Path::new(file.as_str());
+    // maximize skin, but stop rewarding saturation past a point
+    let mut sc = SmartCrop::new();
+    sc.objective_fn = Some(Objective::new(|score, _| {
+        score.skin + score.detail * 0.2 + score.saturation.min(0.05)
+    }));
+    let mut opts = SmartCrop::default();
+    opts.width = 100;
+    opts.height = 100;
+
+    let default = SmartCrop::new().crop(path, &opts).unwrap();
+    let custom = sc.crop(path, &opts).unwrap();
+    println!("default:   {}", default.top_crop);
+    println!("objective: {}", custom.top_crop);
+}
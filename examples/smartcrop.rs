@@ -2,7 +2,7 @@ extern crate chrono;
 extern crate image;
 extern crate smartcrop;
 
-use chrono::UTC;
+use chrono::Utc;
 use smartcrop::SmartCrop;
 use std::env;
 use std::fs::File;
@@ -17,13 +17,13 @@ fn main() {
     };
 
     let path = Path::new(file.as_str());
-    let mut sc = SmartCrop::new();
+    let sc = SmartCrop::new();
     let mut opts = SmartCrop::default();
     opts.width = 100;
     opts.height = 100;
-    let start = UTC::now();
-    let result = sc.crop(path, &opts);
-    let end = UTC::now();
+    let start = Utc::now();
+    let result = sc.crop(path, &opts).unwrap();
+    let end = Utc::now();
     let diff = end - start;
     println!("[result]\n{:?}", result);
     println!("time elapsed: {:?}", diff.num_milliseconds());
@@ -31,7 +31,11 @@ fn main() {
 
     let mut img = image::open(path).unwrap();
     let output_img = img.crop(size.x, size.y, size.width, size.height);
-    let ref mut fout = File::create(&Path::new("out.jpg")).unwrap();
-    let save_img = output_img.resize(opts.width, opts.height, image::FilterType::Lanczos3);
-    let _ = save_img.save(fout, image::JPEG);
+    let mut fout = File::create(Path::new("out.jpg")).unwrap();
+    let save_img = output_img.resize(
+        opts.width,
+        opts.height,
+        image::imageops::FilterType::Lanczos3,
+    );
+    let _ = save_img.write_to(&mut fout, image::ImageFormat::Jpeg);
 }
@@ -1,481 +1,8647 @@
-
 use std::clone::Clone;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
 use std::fs::File;
+use std::io::{self, BufReader, Read};
 use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use image::DynamicImage::ImageRgb8;
-use image::{GenericImage, GenericImageView, ImageBuffer, Rgb, Rgba};
+use image::DynamicImage::{ImageRgb8, ImageRgba8};
+use image::{
+    ColorType, GenericImageView, GrayImage, ImageBuffer, Luma, Pixel, Rgb, RgbImage, Rgba,
+    RgbaImage,
+};
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug)]
-pub struct CropResult {
-    crops: Vec<CropInfo>,
-    pub top_crop: CropInfo,
+pub enum SmartCropError {
+    Image(image::ImageError),
+    // failure while decoding a TIFF strip by strip
+    Tiff(tiff::TiffError),
+    // boost regions that couldn't be parsed
+    InvalidBoosts(serde_json::Error),
+    // a caller-provided candidate that is empty or extends past the image
+    InvalidCandidate(CropSize),
+    // there was no candidate rectangle to choose from
+    NoCandidates,
+    // no candidate meets all of the given Constraints
+    ConstraintsUnmet,
+    // decoded pixels whose channels can't be mapped to RGB
+    UnsupportedColorType(ColorType),
+    // an image that decodes but can't be analyzed as a single picture, such as
+    // an animated GIF
+    UnsupportedForAnalysis(String),
+    // a band_mapping index past the bands the image has
+    InvalidBandMapping([usize; 3]),
+    // a video frame whose planes are too short for its size and strides
+    InvalidFrame(String),
 }
 
-#[derive(Clone, Debug, Default)]
-struct CropScore {
-    detail: f64,
-    saturation: f64,
-    skin: f64,
-    total: f64,
+impl fmt::Display for SmartCropError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SmartCropError::Image(err) => write!(f, "failed to load image: {}", err),
+            SmartCropError::Tiff(err) => write!(f, "failed to load image: {}", err),
+            SmartCropError::InvalidBoosts(err) => write!(f, "invalid boost regions: {}", err),
+            SmartCropError::InvalidCandidate(size) => write!(
+                f,
+                "candidate {}x{} at {},{} is outside the image",
+                size.width, size.height, size.x, size.y
+            ),
+            SmartCropError::NoCandidates => write!(f, "no candidate crops"),
+            SmartCropError::ConstraintsUnmet => {
+                write!(f, "no candidate crop satisfies every constraint")
+            }
+            SmartCropError::UnsupportedColorType(color) => {
+                write!(f, "unsupported color type for analysis: {:?}", color)
+            }
+            SmartCropError::UnsupportedForAnalysis(reason) => {
+                write!(f, "image can't be analyzed: {}", reason)
+            }
+            SmartCropError::InvalidBandMapping(mapping) => write!(
+                f,
+                "band mapping {:?} refers to bands the image doesn't have",
+                mapping
+            ),
+            SmartCropError::InvalidFrame(reason) => write!(f, "invalid video frame: {}", reason),
+        }
+    }
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct CropSize {
-    pub x: u32,
-    pub y: u32,
-    pub width: u32,
-    pub height: u32,
+impl Error for SmartCropError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SmartCropError::Image(err) => Some(err),
+            SmartCropError::Tiff(err) => Some(err),
+            SmartCropError::InvalidBoosts(err) => Some(err),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
-pub struct CropInfo {
-    pub size: CropSize,
-    score: CropScore,
+impl From<image::ImageError> for SmartCropError {
+    fn from(err: image::ImageError) -> SmartCropError {
+        SmartCropError::Image(err)
+    }
 }
 
-fn thirds(x: f64) -> f64 {
-    let y = ((x - (1. / 3.) + 1.0) % 2.0 * 0.5 - 0.5) * 16.;
-    f64::max(1.0 - y * y, 0.0)
+impl From<tiff::TiffError> for SmartCropError {
+    fn from(err: tiff::TiffError) -> SmartCropError {
+        SmartCropError::Tiff(err)
+    }
 }
 
-fn cie(r: f64, g: f64, b: f64) -> f64 {
-    0.5126 * b + 0.7152 * g + 0.0722 * r
+impl From<io::Error> for SmartCropError {
+    fn from(err: io::Error) -> SmartCropError {
+        SmartCropError::Image(image::ImageError::IoError(err))
+    }
 }
 
-fn sample(pixel: Rgba<u8>) -> f64 {
-    let r = pixel[0] as f64;
-    let g = pixel[1] as f64;
-    let b = pixel[2] as f64;
-    cie(r, g, b)
+// Everything analyze_full computes: the crop and the detection channels, at
+// the working (prescaled) resolution
+#[derive(Debug)]
+pub struct AnalysisOutput {
+    pub result: CropResult,
+    pub edge: GrayImage,
+    pub skin: GrayImage,
+    pub saturation: GrayImage,
 }
 
-fn saturation(pixel: Rgba<u8>) -> f64 {
-    let r = pixel[0] as f64;
-    let g = pixel[1] as f64;
-    let b = pixel[2] as f64;
-    let id = vec![r / 255., g / 255., b / 255.];
-    let maximum = id.iter().fold(0.0 / 0.0, |m, v| v.max(m));
-    let minumum = id.iter().fold(0.0 / 0.0, |m, v| v.min(m));
-    if maximum == minumum {
-        return 0.;
-    }
-    let l = (maximum + minumum) / 2.;
-    let d = maximum - minumum;
-    if l > 0.5 {
-        d / (2. - maximum - minumum)
-    } else {
-        d / (maximum + minumum)
-    }
+// Everything crop_with_debug computes, in memory: the detection channels and
+// the downsampled score map at the working (prescaled) resolution, and the
+// candidates drawn over the source image as by annotate
+#[derive(Debug)]
+pub struct DebugBundle {
+    pub edge: GrayImage,
+    pub skin: GrayImage,
+    pub saturation: GrayImage,
+    pub score_map: RgbImage,
+    pub annotated: RgbImage,
 }
 
-#[derive(Clone, Debug)]
-pub struct SmartCrop {
-    pub width: u32,
-    pub height: u32,
-    aspect: i32,
-    crop_width: i32,
-    crop_height: i32,
-    detail_weight: f64,
-    skin_color: (f64, f64, f64),
-    skin_bias: f64,
-    skin_brightness_min: f64,
-    skin_brightness_max: f64,
-    skin_threshold: f64,
-    skin_weight: f64,
-    saturation_brightness_min: f64,
-    saturation_brightness_max: f64,
-    saturation_threshold: f64,
-    saturation_bias: f64,
-    saturation_weight: f64,
-    // step * minscale rounded down to the next power of two should be good
-    score_down_sample: u32,
-    step: u32,
-    scale_step: f64,
-    min_scale: f64,
-    max_scale: f64,
-    edge_radius: f64,
-    edge_weight: f64,
-    outside_importance: f64,
-    rule_of_thirds: bool,
-    prescale: bool,
-    debug: bool,
-    // save_quality: i32,    // not support
-    file_type: String,
+#[derive(Debug)]
+pub struct CropResult {
+    crops: Vec<CropInfo>,
+    pub top_crop: CropInfo,
+    // factor the source image was resized by before analysis (1.0 when not prescaled)
+    pub prescale: f64,
+    // top_crop in the prescaled (working) image coordinates
+    pub prescaled_crop: CropSize,
+    // letterbox padding needed around top_crop to reach the target aspect (Fit
+    // mode), split so that the salient region stays centered
+    pub padding: Padding,
+    // whether top_crop is smaller than the target and has to be enlarged by upscale_factor
+    pub upscaled: bool,
+    pub upscale_factor: f64,
+    // score_down_sample actually used, reduced for images too small for the configured one
+    pub score_down_sample: u32,
+    // dimensions of the original image the crops refer to
+    pub image_width: u32,
+    pub image_height: u32,
+    // no candidate met min_saliency_coverage, so the best one was taken regardless
+    pub saliency_coverage_fallback: bool,
+    // no candidate (in Fit mode, the fitted crop) met every constraint, so the
+    // constraints were ignored
+    pub constraints_unmet: bool,
+    // width / height of top_crop, off the target's by up to aspect_tolerance
+    pub aspect: f64,
+    // (top - runner-up) / top score, where the runner-up is the best candidate
+    // that is a real alternative (overlapping top_crop by less than
+    // AMBIGUITY_MAX_IOU); 1 when there is none
+    pub confidence_margin: f64,
+    // confidence_margin is below ambiguity_threshold, worth a human review
+    pub ambiguous: bool,
+    // time_budget or candidate_budget ran out, so only the candidates in
+    // crops were scored
+    pub partial: bool,
+    // where the y of the rectangles in this result is measured from
+    pub coordinate_origin: CoordinateOrigin,
 }
 
-impl Default for SmartCrop {
-    fn default() -> SmartCrop {
-        SmartCrop {
-            width: 0,
-            height: 0,
-            aspect: 0,
-            crop_width: 0,
-            crop_height: 0,
-            detail_weight: 0.2,
-            skin_color: (0.78, 0.57, 0.44),
-            skin_bias: 0.01,
-            skin_brightness_min: 0.2,
-            skin_brightness_max: 1.0,
-            skin_threshold: 0.8,
-            skin_weight: 1.8,
-            saturation_brightness_min: 0.05,
-            saturation_brightness_max: 0.9,
-            saturation_threshold: 0.4,
-            saturation_bias: 0.2,
-            saturation_weight: 0.3,
-            // step * minscale rounded down to the next power of two should be good
-            score_down_sample: 8,
-            step: 8,
-            scale_step: 0.1,
-            min_scale: 0.9,
-            max_scale: 1.0,
-            edge_radius: 0.4,
-            edge_weight: -20.0,
-            outside_importance: -0.5,
-            rule_of_thirds: true,
-            prescale: true,
-            debug: false,
-            // save_quality: 90,
-            file_type: "JPEG".to_string(),
-        }
-    }
+// A scored candidate of a CropResult, flattened for external re-ranking
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RankedCrop {
+    pub size: CropSize,
+    pub detail: f64,
+    pub skin: f64,
+    pub saturation: f64,
+    pub total: f64,
 }
 
-impl SmartCrop {
-    pub fn new() -> SmartCrop {
-        SmartCrop::default()
+impl fmt::Display for CropResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} of {} candidates", self.top_crop, self.crops.len())
     }
+}
 
-    pub fn crop(&mut self, path: &Path, opts: &SmartCrop) -> CropResult {
-        let mut options = (*opts).clone();
-        let mut img = image::open(path).unwrap();
-        let (img_width, img_height) = img.dimensions();
-
-        let mut scale = 1.;
-        let mut prescale = 1.;
-        if options.width != 0 && options.height != 0 {
-            scale = f64::min(
-                img_width as f64 / options.width as f64,
-                img_height as f64 / options.height as f64,
-            );
-            options.crop_width = f64::floor(options.width as f64 * scale) as i32;
-            options.crop_height = f64::floor(options.height as f64 * scale) as i32;
-            // img = 100x100, width = 95x95, scale = 100/95, 1/scale > min
-            // don't set minscale smaller than 1/scale
-            // -> don't pick crops that need upscaling
-            options.min_scale =
-                f64::min(options.max_scale, f64::max(1. / scale, (options.min_scale)));
-        }
+// Walks the candidates by descending total score, which starts with top_crop
+// unless objective_fn or min_saliency_coverage chose another crop
+impl<'a> IntoIterator for &'a CropResult {
+    type Item = &'a CropInfo;
+    type IntoIter = std::vec::IntoIter<&'a CropInfo>;
 
-        if options.width != 0 && options.height != 0 && options.prescale != false {
-            prescale = 1. / scale / options.min_scale;
-            if prescale < 1. {
-                img = img.resize(
-                    (img_width as f64 * prescale) as u32,
-                    (img_height as f64 * prescale) as u32,
-                    image::imageops::FilterType::Lanczos3,
-                );
-                if self.debug {
-                    //let ref mut fout = File::create(&Path::new("debug.thumb.jpg")).unwrap();
-                    let _ = img.save_with_format("debug.thumb.jpg", image::ImageFormat::Jpeg);
-                }
-                self.crop_width = f64::floor(options.crop_width as f64 * prescale) as i32;
-                self.crop_height = f64::floor(options.crop_height as f64 * prescale) as i32;
-            } else {
-                prescale = 1.;
-            }
-        }
+    fn into_iter(self) -> Self::IntoIter {
+        self.ranked().into_iter()
+    }
+}
 
-        let mut result = self.analyse(img);
-        for crop in result.crops.iter_mut() {
-            (*crop).size = CropSize {
-                x: ((*crop).size.x as f64 / prescale).floor() as u32,
-                y: ((*crop).size.y as f64 / prescale).floor() as u32,
-                width: ((*crop).size.width as f64 / prescale).floor() as u32,
-                height: ((*crop).size.height as f64 / prescale).floor() as u32,
-            };
-        }
+impl CropResult {
+    // candidates ordered by descending total score
+    fn ranked(&self) -> Vec<&CropInfo> {
+        let mut crops: Vec<&CropInfo> = self.crops.iter().collect();
+        crops.sort_by(|a, b| b.score.total.total_cmp(&a.score.total));
+        crops
+    }
 
-        result.top_crop.size = CropSize {
-            x: (result.top_crop.size.x as f64 / prescale).floor() as u32,
-            y: (result.top_crop.size.y as f64 / prescale).floor() as u32,
-            width: (result.top_crop.size.width as f64 / prescale).floor() as u32,
-            height: (result.top_crop.size.height as f64 / prescale).floor() as u32,
+    /// Picks up to `n` crops, starting with `top_crop` and then greedily taking
+    /// the best candidate whose overlap (intersection over union) with every
+    /// crop picked so far is below `min_iou_gap`, for visually distinct options.
+    /// The gap is clamped to [0, 1] (NaN counts as 1), and no crop is picked
+    /// twice.
+    pub fn diverse_crops(&self, n: usize, min_iou_gap: f64) -> Vec<&CropInfo> {
+        let min_iou_gap = if min_iou_gap.is_nan() {
+            1.
+        } else {
+            min_iou_gap.clamp(0., 1.)
         };
-
-        result
+        let mut picked: Vec<&CropInfo> = Vec::new();
+        for crop in std::iter::once(&self.top_crop).chain(self.ranked()) {
+            if picked.len() >= n {
+                break;
+            }
+            if picked
+                .iter()
+                .all(|other| other.size != crop.size && crop.size.iou(&other.size) < min_iou_gap)
+            {
+                picked.push(crop);
+            }
+        }
+        picked
     }
 
-    fn detect_edge(
-        &mut self,
-        img: &image::DynamicImage,
-        output: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-    ) {
-        let (w, h) = img.dimensions();
-        for (x, y, output_pixel) in output.enumerate_pixels_mut() {
-            let pixel = img.get_pixel(x, y);
-            let mut lightness = if x == 0 || x >= w - 1 || y == 0 || y >= h - 1 {
-                sample(pixel)
-            } else {
-                sample(pixel) * 4.
-                    - sample(img.get_pixel(x - 1, y))
-                    - sample(img.get_pixel(x, y - 1))
-                    - sample(img.get_pixel(x, y + 1))
-                    - sample(img.get_pixel(x + 1, y))
-            };
-            lightness = if lightness < 0. {
-                0.
-            } else if lightness > 255. {
-                255.
-            } else {
-                lightness
-            };
-            *output_pixel = Rgb([pixel[0], lightness as u8, pixel[2]]);
-        }
-        if self.debug {
-            let _ = output.save("edge.jpg");
-        }
+    /// Every candidate with its score components, ordered by descending total
+    /// score.
+    pub fn ranked_candidates(&self) -> Vec<RankedCrop> {
+        self.ranked()
+            .into_iter()
+            .map(|crop| RankedCrop {
+                size: crop.size.clone(),
+                detail: crop.score.detail,
+                skin: crop.score.skin,
+                saturation: crop.score.saturation,
+                total: crop.score.total,
+            })
+            .collect()
     }
 
-    fn detect_skin(
-        &mut self,
-        img: &image::DynamicImage,
-        output: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-    ) {
-        for (x, y, output_pixel) in output.enumerate_pixels_mut() {
-            let pixel = img.get_pixel(x, y);
-            let lightness = sample(pixel) / 255.;
-            let skin = self.get_skin_color(pixel);
-            let r: u8 = if skin > self.skin_threshold
-                && lightness >= self.skin_brightness_min
-                && lightness <= self.skin_brightness_max
-            {
-                let mut tr = (skin - self.skin_threshold) * (255. / (1. - self.skin_threshold));
-                tr = if tr < 0. {
-                    0.
-                } else if tr > 255. {
-                    255.
-                } else {
-                    tr
-                };
-                tr as u8
-            } else {
-                0
-            };
-            *output_pixel = Rgb([r, output_pixel[1], output_pixel[2]]);
-        }
-        if self.debug {
-            let _ = output.save("skin.jpg");
+    /// `size`, a rectangle of this result, in top-left origin coordinates.
+    pub fn top_left(&self, size: &CropSize) -> CropSize {
+        match self.coordinate_origin {
+            CoordinateOrigin::TopLeft => size.clone(),
+            CoordinateOrigin::BottomLeft => size.flip_y(self.image_height),
         }
     }
 
-    fn detect_saturation(
-        &mut self,
-        img: &image::DynamicImage,
-        output: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-    ) {
-        for (x, y, output_pixel) in output.enumerate_pixels_mut() {
-            let pixel = img.get_pixel(x, y);
-            let lightness = sample(pixel) / 255.;
-            let sat = saturation(pixel);
-            let b: u8 = if sat > self.saturation_threshold
-                && lightness >= self.saturation_brightness_min
-                && lightness <= self.saturation_brightness_max
-            {
-                let mut tr =
-                    (sat - self.saturation_threshold) * (255. / (1. - self.saturation_threshold));
-                tr = if tr < 0. {
-                    0.
-                } else if tr > 255. {
-                    255.
-                } else {
-                    tr
-                };
-                tr as u8
-            } else {
-                0
-            };
-            *output_pixel = Rgb([output_pixel[0], output_pixel[1], b]);
-        }
-        if self.debug {
-            let _ = output.save("sat.jpg");
-        }
+    /// Returns `top_crop` as `[u0, v0, u1, v1]` texture coordinates relative to
+    /// the original image, with v running down from its top edge.
+    pub fn top_crop_uv(&self) -> [f32; 4] {
+        let size = &self.top_left(&self.top_crop.size);
+        let (width, height) = (self.image_width as f64, self.image_height as f64);
+        [
+            (size.x as f64 / width) as f32,
+            (size.y as f64 / height) as f32,
+            ((size.x + size.width) as f64 / width) as f32,
+            ((size.y + size.height) as f64 / height) as f32,
+        ]
     }
 
-    fn get_skin_color(&mut self, pixel: Rgba<u8>) -> f64 {
-        let r = pixel[0] as f64;
-        let g = pixel[1] as f64;
-        let b = pixel[2] as f64;
-        let (rt, gt, bt) = self.skin_color;
-        let mag = f64::sqrt(r * r + g * g + b * b);
-        let (rd, gd, bd) = if mag == 0. {
-            (-rt, -gt, -bt)
-        } else {
-            (r / mag - rt, g / mag - gt, b / mag - bt)
-        };
+    /// Returns `top_crop` as an ImageMagick geometry, `WxH+X+Y` in original
+    /// image pixels, e.g. for `convert -crop`.
+    pub fn imagemagick_geometry(&self) -> String {
+        let size = self.top_left(&self.top_crop.size);
+        format!("{}x{}+{}+{}", size.width, size.height, size.x, size.y)
+    }
 
-        1. - f64::sqrt(rd * rd + gd * gd + bd * bd)
+    /// The CSS `object-fit` that goes with `css_object_position`.
+    pub fn css_object_fit(&self) -> &'static str {
+        "cover"
     }
 
-    fn importance(&mut self, crop: &CropSize, x: u32, y: u32) -> f64 {
-        if crop.x > x || x >= crop.x + crop.width || crop.y > y || y >= crop.y + crop.height {
-            return self.outside_importance;
+    /// Returns the CSS `object-position` (e.g. `25% 40%`) that shows `top_crop`
+    /// of the original image in a box of the crop's aspect with `object-fit:
+    /// cover`. Cover scales the image until it just fills the box, so that
+    /// only shows the crop when it spans the image's full width or height;
+    /// for crops smaller on both axes this returns None.
+    pub fn css_object_position(&self) -> Option<String> {
+        let size = self.top_left(&self.top_crop.size);
+        if size.width < self.image_width && size.height < self.image_height {
+            return None;
         }
-        let tx = (x - crop.x) as f64 / crop.width as f64;
-        let ty = (y - crop.y) as f64 / crop.height as f64;
-        let px = (0.5 - tx).abs() * 2.;
-        let py = (0.5 - ty).abs() * 2.;
-        // distance from edge
-        let dx = f64::max(px - 1.0 + self.edge_radius, 0.);
-        let dy = f64::max(py - 1.0 + self.edge_radius, 0.);
-        let d = (dx * dx + dy * dy) * self.edge_weight;
-        let mut s = 1.41 - (px * px + py * py).sqrt();
-        if self.rule_of_thirds {
-            s += (f64::max(0., s + d + 0.5) * 1.2) * (thirds(px) + thirds(py));
-        }
-        s + d
+        // a percentage p aligns p of the image with p of the box, so the
+        // visible part starts at p * (image length - visible length)
+        let percent = |start: u32, len: u32, image_len: u32| {
+            if len >= image_len {
+                50.
+            } else {
+                (start as f64 / (image_len - len) as f64 * 10000.).round() / 100.
+            }
+        };
+        Some(format!(
+            "{}% {}%",
+            percent(size.x, size.width, self.image_width),
+            percent(size.y, size.height, self.image_height)
+        ))
     }
+}
 
-    fn get_score(&mut self, img: &image::DynamicImage, crop: &CropSize) -> CropScore {
-        let mut detail = 0.;
-        let mut skin = 0.;
-        let mut saturation = 0.;
-        let (w, h) = img.dimensions();
-        let downsample = self.score_down_sample;
-        let inv_downsample = 1. / downsample as f64;
-        let output_height_downsample = h * downsample;
-        let output_width_downsample = w * downsample;
-
-        for y in (0..output_height_downsample).filter(|y| y % downsample == 0) {
-            for x in (0..output_width_downsample).filter(|x| x % downsample == 0) {
-                let downsample_x = (x as f64 * inv_downsample).floor() as u32;
-                let downsample_y = (y as f64 * inv_downsample).floor() as u32;
-                let importance = self.importance(crop, x, y);
-                let pixel = img.get_pixel(downsample_x, downsample_y);
-                let d = pixel[1] as f64 / 255.;
-                skin = skin + (pixel[0] as f64) / 255. * (d + self.skin_bias) * importance;
-                detail = detail + d * importance;
-                saturation =
-                    saturation + (pixel[2] as f64) / 255. * (d + self.saturation_bias) * importance;
-            }
-        }
+// Audit record of the crop decision for one image, for batch QA
+#[derive(Clone, Debug, Serialize)]
+pub struct Report {
+    pub path: String,
+    pub crop: CropSize,
+    pub detail: f64,
+    pub saturation: f64,
+    pub skin: f64,
+    pub boost: f64,
+    pub total: f64,
+    pub candidates: usize,
+    pub prepare_ms: f64,
+    pub score_ms: f64,
+    // safeguards that changed the outcome: "score_down_sample" when it was
+    // reduced for a small image, "min_saliency_coverage" when no candidate met
+    // it, "time_budget" or "candidate_budget" when the search was cut short
+    pub fallbacks: Vec<String>,
+}
 
-        let total = (detail * self.detail_weight
-            + skin * self.skin_weight
-            + saturation * self.saturation_weight)
-            / crop.width as f64
-            / crop.height as f64;
-        CropScore {
-            total: total,
-            detail: detail,
-            skin: skin,
-            saturation: saturation,
-        }
+impl Report {
+    /// Serializes `reports` as a JSON array.
+    pub fn to_json(reports: &[Report]) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(reports)
     }
 
-    fn analyse(&mut self, img: image::DynamicImage) -> CropResult {
-        let (size_x, size_y) = img.dimensions();
-        let mut output = ImageBuffer::new(size_x, size_y);
+    /// Serializes `reports` as CSV with a header row; fallbacks are joined
+    /// with `;`.
+    pub fn to_csv(reports: &[Report]) -> String {
+        let mut csv = String::from(
+            "path,x,y,width,height,detail,saturation,skin,boost,total,candidates,prepare_ms,score_ms,fallbacks\n",
+        );
+        for r in reports {
+            csv += &format!(
+                "\"{}\",{},{},{},{},{},{},{},{},{},{},{},{},{}\n",
+                r.path.replace('"', "\"\""),
+                r.crop.x,
+                r.crop.y,
+                r.crop.width,
+                r.crop.height,
+                r.detail,
+                r.saturation,
+                r.skin,
+                r.boost,
+                r.total,
+                r.candidates,
+                r.prepare_ms,
+                r.score_ms,
+                r.fallbacks.join(";")
+            );
+        }
+        csv
+    }
+}
 
-        self.detect_edge(&img, &mut output);
-        self.detect_skin(&img, &mut output);
-        self.detect_saturation(&img, &mut output);
+// Detection results for an image, produced by `SmartCrop::prepare`
+#[derive(Clone, Debug)]
+pub struct Analysis {
+    // skin (r), detail (g) and saturation (b) channels at working resolution
+    output: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    // output downsampled by score_down_sample
+    score_map: image::DynamicImage,
+    score_down_sample: u32,
+    // row of a dominant horizontal edge in output, if there is one
+    horizon: Option<u32>,
+    pub prescale: f64,
+    // dimensions of the original image
+    pub width: u32,
+    pub height: u32,
+}
 
-        let score_output = ImageRgb8(output).resize(
-            ((size_x as f64 / self.score_down_sample as f64) as f64).ceil() as u32,
-            ((size_y as f64 / self.score_down_sample as f64) as f64).ceil() as u32,
-            image::imageops::FilterType::Lanczos3,
-        );
+// The candidate search for an image, as returned by search_plan. Sizes and
+// steps are in prescaled (working) coordinates; candidates are crop_width x
+// crop_height times each scale, every step_x and step_y pixels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchPlan {
+    pub prescale: f64,
+    // largest first
+    pub scales: Vec<f64>,
+    pub step_x: u32,
+    pub step_y: u32,
+    pub crop_width: u32,
+    pub crop_height: u32,
+    // number of distinct candidate rectangles
+    pub candidates: usize,
+}
 
-        let mut top_score = i32::min_value() as f64;
-        let mut top_crop: Option<CropInfo> = None;
-        let mut crops = self.crops(img);
+// Requirements a crop has to meet all of to compete, see SmartCrop::constraints.
+// Areas are fractions of the image's, boxes are in image coordinates.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Constraints {
+    pub min_area: Option<f64>,
+    pub max_area: Option<f64>,
+    // (width / height, relative tolerance)
+    pub aspect: Option<(f64, f64)>,
+    // boxes the crop has to contain entirely
+    pub must_contain: Vec<CropSize>,
+    // boxes the crop must not overlap at all
+    pub must_exclude: Vec<CropSize>,
+}
 
-        for crop in crops.iter_mut() {
-            crop.score = self.get_score(&score_output, &crop.size);
-            if crop.score.total > top_score {
-                top_crop = Some(crop.clone());
-                top_score = crop.score.total;
+impl Constraints {
+    /// Whether `size` meets every constraint in an image `image_width` by
+    /// `image_height` pixels.
+    pub fn satisfied_by(&self, size: &CropSize, image_width: u32, image_height: u32) -> bool {
+        let area =
+            size.width as f64 * size.height as f64 / (image_width as f64 * image_height as f64);
+        if self.min_area.is_some_and(|min| area < min)
+            || self.max_area.is_some_and(|max| area > max)
+        {
+            return false;
+        }
+        if let Some((aspect, tolerance)) = self.aspect {
+            let actual = size.width as f64 / size.height as f64;
+            if (actual / aspect - 1.).abs() > tolerance {
+                return false;
             }
         }
+        self.must_contain
+            .iter()
+            .all(|required| size.intersect(required).as_ref() == Some(required))
+            && self
+                .must_exclude
+                .iter()
+                .all(|excluded| size.intersect(excluded).is_none())
+    }
+}
 
-        CropResult {
-            crops: crops,
-            top_crop: top_crop.unwrap(),
+// How far crop_delta's second configuration moved the crop from the first's
+#[derive(Clone, Debug, PartialEq)]
+pub struct CropDelta {
+    // between the two top crops, 1.0 when they are the same
+    pub iou: f64,
+    // second top crop's total minus the first's
+    pub score_delta: f64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Padding {
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+    pub left: u32,
+}
+
+impl Padding {
+    fn unscale(&self, prescale: f64) -> Padding {
+        Padding {
+            top: (self.top as f64 / prescale).floor() as u32,
+            right: (self.right as f64 / prescale).floor() as u32,
+            bottom: (self.bottom as f64 / prescale).floor() as u32,
+            left: (self.left as f64 / prescale).floor() as u32,
         }
     }
+}
 
-    fn crops(&mut self, img: image::DynamicImage) -> Vec<CropInfo> {
-        let mut crops = Vec::new();
-        let (w, h) = img.dimensions();
-        let min_dimension = if w > h { h } else { w };
-        let crop_width = if self.crop_width != 0 {
-            self.crop_width
-        } else {
-            min_dimension as i32
+// How padded_crop fills the parts of a crop that lie outside the image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PadFill {
+    // mirror the image at its edges
+    Reflect,
+    Color(Rgba<u8>),
+}
+
+// `i` mirrored at the edges of 0..n until it lies within them
+fn reflect(i: i64, n: u32) -> u32 {
+    let period = 2 * n as i64;
+    let m = i.rem_euclid(period);
+    if m < n as i64 {
+        m as u32
+    } else {
+        (period - 1 - m) as u32
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CropScore {
+    pub detail: f64,
+    pub saturation: f64,
+    pub skin: f64,
+    pub boost: f64,
+    pub total: f64,
+}
+
+// Caller-supplied value to maximize when picking the top crop, in place of
+// score.total. The crop is given in source image coordinates.
+type ObjectiveFn = dyn Fn(&CropScore, &CropSize) -> f64 + Send + Sync;
+
+#[derive(Clone)]
+pub struct Objective(Arc<ObjectiveFn>);
+
+impl Objective {
+    pub fn new<F>(f: F) -> Objective
+    where
+        F: Fn(&CropScore, &CropSize) -> f64 + Send + Sync + 'static,
+    {
+        Objective(Arc::new(f))
+    }
+}
+
+impl fmt::Debug for Objective {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Objective(..)")
+    }
+}
+
+// Part of the output covered by an overlay such as a title bar, as fractions
+// (0.0 - 1.0) of the crop's width and height
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OverlayZone {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl OverlayZone {
+    fn contains(&self, tx: f64, ty: f64) -> bool {
+        self.x <= tx && tx < self.x + self.width && self.y <= ty && ty < self.y + self.height
+    }
+}
+
+// Region whose saliency is raised by weight (0.0 - 1.0), in image coordinates
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Boost {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub weight: f64,
+}
+
+impl Boost {
+    fn scale(&self, prescale: f64) -> Boost {
+        Boost {
+            x: (self.x as f64 * prescale) as u32,
+            y: (self.y as f64 * prescale) as u32,
+            width: (self.width as f64 * prescale).ceil() as u32,
+            height: (self.height as f64 * prescale).ceil() as u32,
+            weight: self.weight,
+        }
+    }
+
+    fn contains(&self, x: u64, y: u64) -> bool {
+        (self.x as u64..self.x as u64 + self.width as u64).contains(&x)
+            && (self.y as u64..self.y as u64 + self.height as u64).contains(&y)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CropSize {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CropSize {
+    /// The rectangle as `(x, y, width, height)`, the order `crop_imm` takes.
+    pub fn to_tuple(&self) -> (u32, u32, u32, u32) {
+        (self.x, self.y, self.width, self.height)
+    }
+
+    /// Whether pixel (x, y) lies inside the rectangle.
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        self.contains_sample(x as u64, y as u64)
+    }
+
+    // contains for working coordinates, which can exceed u32 for score map
+    // samples with a large score_down_sample
+    fn contains_sample(&self, x: u64, y: u64) -> bool {
+        (self.x as u64..self.x as u64 + self.width as u64).contains(&x)
+            && (self.y as u64..self.y as u64 + self.height as u64).contains(&y)
+    }
+
+    /// The smallest rectangle containing both rectangles.
+    pub fn union(&self, other: &CropSize) -> CropSize {
+        let x0 = u32::min(self.x, other.x);
+        let y0 = u32::min(self.y, other.y);
+        let x1 = u64::max(
+            self.x as u64 + self.width as u64,
+            other.x as u64 + other.width as u64,
+        );
+        let y1 = u64::max(
+            self.y as u64 + self.height as u64,
+            other.y as u64 + other.height as u64,
+        );
+        CropSize {
+            x: x0,
+            y: y0,
+            width: (x1 - x0 as u64) as u32,
+            height: (y1 - y0 as u64) as u32,
+        }
+    }
+
+    /// The overlap of two rectangles, None when they are disjoint or only
+    /// touch along an edge.
+    pub fn intersect(&self, other: &CropSize) -> Option<CropSize> {
+        let x0 = u64::max(self.x as u64, other.x as u64);
+        let y0 = u64::max(self.y as u64, other.y as u64);
+        let x1 = u64::min(
+            self.x as u64 + self.width as u64,
+            other.x as u64 + other.width as u64,
+        );
+        let y1 = u64::min(
+            self.y as u64 + self.height as u64,
+            other.y as u64 + other.height as u64,
+        );
+        if x1 <= x0 || y1 <= y0 {
+            return None;
+        }
+        Some(CropSize {
+            x: x0 as u32,
+            y: y0 as u32,
+            width: (x1 - x0) as u32,
+            height: (y1 - y0) as u32,
+        })
+    }
+
+    /// Intersection over union of two rectangles, 0 when they don't overlap.
+    pub fn iou(&self, other: &CropSize) -> f64 {
+        let intersection = match self.intersect(other) {
+            Some(overlap) => overlap.width as f64 * overlap.height as f64,
+            None => return 0.,
         };
-        let crop_height = if self.crop_height != 0 {
-            self.crop_height
+        let union = self.width as f64 * self.height as f64
+            + other.width as f64 * other.height as f64
+            - intersection;
+        intersection / union
+    }
+
+    /// The same rectangle with `y` measured from the other edge of an image
+    /// `image_height` high, converting between top-left and bottom-left
+    /// origin coordinates in either direction.
+    pub fn flip_y(&self, image_height: u32) -> CropSize {
+        CropSize {
+            y: image_height.saturating_sub(self.y + self.height),
+            ..self.clone()
+        }
+    }
+
+    // The largest rectangle at the aspect of width x height centered in self,
+    // or self when its aspect is within `tolerance` (relative) of that one.
+    fn fit_aspect(&self, width: u32, height: u32, tolerance: f64) -> CropSize {
+        if self.width == 0 || self.height == 0 || width == 0 || height == 0 {
+            return self.clone();
+        }
+        let aspect = self.width as f64 / self.height as f64;
+        let target = width as f64 / height as f64;
+        if (aspect / target - 1.).abs() <= tolerance {
+            return self.clone();
+        }
+        let (fit_width, fit_height) = if aspect > target {
+            ((self.height as f64 * target).round() as u32, self.height)
         } else {
-            min_dimension as i32
-        };
-        let range_min = (self.min_scale * 100.) as u32;
-        let range_max = ((self.max_scale + self.scale_step) * 100.) as u32;
-        let range_step = (self.scale_step * 100.) as u32;
-        let mut scales: Vec<f64> = (range_min..range_max)
-            .filter(|v| v % range_step == 0)
-            .map(|v| v as f64 / 100.)
+            (self.width, (self.width as f64 / target).round() as u32)
+        };
+        let (fit_width, fit_height) = (
+            fit_width.clamp(1, self.width),
+            fit_height.clamp(1, self.height),
+        );
+        CropSize {
+            x: self.x + (self.width - fit_width) / 2,
+            y: self.y + (self.height - fit_height) / 2,
+            width: fit_width,
+            height: fit_height,
+        }
+    }
+
+    // maps an image rectangle into the prescaled working image
+    fn scale(&self, prescale: f64) -> CropSize {
+        CropSize {
+            x: (self.x as f64 * prescale).floor() as u32,
+            y: (self.y as f64 * prescale).floor() as u32,
+            width: u32::max((self.width as f64 * prescale).floor() as u32, 1),
+            height: u32::max((self.height as f64 * prescale).floor() as u32, 1),
+        }
+    }
+
+    fn unscale(&self, prescale: f64) -> CropSize {
+        CropSize {
+            x: (self.x as f64 / prescale).floor() as u32,
+            y: (self.y as f64 / prescale).floor() as u32,
+            width: (self.width as f64 / prescale).floor() as u32,
+            height: (self.height as f64 / prescale).floor() as u32,
+        }
+    }
+
+    // shrinks the crop so that it lies within a width x height image
+    fn clamp(&self, width: u32, height: u32) -> CropSize {
+        let x = u32::min(self.x, width.saturating_sub(1));
+        let y = u32::min(self.y, height.saturating_sub(1));
+        CropSize {
+            x,
+            y,
+            width: u32::min(self.width, width - x),
+            height: u32::min(self.height, height - y),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CropInfo {
+    pub size: CropSize,
+    pub score: CropScore,
+}
+
+impl fmt::Display for CropInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "crop {},{} {}x{} score={:.4} (detail={:.2}, skin={:.2}, sat={:.2})",
+            self.size.x,
+            self.size.y,
+            self.size.width,
+            self.size.height,
+            self.score.total,
+            self.score.detail,
+            self.score.skin,
+            self.score.saturation
+        )
+    }
+}
+
+// Fill crops to the target aspect; Fit keeps the whole salient region and
+// letterboxes it if it doesn't fit the target aspect. The letterbox keeps the
+// salient region centered, so a subject near an edge gets most of the padding
+// on that side rather than half on each.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum CropMode {
+    #[default]
+    Fill,
+    Fit,
+}
+
+// red / green ratio and saturation of skin, for skin_strictness
+const SKIN_RED_GREEN_RATIO: (f64, f64) = (1.05, 1.5);
+const SKIN_SATURATION: (f64, f64) = (0.1, 0.6);
+
+// thresholds merged with skin_softness
+const SKIN_PASSES: usize = 5;
+
+// candidates scored between checks of time_budget
+const TIME_BUDGET_CHECK_INTERVAL: usize = 16;
+
+// intersection over union below which a candidate counts as an alternative to
+// the top crop rather than a shifted copy of it, for confidence_margin
+const AMBIGUITY_MAX_IOU: f64 = 0.5;
+
+// neighborhood of warm_start searched first, as a fraction of its width and
+// height that the position and size of a candidate may differ by
+const WARM_START_RADIUS: f64 = 0.1;
+
+// fraction of the peak saliency a score map pixel needs to count as subject in Fit mode
+const FIT_SALIENCY_THRESHOLD: f64 = 0.1;
+
+// How many times the average row's edge energy the strongest row needs to be
+// treated as a horizon
+const HORIZON_DOMINANCE: f64 = 4.0;
+
+// Smallest score map side that detect will downsample to; images too small
+// for the configured score_down_sample are scored with a smaller one.
+const MIN_SCORE_MAP_SIZE: u32 = 4;
+
+// version of the CropConfig format written by this crate
+pub const CROP_CONFIG_VERSION: u32 = 1;
+
+// Every tunable of the detection and scoring as plain data, for loading from
+// a config file and swapping in at runtime. Missing fields keep their
+// defaults; unknown ones are rejected so that typos don't go unnoticed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CropConfig {
+    pub version: u32,
+    pub detail_weight: f64,
+    pub skin_color: (f64, f64, f64),
+    pub skin_bias: f64,
+    pub skin_brightness_min: f64,
+    pub skin_brightness_max: f64,
+    pub skin_threshold: f64,
+    pub skin_softness: f64,
+    pub skin_weight: f64,
+    pub skin_min_region: u32,
+    pub skin_subsample: u32,
+    pub skin_strictness: f64,
+    pub skin_cap: Option<f64>,
+    pub saturation_brightness_min: f64,
+    pub saturation_brightness_max: f64,
+    pub saturation_threshold: f64,
+    pub saturation_bias: f64,
+    pub saturation_weight: f64,
+    pub saturation_cap: Option<f64>,
+    pub low_key: bool,
+    pub local_contrast_radius: u32,
+    pub normalize_weights: bool,
+    pub score_down_sample: u32,
+    pub auto_score_down_sample: bool,
+    pub full_resolution_scoring: bool,
+    pub step: u32,
+    pub step_x: u32,
+    pub step_y: u32,
+    pub max_candidates: usize,
+    pub dimension_multiple: Option<u32>,
+    pub origin_multiple: Option<u32>,
+    pub scale_step: f64,
+    pub aspect_tolerance: f64,
+    pub max_aspect_distortion: f64,
+    pub min_scale: f64,
+    pub max_scale: f64,
+    pub edge_radius: f64,
+    pub edge_weight: f64,
+    pub mask_transparent: bool,
+    pub alpha_shape: bool,
+    pub detection_octave: u32,
+    pub pre_blur_sigma: f64,
+    pub bilateral_spatial_sigma: f64,
+    pub bilateral_range_sigma: f64,
+    pub edge_operator: EdgeOperator,
+    pub linear_edges: bool,
+    pub linear_brightness_gates: bool,
+    pub boost_weight: f64,
+    pub face_size_weight: f64,
+    pub off_face_skin_factor: f64,
+    pub face_skin_factor: f64,
+    pub depth_weight: f64,
+    pub outside_importance: f64,
+    pub min_saliency_coverage: f64,
+    pub ambiguity_threshold: f64,
+    pub rule_of_thirds: bool,
+    pub horizon_bias: f64,
+    pub seam_penalty: f64,
+    pub symmetry_weight: f64,
+    pub center_bias: f64,
+    pub negative_space_weight: f64,
+    pub mode: CropMode,
+    pub output_shape: OutputShape,
+    pub coordinate_origin: CoordinateOrigin,
+    pub prescale: bool,
+    pub allow_upscale: bool,
+    pub stable_resize: bool,
+    pub box_score_map: bool,
+    pub lenient_decode: bool,
+    pub band_mapping: Option<[usize; 3]>,
+    pub f32_scoring: bool,
+    pub compensated_sum: bool,
+}
+
+impl Default for CropConfig {
+    fn default() -> CropConfig {
+        SmartCrop::default().to_config()
+    }
+}
+
+// Weight presets tuned for common kinds of content
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Preset {
+    // favors faces: strong skin weight that also counts smooth skin
+    Portrait,
+    // detail and color driven, skin detection disabled
+    Landscape,
+    // favors saturated objects over textured backdrops
+    Product,
+    // fine edges only, allowing detail close to the crop border
+    Text,
+}
+
+// Clockwise rotation the user applied to the image; crops are analysed and
+// returned in the rotated frame
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Rotation {
+    #[default]
+    None,
+    Cw90,
+    Cw180,
+    Cw270,
+}
+
+impl Rotation {
+    fn apply<I>(self, img: &I) -> RgbaImage
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        match self {
+            Rotation::None => {
+                ImageBuffer::from_fn(img.width(), img.height(), |x, y| img.get_pixel(x, y))
+            }
+            Rotation::Cw90 => image::imageops::rotate90(img),
+            Rotation::Cw180 => image::imageops::rotate180(img),
+            Rotation::Cw270 => image::imageops::rotate270(img),
+        }
+    }
+}
+
+// Encoding of the files written by crop_to_file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    // quality 1 - 100
+    Jpeg { quality: u8 },
+    Png,
+}
+
+impl Default for OutputFormat {
+    fn default() -> OutputFormat {
+        OutputFormat::Jpeg { quality: 90 }
+    }
+}
+
+// Operator the detail channel measures edges with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum EdgeOperator {
+    // 4-neighbor Laplacian: cheap, but blind to gradual and diagonal edges
+    #[default]
+    Laplacian,
+    // gradient magnitude of the horizontal and vertical Sobel kernels, scaled
+    // so that a full black to white step reads 255
+    Sobel,
+}
+
+// Where the y of reported crops is measured from. Only results are affected;
+// rectangles passed in (boosts, faces, must_contain, candidates, ...) are
+// always measured from the top.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum CoordinateOrigin {
+    // y grows downward from the top edge, as in image buffers
+    #[default]
+    TopLeft,
+    // y is the distance from the bottom edge of the image to the bottom edge
+    // of the crop, as in OpenGL
+    BottomLeft,
+}
+
+// Shape of the final output; round shapes clip the corners of the crop
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum OutputShape {
+    #[default]
+    Rect,
+    // circle inscribed in the shorter side of the crop
+    Circle,
+    // ellipse inscribed in the crop rectangle
+    Ellipse,
+}
+
+// Decodes the image at `path` for analysis and converts it to 8-bit RGB(A),
+// which is what the detectors expect. Any format image decodes (BMP, TGA,
+// ICO, ...) works as a single picture; animated GIFs are rejected rather than
+// judged by their first frame alone. With `bands`, the chosen bands become R,
+// G and B instead. With the icc feature, JPEGs with an embedded RGB profile
+// are converted to sRGB; untagged images are taken to be sRGB already.
+fn load_image(
+    path: &Path,
+    lenient: bool,
+    bands: Option<[usize; 3]>,
+) -> Result<image::DynamicImage, SmartCropError> {
+    if let Some(mapping) = bands {
+        return load_bands(path, mapping);
+    }
+    let format = image::io::Reader::open(path)?
+        .with_guessed_format()?
+        .format();
+    if format == Some(image::ImageFormat::Gif) {
+        use image::AnimationDecoder;
+        let decoder = image::codecs::gif::GifDecoder::new(BufReader::new(File::open(path)?))?;
+        if decoder.into_frames().take(2).count() > 1 {
+            return Err(SmartCropError::UnsupportedForAnalysis(
+                "animated GIF".to_string(),
+            ));
+        }
+    }
+    let img = match image::open(path) {
+        Ok(img) => img,
+        Err(err) if lenient => decode_truncated_jpeg(path)?.ok_or(err)?,
+        Err(err) => return Err(err.into()),
+    };
+    let img = match img.color() {
+        ColorType::Rgb8 | ColorType::Rgba8 => img,
+        ColorType::L8 | ColorType::L16 | ColorType::Rgb16 | ColorType::Bgr8 => {
+            ImageRgb8(img.to_rgb8())
+        }
+        ColorType::La8 | ColorType::La16 | ColorType::Rgba16 | ColorType::Bgra8 => {
+            ImageRgba8(img.to_rgba8())
+        }
+        color => return Err(SmartCropError::UnsupportedColorType(color)),
+    };
+    #[cfg(feature = "icc")]
+    {
+        if format == Some(image::ImageFormat::Jpeg) {
+            let mut bytes = Vec::new();
+            File::open(path)?.read_to_end(&mut bytes)?;
+            if let Some(profile) = jpeg_icc_profile(&bytes).and_then(|p| IccProfile::parse(&p)) {
+                return Ok(profile.to_srgb(img));
+            }
+        }
+    }
+    Ok(img)
+}
+
+// The ICC profile embedded in a JPEG's APP2 segments, reassembled in order
+#[cfg(feature = "icc")]
+fn jpeg_icc_profile(bytes: &[u8]) -> Option<Vec<u8>> {
+    const SIGNATURE: &[u8] = b"ICC_PROFILE\0";
+    if bytes.get(..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut chunks = Vec::new();
+    let mut at = 2;
+    while at + 4 <= bytes.len() && bytes[at] == 0xFF {
+        let marker = bytes[at + 1];
+        // start of scan: no more metadata
+        if marker == 0xDA {
+            break;
+        }
+        let len = u16::from_be_bytes([bytes[at + 2], bytes[at + 3]]) as usize;
+        let segment = bytes.get(at + 4..at + 2 + len)?;
+        if marker == 0xE2 && segment.starts_with(SIGNATURE) && segment.len() > SIGNATURE.len() + 2 {
+            let sequence = segment[SIGNATURE.len()];
+            chunks.push((sequence, &segment[SIGNATURE.len() + 2..]));
+        }
+        at += 2 + len;
+    }
+    if chunks.is_empty() {
+        return None;
+    }
+    chunks.sort_by_key(|&(sequence, _)| sequence);
+    Some(
+        chunks
+            .into_iter()
+            .flat_map(|(_, data)| data.iter().copied())
+            .collect(),
+    )
+}
+
+// Tone curve of an ICC matrix/TRC profile channel, from encoded (0.0 - 1.0)
+// to linear values
+#[cfg(feature = "icc")]
+enum ToneCurve {
+    Gamma(f64),
+    Table(Vec<f64>),
+    // parametricCurveType parameters g, a, b, c, d, e, f
+    Parametric(u16, [f64; 7]),
+}
+
+#[cfg(feature = "icc")]
+impl ToneCurve {
+    fn linear(&self, x: f64) -> f64 {
+        match self {
+            ToneCurve::Gamma(gamma) => x.powf(*gamma),
+            ToneCurve::Table(table) => {
+                let at = x * (table.len() - 1) as f64;
+                let (i, t) = (at.floor() as usize, at.fract());
+                let next = table[(i + 1).min(table.len() - 1)];
+                table[i] * (1. - t) + next * t
+            }
+            ToneCurve::Parametric(kind, [g, a, b, c, d, e, f]) => match kind {
+                0 => x.powf(*g),
+                1 if x >= -b / a => (a * x + b).powf(*g),
+                1 => 0.,
+                2 if x >= -b / a => (a * x + b).powf(*g) + c,
+                2 => *c,
+                3 if x >= *d => (a * x + b).powf(*g),
+                3 => c * x,
+                _ if x >= *d => (a * x + b).powf(*g) + e,
+                _ => c * x + f,
+            },
+        }
+    }
+}
+
+// The matrix/TRC part of an RGB ICC profile (Adobe RGB, ProPhoto, Display P3,
+// ...): a tone curve per channel and the D50 XYZ of the primaries
+#[cfg(feature = "icc")]
+struct IccProfile {
+    curves: [ToneCurve; 3],
+    // columns are the XYZ of red, green and blue
+    to_xyz: [[f64; 3]; 3],
+}
+
+#[cfg(feature = "icc")]
+impl IccProfile {
+    fn parse(profile: &[u8]) -> Option<IccProfile> {
+        let u32_at = |at: usize| {
+            profile
+                .get(at..at + 4)
+                .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+        };
+        let s15_at = |at: usize| u32_at(at).map(|v| v as u32 as i32 as f64 / 65536.);
+        // data colour space
+        if profile.get(16..20)? != b"RGB " {
+            return None;
+        }
+        // a tag table running past the end of the profile means it's malformed
+        let tag_count = u32_at(128)?;
+        if tag_count.checked_mul(12)?.checked_add(132)? > profile.len() {
+            return None;
+        }
+        let tag = |signature: &[u8]| {
+            (0..tag_count)
+                .map(|i| 132 + i * 12)
+                .find(|&entry| profile.get(entry..entry + 4) == Some(signature))
+                .and_then(|entry| Some((u32_at(entry + 4)?, u32_at(entry + 8)?)))
+        };
+        let xyz = |signature: &[u8]| -> Option<[f64; 3]> {
+            let (at, _) = tag(signature)?;
+            Some([s15_at(at + 8)?, s15_at(at + 12)?, s15_at(at + 16)?])
+        };
+        let curve = |signature: &[u8]| -> Option<ToneCurve> {
+            let (at, _) = tag(signature)?;
+            let u16_at = |at: usize| {
+                profile
+                    .get(at..at + 2)
+                    .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            };
+            match profile.get(at..at + 4)? {
+                b"curv" => match u32_at(at + 8)? {
+                    0 => Some(ToneCurve::Gamma(1.)),
+                    1 => Some(ToneCurve::Gamma(u16_at(at + 12)? as f64 / 256.)),
+                    count => (0..count)
+                        .map(|i| u16_at(at + 12 + i * 2).map(|v| v as f64 / 65535.))
+                        .collect::<Option<Vec<_>>>()
+                        .map(ToneCurve::Table),
+                },
+                b"para" => {
+                    let kind = u16_at(at + 8)?;
+                    let count = [1, 3, 4, 5, 7].get(kind as usize)?;
+                    let mut params = [0.; 7];
+                    for (i, param) in params.iter_mut().take(*count).enumerate() {
+                        *param = s15_at(at + 12 + i * 4)?;
+                    }
+                    Some(ToneCurve::Parametric(kind, params))
+                }
+                _ => None,
+            }
+        };
+        let (red, green, blue) = (xyz(b"rXYZ")?, xyz(b"gXYZ")?, xyz(b"bXYZ")?);
+        Some(IccProfile {
+            curves: [curve(b"rTRC")?, curve(b"gTRC")?, curve(b"bTRC")?],
+            to_xyz: [
+                [red[0], green[0], blue[0]],
+                [red[1], green[1], blue[1]],
+                [red[2], green[2], blue[2]],
+            ],
+        })
+    }
+
+    // `img` (8-bit RGB or RGBA in this profile) in sRGB, alpha unchanged
+    fn to_srgb(&self, img: image::DynamicImage) -> image::DynamicImage {
+        // D50 XYZ to linear sRGB, Bradford adapted to D65
+        const XYZ_TO_SRGB: [[f64; 3]; 3] = [
+            [3.1338561, -1.6168667, -0.4906146],
+            [-0.9787684, 1.9161415, 0.033454],
+            [0.0719453, -0.2289914, 1.4052427],
+        ];
+        let tables: Vec<Vec<f64>> = self
+            .curves
+            .iter()
+            .map(|curve| (0..256).map(|v| curve.linear(v as f64 / 255.)).collect())
             .collect();
-        scales.reverse();
+        let convert = |pixel: &mut [u8]| {
+            let linear = [
+                tables[0][pixel[0] as usize],
+                tables[1][pixel[1] as usize],
+                tables[2][pixel[2] as usize],
+            ];
+            let xyz = self
+                .to_xyz
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .zip(linear.iter())
+                        .map(|(m, v)| m * v)
+                        .sum::<f64>()
+                })
+                .collect::<Vec<_>>();
+            for (channel, row) in pixel.iter_mut().zip(XYZ_TO_SRGB.iter()) {
+                let v = row
+                    .iter()
+                    .zip(xyz.iter())
+                    .map(|(m, v)| m * v)
+                    .sum::<f64>()
+                    .clamp(0., 1.);
+                let encoded = if v <= 0.0031308 {
+                    v * 12.92
+                } else {
+                    1.055 * v.powf(1. / 2.4) - 0.055
+                };
+                *channel = (encoded * 255.).round() as u8;
+            }
+        };
+        match img {
+            ImageRgba8(mut rgba) => {
+                rgba.pixels_mut().for_each(|p| convert(&mut p.0[..3]));
+                ImageRgba8(rgba)
+            }
+            img => {
+                let mut rgb = img.to_rgb8();
+                rgb.pixels_mut().for_each(|p| convert(&mut p.0));
+                ImageRgb8(rgb)
+            }
+        }
+    }
+}
 
-        for scale in scales.iter() {
-            for y in (0..h).map(|y| y).filter(|y| y % self.step == 0) {
-                if !((y as f64 + crop_height as f64 * scale) as u32 <= h) {
-                    break;
+// Decodes the image at `path` with the bands in `mapping` as its R, G and B
+// channels. TIFFs are read with all their bands, up to four (e.g. CMYK or RGB
+// plus near infrared); other formats offer their RGBA channels.
+fn load_bands(path: &Path, mapping: [usize; 3]) -> Result<image::DynamicImage, SmartCropError> {
+    let (width, height, bands, samples) =
+        if image::ImageFormat::from_path(path).ok() == Some(image::ImageFormat::Tiff) {
+            let mut decoder = tiff::decoder::Decoder::new(BufReader::new(File::open(path)?))?;
+            let (width, height) = decoder.dimensions()?;
+            let bands = match decoder.colortype()? {
+                tiff::ColorType::Gray(_) => 1,
+                tiff::ColorType::GrayA(_) => 2,
+                tiff::ColorType::RGB(_) => 3,
+                tiff::ColorType::RGBA(_) | tiff::ColorType::CMYK(_) => 4,
+                color => {
+                    return Err(SmartCropError::UnsupportedForAnalysis(format!(
+                        "{:?} TIFF",
+                        color
+                    )))
                 }
-                for x in (0..w).map(|x| x).filter(|x| x % self.step == 0) {
-                    if !((x as f64 + crop_width as f64 * scale) as u32 <= w) {
-                        break;
-                    }
-                    crops.push(CropInfo {
-                        size: CropSize {
-                            x: x as u32,
-                            y: y as u32,
-                            width: (crop_width as f64 * scale) as u32,
-                            height: (crop_height as f64 * scale) as u32,
-                        },
-                        score: CropScore {
-                            ..CropScore::default()
-                        },
-                    });
+            };
+            let samples = match decoder.read_image()? {
+                tiff::decoder::DecodingResult::U8(samples) => samples,
+                tiff::decoder::DecodingResult::U16(samples) => {
+                    samples.into_iter().map(|v| (v >> 8) as u8).collect()
+                }
+                _ => {
+                    return Err(SmartCropError::UnsupportedForAnalysis(
+                        "TIFF with more than 16 bits per sample".to_string(),
+                    ))
                 }
+            };
+            (width, height, bands, samples)
+        } else {
+            let img = image::open(path)?.to_rgba8();
+            (img.width(), img.height(), 4, img.into_raw())
+        };
+    if mapping.iter().any(|&band| band >= bands) {
+        return Err(SmartCropError::InvalidBandMapping(mapping));
+    }
+    let rgb = samples
+        .chunks_exact(bands)
+        .flat_map(|pixel| mapping.iter().map(move |&band| pixel[band]))
+        .collect();
+    let img = ImageBuffer::from_raw(width, height, rgb)
+        .ok_or_else(|| SmartCropError::UnsupportedForAnalysis("truncated samples".to_string()))?;
+    Ok(ImageRgb8(img))
+}
+
+// Decodes what is there of a truncated JPEG. Returns None when `path` isn't a
+// JPEG or is damaged beyond the end of its data.
+fn decode_truncated_jpeg(path: &Path) -> Result<Option<image::DynamicImage>, SmartCropError> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    if image::guess_format(&data).ok() != Some(image::ImageFormat::Jpeg) {
+        return Ok(None);
+    }
+    // an end of image marker makes the decoder stop at the missing data
+    data.extend_from_slice(&[0xff, 0xd9]);
+    let img = match image::load_from_memory_with_format(&data, image::ImageFormat::Jpeg) {
+        Ok(img) => img.to_rgb8(),
+        Err(_) => return Ok(None),
+    };
+
+    // blocks past the end repeat the last decoded block, so the image stops
+    // where rows start matching the ones 8 above them
+    let row = img.width() as usize * 3;
+    let raw = img.as_raw();
+    let same = |y: usize| raw[y * row..(y + 1) * row] == raw[(y - 8) * row..(y - 7) * row];
+    let last = img.height() as usize - 1;
+    let mut y = last;
+    while y >= 8 && same(y) {
+        y -= 1;
+    }
+    let height = if y == last {
+        last + 1
+    } else {
+        y.saturating_sub(7).max(1)
+    };
+    let decoded = image::imageops::crop_imm(&img, 0, 0, img.width(), height as u32).to_image();
+    Ok(Some(ImageRgb8(decoded)))
+}
+
+// A 4:2:0 YUV video frame (BT.601, video range) seen as RGBA. Pixels are
+// converted as the detectors read them, so no RGB copy of the frame is made.
+struct Yuv420<'a> {
+    y: &'a [u8],
+    u: &'a [u8],
+    v: &'a [u8],
+    y_stride: usize,
+    uv_stride: usize,
+    // distance between chroma samples in a row: 1 for planar I420, 2 for
+    // NV12's interleaved UV plane
+    uv_step: usize,
+    width: u32,
+    height: u32,
+}
+
+impl<'a> GenericImageView for Yuv420<'a> {
+    type Pixel = Rgba<u8>;
+    type InnerImageView = Self;
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn bounds(&self) -> (u32, u32, u32, u32) {
+        (0, 0, self.width, self.height)
+    }
+
+    fn get_pixel(&self, x: u32, y: u32) -> Rgba<u8> {
+        let luma = self.y[y as usize * self.y_stride + x as usize] as f64;
+        let chroma = (y / 2) as usize * self.uv_stride + (x / 2) as usize * self.uv_step;
+        let (u, v) = (self.u[chroma] as f64 - 128., self.v[chroma] as f64 - 128.);
+        let l = 1.164 * (luma - 16.);
+        let channel = |c: f64| c.round().clamp(0., 255.) as u8;
+        Rgba([
+            channel(l + 1.596 * v),
+            channel(l - 0.392 * u - 0.813 * v),
+            channel(l + 2.017 * u),
+            255,
+        ])
+    }
+
+    fn inner(&self) -> &Self {
+        self
+    }
+}
+
+impl<'a> Yuv420<'a> {
+    // the frame, if every plane holds the samples its size and strides call for
+    fn validated(self) -> Result<Self, SmartCropError> {
+        if self.width == 0 || self.height == 0 {
+            return Err(SmartCropError::InvalidFrame("empty frame".to_string()));
+        }
+        let chroma_width = (self.width as usize).div_ceil(2);
+        let chroma_height = (self.height as usize).div_ceil(2);
+        let chroma_row = (chroma_width - 1) * self.uv_step + 1;
+        let planes = [
+            (
+                "Y",
+                self.y,
+                self.y_stride,
+                self.width as usize,
+                self.height as usize,
+            ),
+            ("U", self.u, self.uv_stride, chroma_row, chroma_height),
+            ("V", self.v, self.uv_stride, chroma_row, chroma_height),
+        ];
+        for &(name, plane, stride, row, rows) in planes.iter() {
+            if stride < row {
+                return Err(SmartCropError::InvalidFrame(format!(
+                    "{} stride {} is shorter than a row of {} bytes",
+                    name, stride, row
+                )));
+            }
+            let needed = (rows - 1)
+                .checked_mul(stride)
+                .and_then(|start| start.checked_add(row));
+            if needed.is_none_or(|needed| plane.len() < needed) {
+                return Err(SmartCropError::InvalidFrame(format!(
+                    "{} plane of {} bytes is too short for {} rows of stride {}",
+                    name,
+                    plane.len(),
+                    rows,
+                    stride
+                )));
             }
         }
-        crops
+        Ok(self)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use image;
-    use std::fs::File;
-    use std::path::Path;
+// Decoded 8-bit rows of an image file, read top to bottom by prepare_tiled.
+struct RowReader {
+    reader: Box<dyn Read>,
+    width: u32,
+    height: u32,
+    channels: usize,
+}
 
-    #[test]
-    fn it_works() {
-        let mut sc = SmartCrop::new();
-        let path = Path::new("test.jpg");
-        let mut opts = SmartCrop::default();
-        opts.width = 100;
-        opts.height = 100 / (100 / 100);
-        let result = sc.crop(path, &opts);
-        let mut img = image::open(path).unwrap();
-        let size = result.top_crop.size;
+// Opens `path` for row by row decoding. Returns None for formats and color
+// types that can't be streamed, which have to be decoded in full instead.
+fn open_rows(path: &Path) -> Result<Option<RowReader>, SmartCropError> {
+    use image::ImageDecoder;
 
-        let output_img = img.crop(size.x, size.y, size.width, size.height);
-        let ref mut fout = File::create(&Path::new("out.jpg")).unwrap();
-        let _ = output_img.save(fout, image::ImageFormat::Jpeg);
+    let file = BufReader::new(File::open(path)?);
+    match image::ImageFormat::from_path(path)? {
+        image::ImageFormat::Tiff => {
+            let mut decoder = tiff::decoder::Decoder::new(file)?;
+            let (width, height) = decoder.dimensions()?;
+            let channels = match decoder.colortype()? {
+                tiff::ColorType::Gray(8) => 1,
+                tiff::ColorType::GrayA(8) => 2,
+                tiff::ColorType::RGB(8) => 3,
+                tiff::ColorType::RGBA(8) => 4,
+                _ => return Ok(None),
+            };
+            let strips = TiffStrips {
+                decoder,
+                strip: Vec::new(),
+                pos: 0,
+            };
+            Ok(Some(RowReader {
+                reader: Box::new(strips),
+                width,
+                height,
+                channels,
+            }))
+        }
+        image::ImageFormat::Png => {
+            let decoder = image::codecs::png::PngDecoder::new(file)?;
+            let (width, height) = decoder.dimensions();
+            let channels = match decoder.color_type() {
+                ColorType::L8 => 1,
+                ColorType::La8 => 2,
+                ColorType::Rgb8 => 3,
+                ColorType::Rgba8 => 4,
+                _ => return Ok(None),
+            };
+            Ok(Some(RowReader {
+                reader: Box::new(decoder.into_reader()?),
+                width,
+                height,
+                channels,
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+// Reads the samples of an 8-bit TIFF one strip at a time.
+struct TiffStrips<R: Read + io::Seek> {
+    decoder: tiff::decoder::Decoder<R>,
+    strip: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read + io::Seek> Read for TiffStrips<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.strip.len() {
+            self.strip = match self.decoder.read_strip() {
+                Ok(tiff::decoder::DecodingResult::U8(strip)) => strip,
+                Ok(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "not 8-bit")),
+                Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+            };
+            self.pos = 0;
+        }
+        let n = usize::min(buf.len(), self.strip.len() - self.pos);
+        buf[..n].copy_from_slice(&self.strip[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+// Area-averaging downscaler that is fed one source row at a time, so that an
+// image can be resized without holding it in memory. Produces the same pixels
+// as box_resize; only the output rows overlapping the current source row are
+// kept as accumulators.
+struct RowResizer {
+    src_width: u32,
+    src_height: u32,
+    scale_x: f64,
+    scale_y: f64,
+    // output rows still receiving source rows: (row, channel sums, area)
+    pending: VecDeque<(u32, Vec<[f64; 4]>, Vec<f64>)>,
+    next_row: u32,
+    output: RgbaImage,
+}
+
+impl RowResizer {
+    fn new(src_width: u32, src_height: u32, width: u32, height: u32) -> RowResizer {
+        RowResizer {
+            src_width,
+            src_height,
+            scale_x: src_width as f64 / width as f64,
+            scale_y: src_height as f64 / height as f64,
+            pending: VecDeque::new(),
+            next_row: 0,
+            output: ImageBuffer::new(width, height),
+        }
+    }
+
+    // source rows covered by output row `out_y`, same as in box_resize
+    fn source_rows(&self, out_y: u32) -> (u32, u32) {
+        let (y0, y1) = (
+            out_y as f64 * self.scale_y,
+            (out_y + 1) as f64 * self.scale_y,
+        );
+        (
+            y0.floor() as u32,
+            u32::min(y1.ceil() as u32, self.src_height),
+        )
+    }
+
+    fn push_row(&mut self, y: u32, row: &[Rgba<u8>]) {
+        let (width, height) = self.output.dimensions();
+        while self.next_row < height && self.source_rows(self.next_row).0 <= y {
+            let sums = vec![[0.; 4]; width as usize];
+            self.pending
+                .push_back((self.next_row, sums, vec![0.; width as usize]));
+            self.next_row += 1;
+        }
+
+        let (scale_x, scale_y, src_width) = (self.scale_x, self.scale_y, self.src_width);
+        for (out_y, sums, areas) in self.pending.iter_mut() {
+            let (y0, y1) = (*out_y as f64 * scale_y, (*out_y + 1) as f64 * scale_y);
+            let wy = f64::min(y1, (y + 1) as f64) - f64::max(y0, y as f64);
+            for out_x in 0..width {
+                let (x0, x1) = (out_x as f64 * scale_x, (out_x + 1) as f64 * scale_x);
+                let (sum, area) = (&mut sums[out_x as usize], &mut areas[out_x as usize]);
+                for x in x0.floor() as u32..u32::min(x1.ceil() as u32, src_width) {
+                    let wx = f64::min(x1, (x + 1) as f64) - f64::max(x0, x as f64);
+                    let pixel = row[x as usize];
+                    for (c, v) in sum.iter_mut().enumerate() {
+                        *v += pixel[c] as f64 * wx * wy;
+                    }
+                    *area += wx * wy;
+                }
+            }
+        }
+
+        while let Some(&(out_y, _, _)) = self.pending.front() {
+            if self.source_rows(out_y).1 > y + 1 {
+                break;
+            }
+            let (out_y, sums, areas) = self.pending.pop_front().unwrap();
+            for (out_x, (sum, area)) in sums.iter().zip(areas.iter()).enumerate() {
+                self.output.put_pixel(
+                    out_x as u32,
+                    out_y,
+                    Rgba([
+                        (sum[0] / area).round() as u8,
+                        (sum[1] / area).round() as u8,
+                        (sum[2] / area).round() as u8,
+                        (sum[3] / area).round() as u8,
+                    ]),
+                );
+            }
+        }
+    }
+}
+
+// Crop scales searched for a scale range, largest first: max_scale, then
+// max_scale - scale_step, max_scale - 2 * scale_step and so on while they are
+// at least min_scale. max_scale is always included; min_scale only when the
+// range is a multiple of scale_step (up to rounding), so an uneven step stops
+// above it. A step <= 0 or larger than the range yields max_scale alone.
+fn scales(min_scale: f64, max_scale: f64, scale_step: f64) -> Vec<f64> {
+    let mut scales = vec![max_scale];
+    if scale_step > 0. {
+        // the epsilon keeps min_scale itself despite rounding
+        let mut i = 1.;
+        while max_scale - i * scale_step >= min_scale - 1e-9 {
+            scales.push(max_scale - i * scale_step);
+            i += 1.;
+        }
+    }
+    scales
+}
+
+// Start of a crop of length `len` that puts `pos` at `rel` (0.0 - 1.0) of its
+// length, kept within 0..`max`.
+fn anchored(pos: f64, rel: f64, len: u32, max: u32) -> u32 {
+    let start = (pos - rel * len as f64).round().max(0.) as u32;
+    start.min(max.saturating_sub(len))
+}
+
+// whether `anchored` can place `pos` without being held back by 0..`max`
+fn anchor_fits(pos: f64, rel: f64, len: u32, max: u32) -> bool {
+    let start = (pos - rel * len as f64).round();
+    start >= 0. && start + len as f64 <= max as f64
+}
+
+// 1 for a crop centered on `img`, decaying smoothly as its center moves away,
+// with the distance measured in fractions of the image's width and height
+fn centeredness<I: GenericImageView>(img: &I, crop: &CropSize) -> f64 {
+    let (width, height) = img.dimensions();
+    let dx = (crop.x as f64 + crop.width as f64 / 2.) / width as f64 - 0.5;
+    let dy = (crop.y as f64 + crop.height as f64 / 2.) / height as f64 - 0.5;
+    (-(dx * dx + dy * dy) / (2. * 0.25 * 0.25)).exp()
+}
+
+// one channel of the skin (0), detail (1) and saturation (2) output of detect
+fn channel(output: &RgbImage, c: usize) -> GrayImage {
+    ImageBuffer::from_fn(output.width(), output.height(), |x, y| {
+        Luma([output.get_pixel(x, y)[c]])
+    })
+}
+
+// draws the one pixel border of `size`, clipped to `img`
+fn draw_outline(img: &mut RgbImage, size: &CropSize, color: Rgb<u8>) {
+    let (width, height) = img.dimensions();
+    if size.width == 0 || size.height == 0 || size.x >= width || size.y >= height {
+        return;
+    }
+    let (right, bottom) = (size.x + size.width - 1, size.y + size.height - 1);
+    for x in size.x..=right.min(width - 1) {
+        img.put_pixel(x, size.y, color);
+        if bottom < height {
+            img.put_pixel(x, bottom, color);
+        }
+    }
+    for y in size.y..=bottom.min(height - 1) {
+        img.put_pixel(size.x, y, color);
+        if right < width {
+            img.put_pixel(right, y, color);
+        }
+    }
+}
+
+// `depth` with its nearest value mapped to 0 and its farthest to u16::MAX
+fn stretch_depth(depth: &ImageBuffer<Luma<u16>, Vec<u16>>) -> ImageBuffer<Luma<u16>, Vec<u16>> {
+    let (min, max) = depth.pixels().fold((u16::MAX, 0), |(min, max), p| {
+        (min.min(p[0]), max.max(p[0]))
+    });
+    let range = max.saturating_sub(min).max(1) as f64;
+    ImageBuffer::from_fn(depth.width(), depth.height(), |x, y| {
+        let value = (depth.get_pixel(x, y)[0] - min) as f64 / range;
+        Luma([(value * u16::MAX as f64).round() as u16])
+    })
+}
+
+// Running sum of the terms get_score adds up for one channel
+trait ScoreSum {
+    fn add(&mut self, value: f64);
+    fn value(&self) -> f64;
+}
+
+impl ScoreSum for f64 {
+    fn add(&mut self, value: f64) {
+        *self += value;
+    }
+
+    fn value(&self) -> f64 {
+        *self
+    }
+}
+
+// Running f32 sum for f32_scoring, optionally Kahan compensated: the low
+// order bits lost by each addition are carried into the next one
+struct Accumulator {
+    sum: f32,
+    compensation: f32,
+    compensated: bool,
+}
+
+impl Accumulator {
+    fn new(compensated: bool) -> Accumulator {
+        Accumulator {
+            sum: 0.,
+            compensation: 0.,
+            compensated,
+        }
+    }
+}
+
+impl ScoreSum for Accumulator {
+    fn add(&mut self, value: f64) {
+        let value = value as f32;
+        if !self.compensated {
+            self.sum += value;
+            return;
+        }
+        let value = value - self.compensation;
+        let sum = self.sum + value;
+        self.compensation = (sum - self.sum) - value;
+        self.sum = sum;
+    }
+
+    fn value(&self) -> f64 {
+        self.sum as f64
+    }
+}
+
+// the `size` part of `img` at full resolution, after opts.rotate
+fn region(
+    img: &image::DynamicImage,
+    size: &CropSize,
+    opts: &SmartCrop,
+) -> Result<image::DynamicImage, SmartCropError> {
+    if size.width == 0 || size.height == 0 {
+        return Err(SmartCropError::NoCandidates);
+    }
+    if opts.rotate != Rotation::None {
+        let rotated = opts.rotate.apply(img);
+        let region = image::imageops::crop_imm(&rotated, size.x, size.y, size.width, size.height);
+        return Ok(ImageRgba8(region.to_image()));
+    }
+    Ok(img.crop_imm(size.x, size.y, size.width, size.height))
+}
+
+// turns a crop that had to ignore its constraints into an error
+fn checked(result: CropResult) -> Result<CropResult, SmartCropError> {
+    if result.constraints_unmet {
+        Err(SmartCropError::ConstraintsUnmet)
+    } else {
+        Ok(result)
+    }
+}
+
+// `img` blended over an opaque `background` according to its alpha
+fn composite<I>(img: &I, background: Rgb<u8>) -> RgbaImage
+where
+    I: GenericImageView<Pixel = Rgba<u8>>,
+{
+    ImageBuffer::from_fn(img.width(), img.height(), |x, y| {
+        let pixel = img.get_pixel(x, y);
+        let alpha = pixel[3] as u32;
+        let blend = |c: usize| {
+            ((pixel[c] as u32 * alpha + background[c] as u32 * (255 - alpha) + 127) / 255) as u8
+        };
+        Rgba([blend(0), blend(1), blend(2), 255])
+    })
+}
+
+// Mean of `values` (a width x height grid) over the square of the given
+// radius around each cell, clipped to the grid, using a summed-area table.
+fn box_mean(values: &[f64], width: u32, height: u32, radius: u32) -> Vec<f64> {
+    let (w, h, r) = (width as usize, height as usize, radius as usize);
+    let mut sums = vec![0.; (w + 1) * (h + 1)];
+    for y in 0..h {
+        let mut row = 0.;
+        for x in 0..w {
+            row += values[y * w + x];
+            sums[(y + 1) * (w + 1) + x + 1] = sums[y * (w + 1) + x + 1] + row;
+        }
+    }
+    let mut means = Vec::with_capacity(w * h);
+    for y in 0..h {
+        let (y0, y1) = (y.saturating_sub(r), usize::min(y + r + 1, h));
+        for x in 0..w {
+            let (x0, x1) = (x.saturating_sub(r), usize::min(x + r + 1, w));
+            let sum = sums[y1 * (w + 1) + x1] - sums[y0 * (w + 1) + x1] - sums[y1 * (w + 1) + x0]
+                + sums[y0 * (w + 1) + x0];
+            means.push(sum / ((x1 - x0) * (y1 - y0)) as f64);
+        }
+    }
+    means
+}
+
+// Bilateral filter of `values` (width x height, row by row): each value
+// becomes the mean of its neighbors within 2 * spatial_sigma, weighted by
+// distance and by how close they are in value, so that steps much larger
+// than range_sigma aren't blurred.
+fn bilateral(
+    values: &[f64],
+    width: u32,
+    height: u32,
+    spatial_sigma: f64,
+    range_sigma: f64,
+) -> Vec<f64> {
+    let (w, h) = (width as i64, height as i64);
+    let radius = (spatial_sigma * 2.).ceil() as i64;
+    let spatial = |d: i64| (-(d * d) as f64 / (2. * spatial_sigma * spatial_sigma)).exp();
+    let range = |d: f64| (-(d * d) / (2. * range_sigma.max(1e-6).powi(2))).exp();
+    let mut filtered = Vec::with_capacity(values.len());
+    for y in 0..h {
+        for x in 0..w {
+            let center = values[(y * w + x) as usize];
+            let (mut sum, mut weights) = (0., 0.);
+            for ny in (y - radius).max(0)..=(y + radius).min(h - 1) {
+                for nx in (x - radius).max(0)..=(x + radius).min(w - 1) {
+                    let value = values[(ny * w + nx) as usize];
+                    let weight = spatial(nx - x) * spatial(ny - y) * range(value - center);
+                    sum += value * weight;
+                    weights += weight;
+                }
+            }
+            filtered.push(sum / weights);
+        }
+    }
+    filtered
+}
+
+fn prescaled_dimensions(width: u32, height: u32, prescale: f64) -> (u32, u32) {
+    (
+        u32::max((width as f64 * prescale) as u32, 1),
+        u32::max((height as f64 * prescale) as u32, 1),
+    )
+}
+
+// Area-average resize implemented in this crate so that results don't depend
+// on the resampling filters of the image crate.
+fn box_resize<I>(img: &I, width: u32, height: u32) -> RgbaImage
+where
+    I: GenericImageView,
+    I::Pixel: Pixel<Subpixel = u8>,
+{
+    let mut output = ImageBuffer::new(width, height);
+    box_resize_into(img, &mut output);
+    output
+}
+
+// box_resize into `output`, at the size of `output`
+fn box_resize_into<I>(img: &I, output: &mut RgbaImage)
+where
+    I: GenericImageView,
+    I::Pixel: Pixel<Subpixel = u8>,
+{
+    let (src_width, src_height) = img.dimensions();
+    let (width, height) = output.dimensions();
+    let scale_x = src_width as f64 / width as f64;
+    let scale_y = src_height as f64 / height as f64;
+    for (out_x, out_y, output_pixel) in output.enumerate_pixels_mut() {
+        let (x0, x1) = (out_x as f64 * scale_x, (out_x + 1) as f64 * scale_x);
+        let (y0, y1) = (out_y as f64 * scale_y, (out_y + 1) as f64 * scale_y);
+        let mut sum = [0.; 4];
+        let mut area = 0.;
+        for y in y0.floor() as u32..u32::min(y1.ceil() as u32, src_height) {
+            let wy = f64::min(y1, (y + 1) as f64) - f64::max(y0, y as f64);
+            for x in x0.floor() as u32..u32::min(x1.ceil() as u32, src_width) {
+                let wx = f64::min(x1, (x + 1) as f64) - f64::max(x0, x as f64);
+                let pixel = img.get_pixel(x, y).to_rgba();
+                for (c, v) in sum.iter_mut().enumerate() {
+                    *v += pixel[c] as f64 * wx * wy;
+                }
+                area += wx * wy;
+            }
+        }
+        *output_pixel = Rgba([
+            (sum[0] / area).round() as u8,
+            (sum[1] / area).round() as u8,
+            (sum[2] / area).round() as u8,
+            (sum[3] / area).round() as u8,
+        ]);
+    }
+}
+
+// Clears `channel` for 4-connected regions of non-zero pixels smaller than
+// `min_size` pixels.
+fn suppress_small_regions(
+    output: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
+    channel: usize,
+    min_size: u32,
+) {
+    let (w, h) = output.dimensions();
+    let mut visited = vec![false; w as usize * h as usize];
+    let mut stack = Vec::new();
+    let mut region = Vec::new();
+    for start_y in 0..h {
+        for start_x in 0..w {
+            let start = (start_y * w + start_x) as usize;
+            if visited[start] || output.get_pixel(start_x, start_y)[channel] == 0 {
+                continue;
+            }
+            visited[start] = true;
+            stack.push((start_x, start_y));
+            region.clear();
+            while let Some((x, y)) = stack.pop() {
+                region.push((x, y));
+                let neighbors = [
+                    (x.wrapping_sub(1), y),
+                    (x + 1, y),
+                    (x, y.wrapping_sub(1)),
+                    (x, y + 1),
+                ];
+                for &(nx, ny) in neighbors.iter() {
+                    if nx >= w || ny >= h {
+                        continue;
+                    }
+                    let index = (ny * w + nx) as usize;
+                    if !visited[index] && output.get_pixel(nx, ny)[channel] != 0 {
+                        visited[index] = true;
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+            if (region.len() as u32) < min_size {
+                for &(x, y) in region.iter() {
+                    output.get_pixel_mut(x, y)[channel] = 0;
+                }
+            }
+        }
+    }
+}
+
+fn thirds(x: f64) -> f64 {
+    let y = ((x - (1. / 3.) + 1.0) % 2.0 * 0.5 - 0.5) * 16.;
+    f64::max(1.0 - y * y, 0.0)
+}
+
+fn cie(r: f64, g: f64, b: f64) -> f64 {
+    0.5126 * b + 0.7152 * g + 0.0722 * r
+}
+
+fn sample(pixel: Rgba<u8>) -> f64 {
+    let r = pixel[0] as f64;
+    let g = pixel[1] as f64;
+    let b = pixel[2] as f64;
+    cie(r, g, b)
+}
+
+// sRGB component (0.0 - 255.0) in linear light, on the same scale
+fn linear(c: f64) -> f64 {
+    let c = c / 255.;
+    let linear = if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    };
+    linear * 255.
+}
+
+// sample on linear light components
+fn linear_sample(pixel: Rgba<u8>) -> f64 {
+    cie(
+        linear(pixel[0] as f64),
+        linear(pixel[1] as f64),
+        linear(pixel[2] as f64),
+    )
+}
+
+// Mean detail (0.0 - 1.0) along the border of `crop` in the detection output,
+// high when the crop cuts through an object instead of a flat area.
+fn seam_energy(output: &ImageBuffer<Rgb<u8>, Vec<u8>>, crop: &CropSize) -> f64 {
+    let (w, h) = output.dimensions();
+    let crop = crop.clamp(w, h);
+    if crop.width == 0 || crop.height == 0 {
+        return 0.;
+    }
+    let (x0, y0) = (crop.x, crop.y);
+    let (x1, y1) = (crop.x + crop.width - 1, crop.y + crop.height - 1);
+    let detail = |x, y| output.get_pixel(x, y)[1] as f64 / 255.;
+    let mut sum = 0.;
+    let mut count = 0.;
+    for x in x0..=x1 {
+        sum += detail(x, y0) + detail(x, y1);
+        count += 2.;
+    }
+    for y in y0..=y1 {
+        sum += detail(x0, y) + detail(x1, y);
+        count += 2.;
+    }
+    sum / count
+}
+
+// lightness of every pixel of `img`, row-major
+fn luma_samples<I>(img: &I, linear: bool) -> Vec<f64>
+where
+    I: GenericImageView<Pixel = Rgba<u8>>,
+{
+    let sample = if linear { linear_sample } else { sample };
+    img.pixels().map(|(_, _, pixel)| sample(pixel)).collect()
+}
+
+// Whether the red to green ratio and saturation of `pixel` are in the range of
+// skin. Warm backgrounds point in a similar color direction but are redder or
+// more saturated.
+fn skin_plausible(pixel: Rgba<u8>) -> bool {
+    let ratio = pixel[0] as f64 / f64::max(pixel[1] as f64, 1.);
+    let (min_ratio, max_ratio) = SKIN_RED_GREEN_RATIO;
+    let (min_saturation, max_saturation) = SKIN_SATURATION;
+    let saturation = saturation(pixel);
+    (min_ratio..=max_ratio).contains(&ratio)
+        && (min_saturation..=max_saturation).contains(&saturation)
+}
+
+fn saturation(pixel: Rgba<u8>) -> f64 {
+    let r = pixel[0] as f64;
+    let g = pixel[1] as f64;
+    let b = pixel[2] as f64;
+    let id = [r / 255., g / 255., b / 255.];
+    let maximum = id.iter().fold(f64::NAN, |m, v| v.max(m));
+    let minumum = id.iter().fold(f64::NAN, |m, v| v.min(m));
+    if maximum == minumum {
+        return 0.;
+    }
+    let l = (maximum + minumum) / 2.;
+    let d = maximum - minumum;
+    if l > 0.5 {
+        d / (2. - maximum - minumum)
+    } else {
+        d / (maximum + minumum)
+    }
+}
+
+/// Crop detection and scoring parameters. Every method takes `&self` and
+/// `SmartCrop` is `Send + Sync`, so one configured instance can be shared
+/// between threads through an `Arc`.
+#[derive(Clone, Debug)]
+pub struct SmartCrop {
+    pub width: u32,
+    pub height: u32,
+    #[allow(dead_code)]
+    aspect: i32,
+    crop_width: i32,
+    crop_height: i32,
+    pub detail_weight: f64,
+    skin_color: (f64, f64, f64),
+    skin_bias: f64,
+    skin_brightness_min: f64,
+    skin_brightness_max: f64,
+    skin_threshold: f64,
+    // skin is the mean response of SKIN_PASSES thresholds spread over
+    // skin_threshold +- skin_softness, a graded confidence that depends less
+    // on the exact threshold (0 = the single skin_threshold ramp)
+    pub skin_softness: f64,
+    pub skin_weight: f64,
+    // skin regions (4-connected, in working pixels) smaller than this are dropped; 0 = off
+    pub skin_min_region: u32,
+    // skin is detected once per skin_subsample x skin_subsample block, at its
+    // center, and spread over the block; skin color changes slowly, edges
+    // stay at full resolution (0 or 1 = every pixel)
+    pub skin_subsample: u32,
+    // how much skin is discounted on pixels whose red to green ratio or
+    // saturation fall outside the range of skin, e.g. brick, wood or sunsets
+    // (0 = off, 1 = not counted at all)
+    pub skin_strictness: f64,
+    // bound on the magnitude of the average skin and saturation response per
+    // score map sample that counts towards the total, so that a frame full of skin or
+    // colour can't drown out detail, boosts and composition; None = off
+    pub skin_cap: Option<f64>,
+    pub saturation_cap: Option<f64>,
+    saturation_brightness_min: f64,
+    saturation_brightness_max: f64,
+    saturation_threshold: f64,
+    saturation_bias: f64,
+    pub saturation_weight: f64,
+    // detect skin and saturation in much darker pixels, for dark, moody images
+    pub low_key: bool,
+    // detect saturation relative to the mean within this radius (in working
+    // pixels) rather than absolutely, so locally vivid color stands out in
+    // muted images (0 = off)
+    pub local_contrast_radius: u32,
+    // rescale the weights above, along with boost_weight when there are boosts,
+    // to sum to 1.0 before scoring
+    pub normalize_weights: bool,
+    // step * minscale rounded down to the next power of two should be good;
+    // auto_score_down_sample derives it that way instead of using this value
+    score_down_sample: u32,
+    pub auto_score_down_sample: bool,
+    // score the detection channels without downsampling (score_down_sample = 1)
+    pub full_resolution_scoring: bool,
+    step: u32,
+    // candidate spacing along each axis; 0 uses step
+    pub step_x: u32,
+    pub step_y: u32,
+    // the step along the axis with the most positions is widened until there
+    // are at most this many candidates, e.g. for very wide panoramas (0 = no limit)
+    pub max_candidates: usize,
+    // round crop sizes down to multiples of this, e.g. 16 for macroblocks
+    pub dimension_multiple: Option<u32>,
+    // round crop origins down to multiples of this
+    pub origin_multiple: Option<u32>,
+    scale_step: f64,
+    // relative deviation from the target aspect candidates may have (0 = exact)
+    pub aspect_tolerance: f64,
+    // relative aspect difference between a crop and its output tolerated when
+    // resizing; larger ones are trimmed instead of distorting the image
+    pub max_aspect_distortion: f64,
+    min_scale: f64,
+    max_scale: f64,
+    edge_radius: f64,
+    edge_weight: f64,
+    // ignore transparent pixels, scoring crops by the density of their opaque content
+    pub mask_transparent: bool,
+    // the opaque silhouette of a cutout or sticker is the subject: the detail
+    // channel is the alpha channel and the color detectors are skipped
+    pub alpha_shape: bool,
+    // color transparent images are analyzed over, as they'd be displayed. The
+    // result is opaque, so mask_transparent has no effect along with it.
+    pub composite_background: Option<Rgb<u8>>,
+    // run the detectors on the image halved this many times, for a response
+    // that depends less on the subject's size (0 = full working resolution)
+    pub detection_octave: u32,
+    // sigma of a Gaussian blur applied before edge detection to suppress noise (0 = off)
+    pub pre_blur_sigma: f64,
+    // sigmas of a bilateral filter applied to lightness before edge detection:
+    // noise in flat areas is smoothed over spatial_sigma pixels while steps of
+    // more than about range_sigma (in 0 - 255 lightness) survive (0 = off)
+    pub bilateral_spatial_sigma: f64,
+    pub bilateral_range_sigma: f64,
+    // edge detector of the detail channel
+    pub edge_operator: EdgeOperator,
+    // detect edges on linear light lightness rather than on sRGB values, which
+    // weighs contrast in dark areas less. The skin and saturation brightness
+    // gates (skin_brightness_min etc.) stay in sRGB terms unless
+    // linear_brightness_gates is also set, so either can be turned on
+    // without shifting what the other's thresholds mean.
+    pub linear_edges: bool,
+    pub linear_brightness_gates: bool,
+    // regions to favor, e.g. known subjects or focal points supplied by an editor
+    pub boosts: Vec<Boost>,
+    pub boost_weight: f64,
+    // face boxes from an external detector, in image coordinates, boosted like boosts
+    pub faces: Vec<CropSize>,
+    // how much more a larger face pulls the crop than a smaller one
+    pub face_size_weight: f64,
+    // with faces given, skin inside a face box counts face_skin_factor times
+    // and skin outside all of them off_face_skin_factor times as much as skin
+    // does without faces
+    pub off_face_skin_factor: f64,
+    pub face_skin_factor: f64,
+    // distance of each pixel from the camera, e.g. from a stereo camera or a
+    // depth estimation model, stretched over the image if its size differs.
+    // Importance is scaled by 1 + depth_weight for the nearest value in the
+    // map down to 1 - depth_weight for the farthest.
+    pub depth_map: Option<ImageBuffer<Luma<u16>, Vec<u16>>>,
+    pub depth_weight: f64,
+    // importance of pixels outside the crop. Negative values penalize leaving
+    // salient content out and favor larger crops; positive values reward
+    // excluding it and favor tighter crops. 0 ignores the outside entirely.
+    pub outside_importance: f64,
+    // parts of the output that will be covered, which salient content should avoid
+    pub overlay_zones: Vec<OverlayZone>,
+    // only candidates meeting all of these compete, unless none does
+    pub constraints: Constraints,
+    // (source_x, source_y, target_x, target_y): a point in image coordinates
+    // every crop places at the relative position (0.0 - 1.0) target_x,
+    // target_y of itself. Only crop sizes that fit around the point that way
+    // are searched, unless none does; then crops get as close as the image
+    // borders allow.
+    pub anchor: Option<(u32, u32, f64, f64)>,
+    // previous crop (in image coordinates) of a similar image. Only candidates
+    // within WARM_START_RADIUS of its position and size are searched, unless
+    // the best of them is at the edge of that neighborhood, when the best crop
+    // probably lies beyond and the full search runs.
+    pub warm_start: Option<CropSize>,
+    // fraction (0.0 - 1.0) of the image's saliency the chosen crop has to contain
+    pub min_saliency_coverage: f64,
+    // confidence_margin below which a result is flagged as ambiguous
+    pub ambiguity_threshold: f64,
+    // time the candidate search may take; when it runs out, the best crop
+    // scored so far is returned
+    pub time_budget: Option<Duration>,
+    // number of candidates that may be scored (at least 1), like time_budget
+    // but the same on every machine
+    pub candidate_budget: Option<usize>,
+    rule_of_thirds: bool,
+    // bonus for crops placing a detected horizon on a third line (0 = off)
+    pub horizon_bias: f64,
+    // penalty for crops whose border cuts through detail rather than flat areas (0 = off)
+    pub seam_penalty: f64,
+    // bonus for crops balancing saliency between their left and right halves (0 = off)
+    pub symmetry_weight: f64,
+    // bonus for crops centered near the image center, for already centered subjects (0 = off)
+    pub center_bias: f64,
+    // bonus for crops leaving calm, low detail space around the subject (0 = off)
+    pub negative_space_weight: f64,
+    // replaces score.total when choosing the top crop
+    pub objective_fn: Option<Objective>,
+    pub mode: CropMode,
+    pub output_shape: OutputShape,
+    // origin of the y coordinate of reported crops
+    pub coordinate_origin: CoordinateOrigin,
+    pub rotate: Rotation,
+    prescale: bool,
+    // let crops smaller than the target (which need upscaling) be picked
+    pub allow_upscale: bool,
+    // use the built-in box filter instead of image's resize, for reproducible results
+    pub stable_resize: bool,
+    // downsample the score map by area averaging (as stable_resize does) rather
+    // than Lanczos, whose ringing skews the detection channels it averages
+    pub box_score_map: bool,
+    // analyze the decoded part of a truncated JPEG instead of failing
+    pub lenient_decode: bool,
+    // bands of a file (e.g. a multispectral TIFF) analyzed as R, G and B, in
+    // that order; repeat a band to analyze fewer than three
+    pub band_mapping: Option<[usize; 3]>,
+    // accumulate crop scores in f32 instead of f64
+    pub f32_scoring: bool,
+    // sum the f32_scoring accumulators with Kahan compensation, so that large
+    // crops of many samples still rank near ties as f64 would
+    pub compensated_sum: bool,
+    // number of runner-up candidates drawn by annotate
+    pub annotate_top_n: usize,
+    debug: bool,
+    // encoding of the files written by crop_to_file
+    pub output_format: OutputFormat,
+}
+
+impl Default for SmartCrop {
+    fn default() -> SmartCrop {
+        SmartCrop {
+            width: 0,
+            height: 0,
+            aspect: 0,
+            crop_width: 0,
+            crop_height: 0,
+            detail_weight: 0.2,
+            skin_color: (0.78, 0.57, 0.44),
+            skin_bias: 0.01,
+            skin_brightness_min: 0.2,
+            skin_brightness_max: 1.0,
+            skin_threshold: 0.8,
+            skin_softness: 0.,
+            skin_weight: 1.8,
+            skin_min_region: 0,
+            skin_subsample: 1,
+            skin_strictness: 0.,
+            skin_cap: None,
+            saturation_cap: None,
+            saturation_brightness_min: 0.05,
+            saturation_brightness_max: 0.9,
+            saturation_threshold: 0.4,
+            saturation_bias: 0.2,
+            saturation_weight: 0.3,
+            low_key: false,
+            local_contrast_radius: 0,
+            normalize_weights: false,
+            // step * minscale rounded down to the next power of two should be good
+            score_down_sample: 8,
+            auto_score_down_sample: false,
+            full_resolution_scoring: false,
+            step: 8,
+            step_x: 0,
+            step_y: 0,
+            max_candidates: 4000,
+            dimension_multiple: None,
+            origin_multiple: None,
+            scale_step: 0.1,
+            aspect_tolerance: 0.,
+            max_aspect_distortion: 0.01,
+            min_scale: 0.9,
+            max_scale: 1.0,
+            edge_radius: 0.4,
+            edge_weight: -20.0,
+            mask_transparent: false,
+            alpha_shape: false,
+            composite_background: None,
+            detection_octave: 0,
+            pre_blur_sigma: 0.,
+            bilateral_spatial_sigma: 0.,
+            bilateral_range_sigma: 20.,
+            edge_operator: EdgeOperator::Laplacian,
+            linear_edges: false,
+            linear_brightness_gates: false,
+            boosts: Vec::new(),
+            boost_weight: 100.0,
+            faces: Vec::new(),
+            face_size_weight: 0.,
+            off_face_skin_factor: 1.,
+            face_skin_factor: 1.,
+            depth_map: None,
+            depth_weight: 0.5,
+            outside_importance: -0.5,
+            overlay_zones: Vec::new(),
+            constraints: Constraints::default(),
+            anchor: None,
+            warm_start: None,
+            min_saliency_coverage: 0.,
+            ambiguity_threshold: 0.05,
+            time_budget: None,
+            candidate_budget: None,
+            rule_of_thirds: true,
+            horizon_bias: 0.,
+            seam_penalty: 0.,
+            symmetry_weight: 0.,
+            center_bias: 0.,
+            negative_space_weight: 0.,
+            objective_fn: None,
+            mode: CropMode::Fill,
+            output_shape: OutputShape::Rect,
+            coordinate_origin: CoordinateOrigin::TopLeft,
+            rotate: Rotation::None,
+            prescale: true,
+            allow_upscale: false,
+            stable_resize: false,
+            box_score_map: false,
+            lenient_decode: false,
+            band_mapping: None,
+            f32_scoring: false,
+            compensated_sum: false,
+            annotate_top_n: 0,
+            debug: false,
+            output_format: OutputFormat::default(),
+        }
+    }
+}
+
+impl SmartCrop {
+    pub fn new() -> SmartCrop {
+        SmartCrop::default()
+    }
+
+    pub fn preset(preset: Preset) -> SmartCrop {
+        let default = SmartCrop::default();
+        match preset {
+            Preset::Portrait => SmartCrop {
+                detail_weight: 0.1,
+                skin_bias: 0.2,
+                skin_weight: 2.5,
+                saturation_weight: 0.1,
+                ..default
+            },
+            Preset::Landscape => SmartCrop {
+                detail_weight: 0.5,
+                skin_weight: 0.,
+                saturation_weight: 0.2,
+                ..default
+            },
+            Preset::Product => SmartCrop {
+                skin_weight: 0.5,
+                saturation_bias: 0.5,
+                saturation_weight: 1.0,
+                ..default
+            },
+            Preset::Text => SmartCrop {
+                detail_weight: 1.0,
+                skin_weight: 0.,
+                saturation_weight: 0.,
+                edge_radius: 0.2,
+                ..default
+            },
+        }
+    }
+
+    pub fn crop(&self, path: &Path, opts: &SmartCrop) -> Result<CropResult, SmartCropError> {
+        let img = load_image(path, self.lenient_decode, self.band_mapping)?;
+        checked(self.crop_image(&img, opts))
+    }
+
+    /// Crops any image view (e.g. a `SubImage` into an atlas) without copying
+    /// it, unless it has to be prescaled first.
+    pub fn crop_image<I>(&self, img: &I, opts: &SmartCrop) -> CropResult
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        let analysis = self.prepare(img, opts);
+        self.rescore(&analysis, opts)
+    }
+
+    /// Crops `img` with configurations `a` and `b`, each also giving the
+    /// target, and reports how much the top crop moved, for tracking the
+    /// effect of tuning changes.
+    pub fn crop_delta(img: &image::DynamicImage, a: &SmartCrop, b: &SmartCrop) -> CropDelta {
+        let (a, b) = (a.crop_image(img, a), b.crop_image(img, b));
+        CropDelta {
+            iou: a
+                .top_left(&a.top_crop.size)
+                .iou(&b.top_left(&b.top_crop.size)),
+            score_delta: b.top_crop.score.total - a.top_crop.score.total,
+        }
+    }
+
+    /// Returns the best crop of `img` at full source resolution, without
+    /// resizing it to the target. With `opts.rotate` the region is taken from
+    /// the rotated image, which is what the crop coordinates refer to.
+    pub fn best_crop_region(
+        &self,
+        img: &image::DynamicImage,
+        opts: &SmartCrop,
+    ) -> Result<image::DynamicImage, SmartCropError> {
+        let result = checked(self.crop_image(img, opts))?;
+        region(img, &result.top_left(&result.top_crop.size), opts)
+    }
+
+    /// Returns the best crop of `img` resized to exactly the target in `opts`.
+    /// Where the ideal crop overflows the image, as Fit mode's padding does,
+    /// the overflow is filled according to `fill` rather than clipped, which
+    /// keeps the subject where the crop placed it.
+    pub fn padded_crop(
+        &self,
+        img: &image::DynamicImage,
+        fill: PadFill,
+        opts: &SmartCrop,
+    ) -> Result<image::DynamicImage, SmartCropError> {
+        let result = checked(self.crop_image(img, opts))?;
+        let size = result.top_left(&result.top_crop.size);
+        if size.width == 0 || size.height == 0 {
+            return Err(SmartCropError::NoCandidates);
+        }
+        let padding = &result.padding;
+        let source = opts.rotate.apply(img);
+        let (width, height) = source.dimensions();
+        let padded = ImageBuffer::from_fn(
+            size.width + padding.left + padding.right,
+            size.height + padding.top + padding.bottom,
+            |x, y| {
+                let sx = size.x as i64 + x as i64 - padding.left as i64;
+                let sy = size.y as i64 + y as i64 - padding.top as i64;
+                let inside = (0..width as i64).contains(&sx) && (0..height as i64).contains(&sy);
+                match fill {
+                    _ if inside => *source.get_pixel(sx as u32, sy as u32),
+                    PadFill::Reflect => *source.get_pixel(reflect(sx, width), reflect(sy, height)),
+                    PadFill::Color(color) => color,
+                }
+            },
+        );
+        let output = ImageRgba8(padded);
+        if opts.width == 0 || opts.height == 0 {
+            return Ok(output);
+        }
+        Ok(output.resize_exact(
+            opts.width,
+            opts.height,
+            image::imageops::FilterType::Lanczos3,
+        ))
+    }
+
+    /// Crops `img`, resizes the crop to the target in `opts` and writes it to
+    /// `path` encoded as `output_format`. Crops off the target aspect are
+    /// trimmed like in `crop_to_slot`, and are only enlarged to the target
+    /// with `opts.allow_upscale`.
+    pub fn crop_to_file(
+        &self,
+        img: &image::DynamicImage,
+        path: &Path,
+        opts: &SmartCrop,
+    ) -> Result<CropResult, SmartCropError> {
+        let result = checked(self.crop_image(img, opts))?;
+        let mut output = region(img, &result.top_left(&result.top_crop.size), opts)?;
+        if opts.width != 0 && opts.height != 0 {
+            let size = CropSize {
+                x: 0,
+                y: 0,
+                width: output.width(),
+                height: output.height(),
+            }
+            .fit_aspect(opts.width, opts.height, self.max_aspect_distortion);
+            output = output.crop_imm(size.x, size.y, size.width, size.height);
+            if opts.allow_upscale || (size.width >= opts.width && size.height >= opts.height) {
+                output = output.resize_exact(
+                    opts.width,
+                    opts.height,
+                    image::imageops::FilterType::Lanczos3,
+                );
+            }
+        }
+        self.save(&output, path)?;
+        Ok(result)
+    }
+
+    // writes `img` to `path` as output_format
+    fn save(&self, img: &image::DynamicImage, path: &Path) -> Result<(), SmartCropError> {
+        let format = match self.output_format {
+            OutputFormat::Jpeg { quality } => image::ImageOutputFormat::Jpeg(quality.clamp(1, 100)),
+            OutputFormat::Png => image::ImageOutputFormat::Png,
+        };
+        let mut file = io::BufWriter::new(File::create(path)?);
+        match format {
+            // JPEG has no alpha channel
+            image::ImageOutputFormat::Jpeg(_) => {
+                ImageRgb8(img.to_rgb8()).write_to(&mut file, format)?
+            }
+            _ => img.write_to(&mut file, format)?,
+        }
+        Ok(())
+    }
+
+    /// Crops `img` and returns the result along with the edge, skin and
+    /// saturation maps it was based on, entirely in memory: nothing is written
+    /// to the filesystem, even with `debug` set.
+    pub fn analyze_full(&self, img: &image::DynamicImage, opts: &SmartCrop) -> AnalysisOutput {
+        let (analysis, result) = self.analyze_quietly(img, opts);
+        AnalysisOutput {
+            result,
+            edge: channel(&analysis.output, 1),
+            skin: channel(&analysis.output, 0),
+            saturation: channel(&analysis.output, 2),
+        }
+    }
+
+    /// Crops `img` and returns every intermediate image along with the result,
+    /// for tuning tools: the detection channels, the score map candidates are
+    /// summed over and the candidates drawn over `img`. Like `analyze_full`
+    /// nothing is written to the filesystem.
+    pub fn crop_with_debug(
+        &self,
+        img: &image::DynamicImage,
+        opts: &SmartCrop,
+    ) -> (CropResult, DebugBundle) {
+        let (analysis, result) = self.analyze_quietly(img, opts);
+        let bundle = DebugBundle {
+            edge: channel(&analysis.output, 1),
+            skin: channel(&analysis.output, 0),
+            saturation: channel(&analysis.output, 2),
+            score_map: analysis.score_map.to_rgb8(),
+            annotated: self.annotate(img, &result),
+        };
+        (result, bundle)
+    }
+
+    // prepare and rescore with debug output off
+    fn analyze_quietly(
+        &self,
+        img: &image::DynamicImage,
+        opts: &SmartCrop,
+    ) -> (Analysis, CropResult) {
+        let sc = SmartCrop {
+            debug: false,
+            ..self.clone()
+        };
+        let analysis = sc.prepare(img, opts);
+        let result = sc.rescore(&analysis, opts);
+        (analysis, result)
+    }
+
+    /// Builds a SmartCrop from the tunables in `cfg`, leaving the target,
+    /// boosts, faces and other per-call inputs at their defaults.
+    pub fn from_config(cfg: &CropConfig) -> SmartCrop {
+        let cfg = cfg.clone();
+        SmartCrop {
+            detail_weight: cfg.detail_weight,
+            skin_color: cfg.skin_color,
+            skin_bias: cfg.skin_bias,
+            skin_brightness_min: cfg.skin_brightness_min,
+            skin_brightness_max: cfg.skin_brightness_max,
+            skin_threshold: cfg.skin_threshold,
+            skin_softness: cfg.skin_softness,
+            skin_weight: cfg.skin_weight,
+            skin_min_region: cfg.skin_min_region,
+            skin_subsample: cfg.skin_subsample,
+            skin_strictness: cfg.skin_strictness,
+            skin_cap: cfg.skin_cap,
+            saturation_cap: cfg.saturation_cap,
+            saturation_brightness_min: cfg.saturation_brightness_min,
+            saturation_brightness_max: cfg.saturation_brightness_max,
+            saturation_threshold: cfg.saturation_threshold,
+            saturation_bias: cfg.saturation_bias,
+            saturation_weight: cfg.saturation_weight,
+            low_key: cfg.low_key,
+            local_contrast_radius: cfg.local_contrast_radius,
+            normalize_weights: cfg.normalize_weights,
+            score_down_sample: cfg.score_down_sample,
+            auto_score_down_sample: cfg.auto_score_down_sample,
+            full_resolution_scoring: cfg.full_resolution_scoring,
+            step: cfg.step,
+            step_x: cfg.step_x,
+            step_y: cfg.step_y,
+            max_candidates: cfg.max_candidates,
+            dimension_multiple: cfg.dimension_multiple,
+            origin_multiple: cfg.origin_multiple,
+            scale_step: cfg.scale_step,
+            aspect_tolerance: cfg.aspect_tolerance,
+            max_aspect_distortion: cfg.max_aspect_distortion,
+            min_scale: cfg.min_scale,
+            max_scale: cfg.max_scale,
+            edge_radius: cfg.edge_radius,
+            edge_weight: cfg.edge_weight,
+            mask_transparent: cfg.mask_transparent,
+            alpha_shape: cfg.alpha_shape,
+            detection_octave: cfg.detection_octave,
+            pre_blur_sigma: cfg.pre_blur_sigma,
+            bilateral_spatial_sigma: cfg.bilateral_spatial_sigma,
+            bilateral_range_sigma: cfg.bilateral_range_sigma,
+            edge_operator: cfg.edge_operator,
+            linear_edges: cfg.linear_edges,
+            linear_brightness_gates: cfg.linear_brightness_gates,
+            boost_weight: cfg.boost_weight,
+            face_size_weight: cfg.face_size_weight,
+            off_face_skin_factor: cfg.off_face_skin_factor,
+            face_skin_factor: cfg.face_skin_factor,
+            depth_weight: cfg.depth_weight,
+            outside_importance: cfg.outside_importance,
+            min_saliency_coverage: cfg.min_saliency_coverage,
+            ambiguity_threshold: cfg.ambiguity_threshold,
+            rule_of_thirds: cfg.rule_of_thirds,
+            horizon_bias: cfg.horizon_bias,
+            seam_penalty: cfg.seam_penalty,
+            symmetry_weight: cfg.symmetry_weight,
+            center_bias: cfg.center_bias,
+            negative_space_weight: cfg.negative_space_weight,
+            mode: cfg.mode,
+            output_shape: cfg.output_shape,
+            coordinate_origin: cfg.coordinate_origin,
+            prescale: cfg.prescale,
+            allow_upscale: cfg.allow_upscale,
+            stable_resize: cfg.stable_resize,
+            box_score_map: cfg.box_score_map,
+            lenient_decode: cfg.lenient_decode,
+            band_mapping: cfg.band_mapping,
+            f32_scoring: cfg.f32_scoring,
+            compensated_sum: cfg.compensated_sum,
+            ..SmartCrop::default()
+        }
+    }
+
+    /// The tunables of `self` as a `CropConfig`, e.g. to save a tuned setup.
+    pub fn to_config(&self) -> CropConfig {
+        CropConfig {
+            version: CROP_CONFIG_VERSION,
+            detail_weight: self.detail_weight,
+            skin_color: self.skin_color,
+            skin_bias: self.skin_bias,
+            skin_brightness_min: self.skin_brightness_min,
+            skin_brightness_max: self.skin_brightness_max,
+            skin_threshold: self.skin_threshold,
+            skin_softness: self.skin_softness,
+            skin_weight: self.skin_weight,
+            skin_min_region: self.skin_min_region,
+            skin_subsample: self.skin_subsample,
+            skin_strictness: self.skin_strictness,
+            skin_cap: self.skin_cap,
+            saturation_cap: self.saturation_cap,
+            saturation_brightness_min: self.saturation_brightness_min,
+            saturation_brightness_max: self.saturation_brightness_max,
+            saturation_threshold: self.saturation_threshold,
+            saturation_bias: self.saturation_bias,
+            saturation_weight: self.saturation_weight,
+            low_key: self.low_key,
+            local_contrast_radius: self.local_contrast_radius,
+            normalize_weights: self.normalize_weights,
+            score_down_sample: self.score_down_sample,
+            auto_score_down_sample: self.auto_score_down_sample,
+            full_resolution_scoring: self.full_resolution_scoring,
+            step: self.step,
+            step_x: self.step_x,
+            step_y: self.step_y,
+            max_candidates: self.max_candidates,
+            dimension_multiple: self.dimension_multiple,
+            origin_multiple: self.origin_multiple,
+            scale_step: self.scale_step,
+            aspect_tolerance: self.aspect_tolerance,
+            max_aspect_distortion: self.max_aspect_distortion,
+            min_scale: self.min_scale,
+            max_scale: self.max_scale,
+            edge_radius: self.edge_radius,
+            edge_weight: self.edge_weight,
+            mask_transparent: self.mask_transparent,
+            alpha_shape: self.alpha_shape,
+            detection_octave: self.detection_octave,
+            pre_blur_sigma: self.pre_blur_sigma,
+            bilateral_spatial_sigma: self.bilateral_spatial_sigma,
+            bilateral_range_sigma: self.bilateral_range_sigma,
+            edge_operator: self.edge_operator,
+            linear_edges: self.linear_edges,
+            linear_brightness_gates: self.linear_brightness_gates,
+            boost_weight: self.boost_weight,
+            face_size_weight: self.face_size_weight,
+            off_face_skin_factor: self.off_face_skin_factor,
+            face_skin_factor: self.face_skin_factor,
+            depth_weight: self.depth_weight,
+            outside_importance: self.outside_importance,
+            min_saliency_coverage: self.min_saliency_coverage,
+            ambiguity_threshold: self.ambiguity_threshold,
+            rule_of_thirds: self.rule_of_thirds,
+            horizon_bias: self.horizon_bias,
+            seam_penalty: self.seam_penalty,
+            symmetry_weight: self.symmetry_weight,
+            center_bias: self.center_bias,
+            negative_space_weight: self.negative_space_weight,
+            mode: self.mode,
+            output_shape: self.output_shape,
+            coordinate_origin: self.coordinate_origin,
+            prescale: self.prescale,
+            allow_upscale: self.allow_upscale,
+            stable_resize: self.stable_resize,
+            box_score_map: self.box_score_map,
+            lenient_decode: self.lenient_decode,
+            band_mapping: self.band_mapping,
+            f32_scoring: self.f32_scoring,
+            compensated_sum: self.compensated_sum,
+        }
+    }
+
+    /// Crops the image at `path` like `crop`, recording the decision, its
+    /// score components and timings in a `Report`.
+    pub fn report(&self, path: &Path, opts: &SmartCrop) -> Result<Report, SmartCropError> {
+        let img = load_image(path, self.lenient_decode, self.band_mapping)?;
+        let start = Instant::now();
+        let analysis = self.prepare(&img, opts);
+        let prepare_ms = start.elapsed().as_secs_f64() * 1000.;
+        let start = Instant::now();
+        let result = checked(self.rescore(&analysis, opts))?;
+        let score_ms = start.elapsed().as_secs_f64() * 1000.;
+
+        let mut fallbacks = Vec::new();
+        if result.score_down_sample < self.configured_score_down_sample() {
+            fallbacks.push("score_down_sample".to_string());
+        }
+        if result.saliency_coverage_fallback {
+            fallbacks.push("min_saliency_coverage".to_string());
+        }
+        if result.partial {
+            let budget = if self.candidate_budget.is_some() {
+                "candidate_budget"
+            } else {
+                "time_budget"
+            };
+            fallbacks.push(budget.to_string());
+        }
+        let score = &result.top_crop.score;
+        Ok(Report {
+            path: path.display().to_string(),
+            crop: result.top_crop.size.clone(),
+            detail: score.detail,
+            saturation: score.saturation,
+            skin: score.skin,
+            boost: score.boost,
+            total: score.total,
+            candidates: result.crops.len(),
+            prepare_ms,
+            score_ms,
+            fallbacks,
+        })
+    }
+
+    /// Crops `img` to the aspect of a `slot_width` x `slot_height` slot and
+    /// resizes the crop to fill it exactly. When the slot is larger than the
+    /// best crop, the crop is enlarged only with `allow_upscale`; otherwise it
+    /// is returned at its own size, at the slot's aspect. A crop whose aspect
+    /// is off by more than `max_aspect_distortion` (e.g. with
+    /// `aspect_tolerance`) is trimmed to the slot's around its center rather
+    /// than stretched. Everything but the target size comes from `self`, so
+    /// with `rotate` set the crop is taken from the rotated image.
+    pub fn crop_to_slot<I>(
+        &self,
+        img: &I,
+        slot_width: u32,
+        slot_height: u32,
+        allow_upscale: bool,
+    ) -> RgbaImage
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        let opts = SmartCrop {
+            width: slot_width,
+            height: slot_height,
+            allow_upscale,
+            ..self.clone()
+        };
+        let result = self.crop_image(img, &opts);
+        let size = result.top_left(&result.top_crop.size);
+        let size = size.fit_aspect(slot_width, slot_height, self.max_aspect_distortion);
+        let source = self.rotate.apply(img);
+        let cropped = image::imageops::crop_imm(&source, size.x, size.y, size.width, size.height);
+        let (width, height) =
+            if allow_upscale || (size.width >= slot_width && size.height >= slot_height) {
+                (slot_width, slot_height)
+            } else {
+                (size.width, size.height)
+            };
+        image::imageops::resize(
+            &cropped,
+            width,
+            height,
+            image::imageops::FilterType::Lanczos3,
+        )
+    }
+
+    /// Crops the image at `path` with the regions in `boosts_json` added to
+    /// `boosts`. The JSON is an array of `{"x", "y", "width", "height",
+    /// "weight"}` objects in image coordinates, as emitted by art tools.
+    pub fn crop_with_boost_json(
+        &self,
+        path: &Path,
+        boosts_json: &str,
+        opts: &SmartCrop,
+    ) -> Result<CropResult, SmartCropError> {
+        let boosts: Vec<Boost> =
+            serde_json::from_str(boosts_json).map_err(SmartCropError::InvalidBoosts)?;
+        let sc = SmartCrop {
+            boosts: self.boosts.iter().cloned().chain(boosts).collect(),
+            ..self.clone()
+        };
+        sc.crop(path, opts)
+    }
+
+    /// Runs detection for `img`, prescaling it for the target in `opts`. The
+    /// returned analysis can be scored repeatedly with `rescore`.
+    pub fn prepare<I>(&self, img: &I, opts: &SmartCrop) -> Analysis
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        if opts.rotate != Rotation::None {
+            let rotated = opts.rotate.apply(img);
+            let opts = SmartCrop {
+                rotate: Rotation::None,
+                ..opts.clone()
+            };
+            return self.prepare(&rotated, &opts);
+        }
+        if let Some(background) = self.composite_background {
+            // see the image as it will be rendered over the background,
+            // before prescaling blends transparent pixels into opaque ones
+            let sc = SmartCrop {
+                composite_background: None,
+                ..self.clone()
+            };
+            return sc.prepare(&composite(img, background), opts);
+        }
+        self.prepare_reusing(img, opts, &mut Scratch::default())
+    }
+
+    // prepare without rotation or compositing, filling the buffers in
+    // `scratch` instead of allocating new ones where their sizes match
+    fn prepare_reusing<I>(&self, img: &I, opts: &SmartCrop, scratch: &mut Scratch) -> Analysis
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        let (img_width, img_height) = img.dimensions();
+
+        let (working, prescale) = self.prepare_options(img_width, img_height, opts);
+        let previous = scratch.analysis.take();
+        let mut analysis = if prescale < 1. {
+            let (width, height) = prescaled_dimensions(img_width, img_height, prescale);
+            if self.stable_resize {
+                if scratch.resized.dimensions() != (width, height) {
+                    scratch.resized = ImageBuffer::new(width, height);
+                }
+                box_resize_into(img, &mut scratch.resized);
+                self.save_debug_thumb(&scratch.resized);
+                working.detect_reusing(&scratch.resized, previous)
+            } else {
+                let resized = image::imageops::resize(
+                    img,
+                    width,
+                    height,
+                    image::imageops::FilterType::Lanczos3,
+                );
+                self.save_debug_thumb(&resized);
+                working.detect_reusing(&resized, previous)
+            }
+        } else {
+            working.detect_reusing(img, previous)
+        };
+        analysis.prescale = prescale;
+        analysis.width = img_width;
+        analysis.height = img_height;
+        analysis
+    }
+
+    /// Analyzes `thumbnail`, a downscaled copy of an `original_width` x
+    /// `original_height` image, in place of the original, e.g. when a preview
+    /// is already at hand. The analysis refers to the original, so crops from
+    /// it come out in full resolution coordinates.
+    pub fn prepare_thumbnail<I>(
+        &self,
+        thumbnail: &I,
+        original_width: u32,
+        original_height: u32,
+        opts: &SmartCrop,
+    ) -> Analysis
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        if opts.rotate != Rotation::None {
+            let rotated = opts.rotate.apply(thumbnail);
+            let (original_width, original_height) = match opts.rotate {
+                Rotation::Cw90 | Rotation::Cw270 => (original_height, original_width),
+                _ => (original_width, original_height),
+            };
+            let opts = SmartCrop {
+                rotate: Rotation::None,
+                ..opts.clone()
+            };
+            return self.prepare_thumbnail(&rotated, original_width, original_height, &opts);
+        }
+        if let Some(background) = self.composite_background {
+            let sc = SmartCrop {
+                composite_background: None,
+                ..self.clone()
+            };
+            let composited = composite(thumbnail, background);
+            return sc.prepare_thumbnail(&composited, original_width, original_height, opts);
+        }
+        let prescale = thumbnail.width() as f64 / original_width as f64;
+        let working = self.working_options(original_width, original_height, opts, prescale);
+        let mut analysis = working.detect(thumbnail);
+        analysis.prescale = prescale;
+        analysis.width = original_width;
+        analysis.height = original_height;
+        analysis
+    }
+
+    /// Crops an `original_width` x `original_height` image using only its
+    /// `thumbnail`; see `prepare_thumbnail`.
+    pub fn crop_thumbnail<I>(
+        &self,
+        thumbnail: &I,
+        original_width: u32,
+        original_height: u32,
+        opts: &SmartCrop,
+    ) -> CropResult
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        let analysis = self.prepare_thumbnail(thumbnail, original_width, original_height, opts);
+        self.rescore(&analysis, opts)
+    }
+
+    /// Crops a planar YUV 4:2:0 (I420) video frame of `width` x `height`
+    /// without converting it to RGB first. `strides` are the row lengths in
+    /// bytes of the Y plane and of the U and V planes; the samples are taken
+    /// as BT.601 video range. Fails with `InvalidFrame` when a plane is too
+    /// short for the frame's size and strides.
+    #[allow(clippy::too_many_arguments)]
+    pub fn crop_yuv420(
+        &self,
+        y_plane: &[u8],
+        u_plane: &[u8],
+        v_plane: &[u8],
+        strides: (usize, usize),
+        width: u32,
+        height: u32,
+        opts: &SmartCrop,
+    ) -> Result<CropResult, SmartCropError> {
+        let frame = Yuv420 {
+            y: y_plane,
+            u: u_plane,
+            v: v_plane,
+            y_stride: strides.0,
+            uv_stride: strides.1,
+            uv_step: 1,
+            width,
+            height,
+        }
+        .validated()?;
+        Ok(self.crop_image(&frame, opts))
+    }
+
+    /// Same as `crop_yuv420` for an NV12 frame, whose U and V samples are
+    /// interleaved in a single plane.
+    pub fn crop_nv12(
+        &self,
+        y_plane: &[u8],
+        uv_plane: &[u8],
+        strides: (usize, usize),
+        width: u32,
+        height: u32,
+        opts: &SmartCrop,
+    ) -> Result<CropResult, SmartCropError> {
+        let frame = Yuv420 {
+            y: y_plane,
+            u: uv_plane,
+            v: uv_plane.get(1..).unwrap_or(&[]),
+            y_stride: strides.0,
+            uv_stride: strides.1,
+            uv_step: 2,
+            width,
+            height,
+        }
+        .validated()?;
+        Ok(self.crop_image(&frame, opts))
+    }
+
+    /// Same as `prepare` with `stable_resize`, but decodes the image at `path`
+    /// one strip at a time and prescales it on the fly, so that the full
+    /// resolution image is never held in memory. 8-bit TIFF and non-interlaced
+    /// PNG files are streamed; other images are decoded in full.
+    pub fn prepare_tiled(&self, path: &Path, opts: &SmartCrop) -> Result<Analysis, SmartCropError> {
+        // a rotated image can't be streamed top to bottom, and compositing is
+        // left to prepare
+        let streamable = opts.rotate == Rotation::None
+            && self.composite_background.is_none()
+            && self.band_mapping.is_none();
+        let rows = match open_rows(path)?.filter(|_| streamable) {
+            Some(rows) => rows,
+            None => {
+                let img = load_image(path, self.lenient_decode, self.band_mapping)?;
+                let sc = SmartCrop {
+                    stable_resize: true,
+                    ..self.clone()
+                };
+                return Ok(sc.prepare(&img, opts));
+            }
+        };
+        let RowReader {
+            mut reader,
+            width: img_width,
+            height: img_height,
+            channels,
+        } = rows;
+
+        let (working, prescale) = self.prepare_options(img_width, img_height, opts);
+        let (width, height) = if prescale < 1. {
+            prescaled_dimensions(img_width, img_height, prescale)
+        } else {
+            (img_width, img_height)
+        };
+        let mut resizer = RowResizer::new(img_width, img_height, width, height);
+        let mut samples = vec![0; img_width as usize * channels];
+        let mut row = vec![Rgba([0, 0, 0, 255]); img_width as usize];
+        for y in 0..img_height {
+            reader.read_exact(&mut samples)?;
+            for (pixel, s) in row.iter_mut().zip(samples.chunks_exact(channels)) {
+                *pixel = match *s {
+                    [l] => Rgba([l, l, l, 255]),
+                    [l, a] => Rgba([l, l, l, a]),
+                    [r, g, b] => Rgba([r, g, b, 255]),
+                    [r, g, b, a] => Rgba([r, g, b, a]),
+                    _ => unreachable!(),
+                };
+            }
+            resizer.push_row(y, &row);
+        }
+        drop(reader);
+
+        if prescale < 1. {
+            self.save_debug_thumb(&resizer.output);
+        }
+        let mut analysis = working.detect(&resizer.output);
+        analysis.prescale = prescale;
+        analysis.width = img_width;
+        analysis.height = img_height;
+        Ok(analysis)
+    }
+
+    /// Same as `crop`, using `prepare_tiled` to bound memory use on very
+    /// large images.
+    pub fn crop_tiled(&self, path: &Path, opts: &SmartCrop) -> Result<CropResult, SmartCropError> {
+        let analysis = self.prepare_tiled(path, opts)?;
+        checked(self.rescore(&analysis, opts))
+    }
+
+    fn save_debug_thumb(&self, resized: &RgbaImage) {
+        if self.debug {
+            //let ref mut fout = File::create(&Path::new("debug.thumb.jpg")).unwrap();
+            let thumb = ImageRgba8(resized.clone()).to_rgb8();
+            let _ = thumb.save_with_format("debug.thumb.jpg", image::ImageFormat::Jpeg);
+        }
+    }
+
+    /// Scores the candidates for the target in `opts` using the weights of
+    /// `self`, without running detection again. `opts` is expected to describe
+    /// the same target as the one passed to `prepare`. Skin and saturation
+    /// aren't detected when `prepare` ran with both of their weights at zero,
+    /// so such an analysis only supports rescoring with detail.
+    pub fn rescore(&self, analysis: &Analysis, opts: &SmartCrop) -> CropResult {
+        self.rescore_crops(analysis, opts, None)
+    }
+
+    /// The best crop at each scale searched for `opts`, largest scale first,
+    /// to show how the winner and its score change with the crop size when
+    /// tuning `min_scale` and `max_scale`.
+    pub fn best_per_scale(&self, analysis: &Analysis, opts: &SmartCrop) -> Vec<(f64, CropInfo)> {
+        let working = self.scoring_options(analysis, opts);
+        let (width, height) = analysis.output.dimensions();
+        let scales = scales(working.min_scale, working.max_scale, working.scale_step);
+        // like crops(), a rectangle belongs to the largest scale producing it
+        let mut scale_of = HashMap::new();
+        for (i, &scale) in scales.iter().enumerate() {
+            let at_scale = SmartCrop {
+                min_scale: scale,
+                max_scale: scale,
+                ..working.clone()
+            };
+            for crop in at_scale.crops(width, height) {
+                scale_of.entry(crop.size).or_insert(i);
+            }
+        }
+        let anchor = self.anchor;
+        let unscaled = |size: &CropSize| self.unscaled(analysis, size, anchor);
+        let scored = working.score_crops(analysis, working.crops(width, height), &unscaled);
+        let mut best: Vec<Option<&CropInfo>> = vec![None; scales.len()];
+        for crop in scored.crops.iter() {
+            if let Some(&i) = scale_of.get(&crop.size) {
+                let value = working.objective(analysis, crop);
+                if best[i].is_none_or(|top| value > working.objective(analysis, top)) {
+                    best[i] = Some(crop);
+                }
+            }
+        }
+        scales
+            .iter()
+            .zip(best)
+            .filter_map(|(&scale, crop)| {
+                let crop = crop?;
+                let mut size = unscaled(&crop.size);
+                if self.coordinate_origin == CoordinateOrigin::BottomLeft {
+                    size = size.flip_y(analysis.height);
+                }
+                Some((
+                    scale,
+                    CropInfo {
+                        size,
+                        score: crop.score.clone(),
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    /// How much cropping smartly beats a plain center crop for the target in
+    /// `opts`, from 0 (the center crop scores as well as the best one, e.g. for
+    /// uniform images or centered subjects) to 1 (the center crop scores
+    /// nothing). Bulk pipelines can skip analysis-driven crops below a threshold.
+    pub fn crop_confidence(&self, analysis: &Analysis, opts: &SmartCrop) -> f64 {
+        let result = self.rescore(analysis, opts);
+        let mut working =
+            self.working_options(analysis.width, analysis.height, opts, analysis.prescale);
+        working.score_down_sample = analysis.score_down_sample;
+        let (w, h) = analysis.output.dimensions();
+        let top = &result.prescaled_crop;
+        let center = CropSize {
+            x: (w - top.width.min(w)) / 2,
+            y: (h - top.height.min(h)) / 2,
+            width: top.width,
+            height: top.height,
+        };
+        let top_total = result.top_crop.score.total;
+        let peak = working.negative_space_peak(analysis);
+        let center_total = working.get_score(&analysis.score_map, &center).total
+            + working.composition_score(analysis, &center, peak);
+        if top_total <= 0. {
+            return 0.;
+        }
+        (1. - center_total / top_total).clamp(0., 1.)
+    }
+
+    /// Finds the best crop among the candidates meeting every constraint in
+    /// `constraints`, which take the place of `self.constraints`. Fails with
+    /// `ConstraintsUnmet` when none does.
+    pub fn crop_constrained<I>(
+        &self,
+        img: &I,
+        constraints: &Constraints,
+        opts: &SmartCrop,
+    ) -> Result<CropResult, SmartCropError>
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        let sc = SmartCrop {
+            constraints: constraints.clone(),
+            ..self.clone()
+        };
+        checked(sc.crop_image(img, opts))
+    }
+
+    /// Finds the best crop at the target aspect that contains every box in
+    /// `boxes` (in image coordinates), e.g. all detected objects. Crops down
+    /// to the smallest that can hold the boxes' bounding box are searched and
+    /// ranked by the usual score, along with the rest of `constraints`, whose
+    /// `must_contain` boxes are kept. Fails with `ConstraintsUnmet` when no
+    /// crop at the target aspect holds them all.
+    pub fn crop_containing<I>(
+        &self,
+        img: &I,
+        boxes: &[CropSize],
+        opts: &SmartCrop,
+    ) -> Result<CropResult, SmartCropError>
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        let union = match boxes.split_first() {
+            Some((first, rest)) => rest.iter().fold(first.clone(), |union, b| union.union(b)),
+            None => return checked(self.crop_image(img, opts)),
+        };
+        let (width, height) = img.dimensions();
+        let full = self.working_options(width, height, opts, 1.);
+        let needed = f64::max(
+            union.width as f64 / full.crop_width as f64,
+            union.height as f64 / full.crop_height as f64,
+        );
+        let mut constraints = self.constraints.clone();
+        constraints.must_contain.push(union);
+        let sc = SmartCrop {
+            min_scale: f64::min(needed, self.max_scale),
+            constraints,
+            ..self.clone()
+        };
+        checked(sc.crop_image(img, opts))
+    }
+
+    /// Scores exactly the rectangles in `candidates` (in image coordinates)
+    /// instead of searching the grid, e.g. to rank externally generated
+    /// region proposals, and returns them with the best one as `top_crop`.
+    pub fn best_among(
+        &self,
+        img: &image::DynamicImage,
+        candidates: &[CropSize],
+        opts: &SmartCrop,
+    ) -> Result<CropResult, SmartCropError> {
+        if candidates.is_empty() {
+            return Err(SmartCropError::NoCandidates);
+        }
+        let (width, height) = img.dimensions();
+        if let Some(size) = candidates.iter().find(|size| {
+            size.width == 0
+                || size.height == 0
+                || size.x as u64 + size.width as u64 > width as u64
+                || size.y as u64 + size.height as u64 > height as u64
+        }) {
+            return Err(SmartCropError::InvalidCandidate(size.clone()));
+        }
+        let analysis = self.prepare(img, opts);
+        checked(self.rescore_crops(&analysis, opts, Some(candidates)))
+    }
+
+    // rescore, over `candidates` instead of the grid when given
+    fn rescore_crops(
+        &self,
+        analysis: &Analysis,
+        opts: &SmartCrop,
+        candidates: Option<&[CropSize]>,
+    ) -> CropResult {
+        let working = self.scoring_options(analysis, opts);
+        let crops = match candidates {
+            Some(candidates) => candidates
+                .iter()
+                .map(|size| CropInfo {
+                    size: size.scale(analysis.prescale),
+                    score: CropScore::default(),
+                })
+                .collect(),
+            None => {
+                let (width, height) = analysis.output.dimensions();
+                working.crops(width, height)
+            }
+        };
+        if let (Some(previous), None) = (&working.warm_start, candidates) {
+            let (radius_x, radius_y) = (
+                previous.width as f64 * WARM_START_RADIUS,
+                previous.height as f64 * WARM_START_RADIUS,
+            );
+            let offset = |a: u32, b: u32| (a as f64 - b as f64).abs();
+            let near = |size: &CropSize, margin_x: f64, margin_y: f64| {
+                offset(size.x, previous.x) + margin_x <= radius_x
+                    && offset(size.y, previous.y) + margin_y <= radius_y
+                    && offset(size.width, previous.width) <= radius_x
+                    && offset(size.height, previous.height) <= radius_y
+            };
+            let local: Vec<CropInfo> = crops
+                .iter()
+                .filter(|crop| near(&crop.size, 0., 0.))
+                .cloned()
+                .collect();
+            if !local.is_empty() {
+                let result = self.score_working(analysis, opts, &working, local, None);
+                // a step further out could still be better, and requirements
+                // the neighborhood can't meet may be met elsewhere
+                let (step_x, step_y) = working.steps();
+                let fell_back = result.constraints_unmet || result.saliency_coverage_fallback;
+                if !fell_back && near(&result.prescaled_crop, step_x as f64, step_y as f64) {
+                    return result;
+                }
+            }
+        }
+        self.score_working(analysis, opts, &working, crops, candidates)
+    }
+
+    // the working options scoring `analysis` for the target in `opts`
+    fn scoring_options(&self, analysis: &Analysis, opts: &SmartCrop) -> SmartCrop {
+        let mut working =
+            self.working_options(analysis.width, analysis.height, opts, analysis.prescale);
+        working.score_down_sample = analysis.score_down_sample;
+        working
+    }
+
+    // scores `crops` (in working coordinates) with `working` and brings the
+    // result back to image coordinates; `candidates` are the rectangles the
+    // crops were made from, if the caller gave them
+    fn score_working(
+        &self,
+        analysis: &Analysis,
+        opts: &SmartCrop,
+        working: &SmartCrop,
+        crops: Vec<CropInfo>,
+        candidates: Option<&[CropSize]>,
+    ) -> CropResult {
+        let prescale = analysis.prescale;
+        let anchor = self.anchor.filter(|_| candidates.is_none());
+        let unscaled = |size: &CropSize| self.unscaled(analysis, size, anchor);
+        let mut result = working.score_crops(analysis, crops, &unscaled);
+
+        result.prescale = prescale;
+        let (width, height) = (analysis.width, analysis.height);
+        for crop in result.crops.iter_mut() {
+            crop.size = unscaled(&crop.size);
+        }
+        result.top_crop.size = unscaled(&result.top_crop.size);
+        result.padding = result.padding.unscale(prescale);
+        result.image_width = width;
+        result.image_height = height;
+        if let Some(candidates) = candidates.filter(|_| working.mode != CropMode::Fit) {
+            // report the rectangles as given rather than their round trip
+            // through the working image
+            let best = result
+                .crops
+                .iter()
+                .position(|crop| crop.size == result.top_crop.size);
+            for (crop, size) in result.crops.iter_mut().zip(candidates) {
+                crop.size = size.clone();
+            }
+            if let Some(best) = best {
+                result.top_crop.size = candidates[best].clone();
+            }
+        }
+        let size = &result.top_crop.size;
+        if size.height > 0 {
+            result.aspect = size.width as f64 / size.height as f64;
+        }
+        if opts.width != 0 && opts.height != 0 {
+            let size = &result.top_crop.size;
+            let factor = f64::max(
+                opts.width as f64 / size.width as f64,
+                opts.height as f64 / size.height as f64,
+            );
+            result.upscaled = factor > 1.;
+            result.upscale_factor = f64::max(factor, 1.);
+        }
+        if self.coordinate_origin == CoordinateOrigin::BottomLeft {
+            for crop in result.crops.iter_mut() {
+                crop.size = crop.size.flip_y(height);
+            }
+            result.top_crop.size = result.top_crop.size.flip_y(height);
+            result.coordinate_origin = CoordinateOrigin::BottomLeft;
+        }
+
+        result
+    }
+
+    // `size` in the working coordinates of `analysis` brought back to image
+    // coordinates; unscaling can move an anchored crop by a few pixels, so
+    // `anchor` is placed again at full resolution
+    fn unscaled(
+        &self,
+        analysis: &Analysis,
+        size: &CropSize,
+        anchor: Option<(u32, u32, f64, f64)>,
+    ) -> CropSize {
+        let (width, height) = (analysis.width, analysis.height);
+        let size = size.unscale(analysis.prescale).clamp(width, height);
+        self.align(match anchor {
+            Some((x, y, rel_x, rel_y)) => CropSize {
+                x: anchored(x as f64, rel_x, size.width, width),
+                y: anchored(y as f64, rel_y, size.height, height),
+                ..size
+            },
+            None => size,
+        })
+    }
+
+    /// Weighted centroid of the combined detection map of `analysis`, in original
+    /// image coordinates. Falls back to the image center when nothing is salient.
+    pub fn saliency_centroid(&self, analysis: &Analysis) -> (f64, f64) {
+        let (working_width, working_height) = analysis.output.dimensions();
+        let (score_width, score_height) = analysis.score_map.dimensions();
+        let scale_x = working_width as f64 / score_width as f64 / analysis.prescale;
+        let scale_y = working_height as f64 / score_height as f64 / analysis.prescale;
+
+        let (mut sum, mut sum_x, mut sum_y) = (0., 0., 0.);
+        for (x, y, pixel) in analysis.score_map.pixels() {
+            let saliency = f64::max(self.saliency(pixel), 0.);
+            sum += saliency;
+            sum_x += saliency * (x as f64 + 0.5) * scale_x;
+            sum_y += saliency * (y as f64 + 0.5) * scale_y;
+        }
+        if sum == 0. {
+            return (analysis.width as f64 / 2., analysis.height as f64 / 2.);
+        }
+        (sum_x / sum, sum_y / sum)
+    }
+
+    /// Average combined detection saliency (as weighted by `self`) of each
+    /// tile of a `cols` x `rows` grid over the image, indexed `[row][col]`,
+    /// as a compact summary of where the interesting content is. The grid is
+    /// empty when `cols` or `rows` is zero.
+    pub fn saliency_grid(&self, analysis: &Analysis, cols: u32, rows: u32) -> Vec<Vec<f64>> {
+        let (width, height) = analysis.output.dimensions();
+        let (cols, rows) = (cols.min(width), rows.min(height));
+        if cols == 0 || rows == 0 {
+            return Vec::new();
+        }
+        let mut sums = vec![vec![0.; cols as usize]; rows as usize];
+        let mut counts = vec![vec![0u32; cols as usize]; rows as usize];
+        for (x, y, pixel) in analysis.output.enumerate_pixels() {
+            let (col, row) = (
+                (x as u64 * cols as u64 / width as u64) as usize,
+                (y as u64 * rows as u64 / height as u64) as usize,
+            );
+            let saliency = self.saliency(Rgba([pixel[0], pixel[1], pixel[2], 255]));
+            sums[row][col] += f64::max(saliency, 0.);
+            counts[row][col] += 1;
+        }
+        for (sums, counts) in sums.iter_mut().zip(counts) {
+            for (sum, count) in sums.iter_mut().zip(counts) {
+                *sum /= f64::max(count as f64, 1.);
+            }
+        }
+        sums
+    }
+
+    /// Renders the scoring landscape of `analysis` at crop scale `scale` for
+    /// debugging: each pixel of the prescaled image is as bright as the total
+    /// score of the candidate whose top left corner it is, from black for the
+    /// worst to white for the best. Origins where the crop doesn't fit are black.
+    pub fn score_surface(
+        &self,
+        analysis: &Analysis,
+        opts: &SmartCrop,
+        scale: f64,
+    ) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+        let mut working =
+            self.working_options(analysis.width, analysis.height, opts, analysis.prescale);
+        working.score_down_sample = analysis.score_down_sample;
+        let (w, h) = analysis.output.dimensions();
+        let (crop_width, crop_height) = working.crop_dimensions(w, h);
+        let (crop_width, crop_height) = (
+            (crop_width as f64 * scale) as u32,
+            (crop_height as f64 * scale) as u32,
+        );
+
+        let peak = working.negative_space_peak(analysis);
+        let mut totals = vec![None; (w * h) as usize];
+        for y in 0..(h + 1).saturating_sub(crop_height) {
+            for x in 0..(w + 1).saturating_sub(crop_width) {
+                let size = CropSize {
+                    x,
+                    y,
+                    width: crop_width,
+                    height: crop_height,
+                };
+                let total = working.get_score(&analysis.score_map, &size).total
+                    + working.composition_score(analysis, &size, peak);
+                totals[(y * w + x) as usize] = Some(total);
+            }
+        }
+
+        let (min, max) = totals
+            .iter()
+            .flatten()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &total| {
+                (min.min(total), max.max(total))
+            });
+        let range = if max > min { max - min } else { 1. };
+        ImageBuffer::from_fn(w, h, |x, y| match totals[(y * w + x) as usize] {
+            Some(total) => Luma([((total - min) / range * 255.).round() as u8]),
+            None => Luma([0]),
+        })
+    }
+
+    /// Draws the top crop of `result` onto a copy of `img` in red, along with the
+    /// next `annotate_top_n` candidates in fading yellow, for visual review.
+    pub fn annotate(
+        &self,
+        img: &image::DynamicImage,
+        result: &CropResult,
+    ) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+        let mut output = img.to_rgb8();
+        let mut draw = |size: &CropSize, color: Rgb<u8>| {
+            draw_outline(&mut output, &result.top_left(size), color);
+        };
+
+        let n = self.annotate_top_n;
+        let alternatives = result
+            .ranked()
+            .into_iter()
+            .filter(|crop| crop.size != result.top_crop.size)
+            .take(n)
+            .collect::<Vec<_>>();
+        // draw the weakest first so that better candidates end up on top
+        for (i, crop) in alternatives.iter().enumerate().rev() {
+            let fade = 1. - i as f64 / (n + 1) as f64;
+            draw(
+                &crop.size,
+                Rgb([(255. * fade) as u8, (255. * fade) as u8, 0]),
+            );
+        }
+        draw(&result.top_crop.size, Rgb([255, 0, 0]));
+        output
+    }
+
+    /// Describes the candidate search `crop` would run for an image of
+    /// `img_w`x`img_h`, without running detection.
+    pub fn search_plan(&self, img_w: u32, img_h: u32, opts: &SmartCrop) -> SearchPlan {
+        let (working, prescale) = self.prepare_options(img_w, img_h, opts);
+        let (width, height) = prescaled_dimensions(img_w, img_h, prescale);
+        let (crop_width, crop_height) = working.fitted_crop_dimensions(width, height);
+        let (step_x, step_y) = working.adaptive_steps(width, height);
+        SearchPlan {
+            prescale,
+            scales: scales(working.min_scale, working.max_scale, working.scale_step),
+            step_x,
+            step_y,
+            crop_width,
+            crop_height,
+            candidates: working.crops(width, height).len(),
+        }
+    }
+
+    /// Returns the candidate rectangles that `crop` would score for an image of
+    /// `img_w`x`img_h`, in original image coordinates, without running detection.
+    pub fn candidate_rects(&self, img_w: u32, img_h: u32, opts: &SmartCrop) -> Vec<CropSize> {
+        let (working, prescale) = self.prepare_options(img_w, img_h, opts);
+        let (width, height) = prescaled_dimensions(img_w, img_h, prescale);
+        working
+            .crops(width, height)
+            .iter()
+            .map(|crop| self.align(crop.size.unscale(prescale)))
+            .collect()
+    }
+
+    // Works out the prescale factor for an image of the given size and returns it
+    // along with a copy of self carrying the crop size in prescaled coordinates.
+    fn prepare_options(
+        &self,
+        img_width: u32,
+        img_height: u32,
+        opts: &SmartCrop,
+    ) -> (SmartCrop, f64) {
+        let mut prescale = 1.;
+        if opts.width != 0 && opts.height != 0 && opts.prescale {
+            let scale = f64::min(
+                img_width as f64 / opts.width as f64,
+                img_height as f64 / opts.height as f64,
+            );
+            // shrink the image so that the smallest crop searched ends up at
+            // the target size
+            let min_scale = self
+                .working_options(img_width, img_height, opts, 1.)
+                .min_scale;
+            prescale = f64::min(1. / scale / min_scale, 1.);
+        }
+
+        (
+            self.working_options(img_width, img_height, opts, prescale),
+            prescale,
+        )
+    }
+
+    // Copy of self carrying the crop size for the target in opts, in
+    // coordinates prescaled by `prescale`.
+    fn working_options(
+        &self,
+        img_width: u32,
+        img_height: u32,
+        opts: &SmartCrop,
+        prescale: f64,
+    ) -> SmartCrop {
+        let mut working = (*self).clone();
+        let (crop_width, crop_height) = if opts.width != 0 && opts.height != 0 {
+            let scale = f64::min(
+                img_width as f64 / opts.width as f64,
+                img_height as f64 / opts.height as f64,
+            );
+            // img = 100x100, width = 95x95, scale = 100/95, 1/scale > min
+            // don't set minscale smaller than 1/scale
+            // -> don't pick crops that need upscaling
+            if !opts.allow_upscale {
+                working.min_scale =
+                    f64::min(working.max_scale, f64::max(1. / scale, working.min_scale));
+            }
+            (
+                f64::floor(opts.width as f64 * scale),
+                f64::floor(opts.height as f64 * scale),
+            )
+        } else {
+            (opts.crop_width as f64, opts.crop_height as f64)
+        };
+        working.crop_width = f64::floor(crop_width * prescale) as i32;
+        working.crop_height = f64::floor(crop_height * prescale) as i32;
+        working.faces = self.faces.iter().map(|face| face.scale(prescale)).collect();
+        working.depth_map = self.depth_map.as_ref().map(|depth| {
+            let (width, height) = prescaled_dimensions(img_width, img_height, prescale);
+            stretch_depth(&image::imageops::resize(
+                depth,
+                width,
+                height,
+                image::imageops::FilterType::Triangle,
+            ))
+        });
+        working.warm_start = self.warm_start.as_ref().map(|size| size.scale(prescale));
+        working.anchor = self.anchor.map(|(x, y, rel_x, rel_y)| {
+            (
+                (x as f64 * prescale) as u32,
+                (y as f64 * prescale) as u32,
+                rel_x,
+                rel_y,
+            )
+        });
+        working.boosts = self
+            .boosts
+            .iter()
+            .chain(self.face_boosts().iter())
+            .map(|boost| boost.scale(prescale))
+            .collect();
+        working
+    }
+
+    fn detect_edge<I>(&self, img: &I, output: &mut ImageBuffer<Rgb<u8>, Vec<u8>>)
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        let (w, h) = img.dimensions();
+        let sample = if self.linear_edges {
+            linear_sample
+        } else {
+            sample
+        };
+        let at = |x: u32, y: u32| sample(img.get_pixel(x, y));
+        for (x, y, output_pixel) in output.enumerate_pixels_mut() {
+            let pixel = img.get_pixel(x, y);
+            let lightness = self.edge(at, x, y, w, h);
+            *output_pixel = Rgb([pixel[0], lightness as u8, pixel[2]]);
+        }
+        if self.debug {
+            let _ = output.save("edge.jpg");
+        }
+    }
+
+    // edge strength (0.0 - 255.0) at x, y of a w x h image whose lightness is
+    // `at`; pixels on the border keep their lightness
+    fn edge<F>(&self, at: F, x: u32, y: u32, w: u32, h: u32) -> f64
+    where
+        F: Fn(u32, u32) -> f64,
+    {
+        let lightness = if x == 0 || x >= w - 1 || y == 0 || y >= h - 1 {
+            at(x, y)
+        } else {
+            match self.edge_operator {
+                EdgeOperator::Laplacian => {
+                    at(x, y) * 4. - at(x - 1, y) - at(x, y - 1) - at(x, y + 1) - at(x + 1, y)
+                }
+                EdgeOperator::Sobel => {
+                    let gx = at(x + 1, y - 1) + 2. * at(x + 1, y) + at(x + 1, y + 1)
+                        - at(x - 1, y - 1)
+                        - 2. * at(x - 1, y)
+                        - at(x - 1, y + 1);
+                    let gy = at(x - 1, y + 1) + 2. * at(x, y + 1) + at(x + 1, y + 1)
+                        - at(x - 1, y - 1)
+                        - 2. * at(x, y - 1)
+                        - at(x + 1, y - 1);
+                    f64::sqrt(gx * gx + gy * gy) / 4.
+                }
+            }
+        };
+        lightness.clamp(0., 255.)
+    }
+
+    // detect_edge on precomputed lightness samples, row by row
+    fn detect_edge_luma(&self, luma: &[f64], output: &mut ImageBuffer<Rgb<u8>, Vec<u8>>) {
+        let (w, h) = output.dimensions();
+        let at = |x: u32, y: u32| luma[(y * w + x) as usize];
+        for (x, y, output_pixel) in output.enumerate_pixels_mut() {
+            let lightness = self.edge(at, x, y, w, h);
+            *output_pixel = Rgb([0, lightness as u8, 0]);
+        }
+        if self.debug {
+            let _ = output.save("edge.jpg");
+        }
+    }
+
+    fn detect_skin<I>(&self, img: &I, output: &mut ImageBuffer<Rgb<u8>, Vec<u8>>)
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        let block = self.skin_subsample.max(1);
+        if block == 1 {
+            for (x, y, output_pixel) in output.enumerate_pixels_mut() {
+                output_pixel[0] = self.skin_response(img.get_pixel(x, y));
+            }
+        } else {
+            let (width, height) = output.dimensions();
+            for block_y in (0..height).step_by(block as usize) {
+                for block_x in (0..width).step_by(block as usize) {
+                    let (right, bottom) =
+                        ((block_x + block).min(width), (block_y + block).min(height));
+                    let r = self.skin_response(
+                        img.get_pixel((block_x + right) / 2, (block_y + bottom) / 2),
+                    );
+                    for y in block_y..bottom {
+                        for x in block_x..right {
+                            output.get_pixel_mut(x, y)[0] = r;
+                        }
+                    }
+                }
+            }
+        }
+        if self.skin_min_region > 0 {
+            suppress_small_regions(output, 0, self.skin_min_region);
+        }
+        if self.debug {
+            let _ = output.save("skin.jpg");
+        }
+    }
+
+    // skin channel value for one pixel
+    fn skin_response(&self, pixel: Rgba<u8>) -> u8 {
+        let lightness = self.gate_lightness(pixel);
+        if lightness < self.brightness_min(self.skin_brightness_min)
+            || lightness > self.skin_brightness_max
+        {
+            return 0;
+        }
+        let mut tr = self.skin_confidence(self.get_skin_color(pixel));
+        if tr > 0. && !skin_plausible(pixel) {
+            tr *= 1. - self.skin_strictness;
+        }
+        tr.clamp(0., 255.) as u8
+    }
+
+    // skin response (0.0 - 255.0) to a skin color similarity, a ramp from
+    // skin_threshold up or with skin_softness the mean of SKIN_PASSES ramps
+    fn skin_confidence(&self, skin: f64) -> f64 {
+        let ramp = |threshold: f64| {
+            if skin > threshold && threshold < 1. {
+                (skin - threshold) * (255. / (1. - threshold))
+            } else {
+                0.
+            }
+        };
+        if self.skin_softness <= 0. {
+            return ramp(self.skin_threshold);
+        }
+        (0..SKIN_PASSES)
+            .map(|i| {
+                let offset = i as f64 / (SKIN_PASSES - 1) as f64 * 2. - 1.;
+                ramp(self.skin_threshold + offset * self.skin_softness)
+            })
+            .sum::<f64>()
+            / SKIN_PASSES as f64
+    }
+
+    // lightness (0.0 - 1.0) the brightness gates compare with, independent
+    // of linear_edges
+    fn gate_lightness(&self, pixel: Rgba<u8>) -> f64 {
+        if self.linear_brightness_gates {
+            linear_sample(pixel) / 255.
+        } else {
+            sample(pixel) / 255.
+        }
+    }
+
+    // lowest lightness skin and saturation are detected at, lowered for low_key
+    fn brightness_min(&self, min: f64) -> f64 {
+        if self.low_key {
+            min / 4.
+        } else {
+            min
+        }
+    }
+
+    fn detect_saturation<I>(&self, img: &I, output: &mut ImageBuffer<Rgb<u8>, Vec<u8>>)
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        let (width, height) = output.dimensions();
+        let sats: Vec<f64> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| saturation(img.get_pixel(x, y)))
+            .collect();
+        let local_means = if self.local_contrast_radius > 0 {
+            Some(box_mean(&sats, width, height, self.local_contrast_radius))
+        } else {
+            None
+        };
+        for (x, y, output_pixel) in output.enumerate_pixels_mut() {
+            let pixel = img.get_pixel(x, y);
+            let lightness = self.gate_lightness(pixel);
+            let i = (y * width + x) as usize;
+            let sat = match &local_means {
+                // how many times more saturated than the neighborhood: 0 for
+                // the same as around, 1 for three times as much
+                Some(means) => ((sats[i] / means[i].max(0.05) - 1.) / 2.).clamp(0., 1.),
+                None => sats[i],
+            };
+            let b: u8 = if sat > self.saturation_threshold
+                && lightness >= self.brightness_min(self.saturation_brightness_min)
+                && lightness <= self.saturation_brightness_max
+            {
+                let mut tr =
+                    (sat - self.saturation_threshold) * (255. / (1. - self.saturation_threshold));
+                tr = tr.clamp(0., 255.);
+                tr as u8
+            } else {
+                0
+            };
+            *output_pixel = Rgb([output_pixel[0], output_pixel[1], b]);
+        }
+        if self.debug {
+            let _ = output.save("sat.jpg");
+        }
+    }
+
+    fn get_skin_color(&self, pixel: Rgba<u8>) -> f64 {
+        let r = pixel[0] as f64;
+        let g = pixel[1] as f64;
+        let b = pixel[2] as f64;
+        let (rt, gt, bt) = self.skin_color;
+        let mag = f64::sqrt(r * r + g * g + b * b);
+        let (rd, gd, bd) = if mag == 0. {
+            (-rt, -gt, -bt)
+        } else {
+            (r / mag - rt, g / mag - gt, b / mag - bt)
+        };
+
+        1. - f64::sqrt(rd * rd + gd * gd + bd * bd)
+    }
+
+    // detail, skin, saturation and boost weights; boost_weight only counts
+    // towards normalization when there is something to boost
+    fn weights(&self) -> (f64, f64, f64, f64) {
+        let weights = (
+            self.detail_weight,
+            self.skin_weight,
+            self.saturation_weight,
+            self.boost_weight,
+        );
+        let boosted = if self.boosts.is_empty() {
+            0.
+        } else {
+            weights.3
+        };
+        let sum = weights.0 + weights.1 + weights.2 + boosted;
+        if !self.normalize_weights || sum == 0. {
+            return weights;
+        }
+        (
+            weights.0 / sum,
+            weights.1 / sum,
+            weights.2 / sum,
+            weights.3 / sum,
+        )
+    }
+
+    // Boosts for the face boxes. With face_size_weight 0 every face pulls
+    // equally hard, however large; at 1 the pull grows with the face's area,
+    // and beyond that larger faces dominate even more.
+    fn face_boosts(&self) -> Vec<Boost> {
+        let pulls: Vec<f64> = self
+            .faces
+            .iter()
+            .map(|face| {
+                let area = f64::max(face.width as f64 * face.height as f64, 1.);
+                area.powf(self.face_size_weight - 1.)
+            })
+            .collect();
+        let strongest = pulls.iter().cloned().fold(0., f64::max);
+        self.faces
+            .iter()
+            .zip(pulls)
+            .map(|(face, pull)| Boost {
+                x: face.x,
+                y: face.y,
+                width: face.width,
+                height: face.height,
+                weight: pull / strongest,
+            })
+            .collect()
+    }
+
+    // combined boost at working pixel (x, y), capped like an 8-bit channel
+    fn boost(&self, x: u64, y: u64) -> f64 {
+        let boost: f64 = self
+            .boosts
+            .iter()
+            .filter(|boost| boost.contains(x, y))
+            .map(|boost| boost.weight)
+            .sum();
+        boost.clamp(0., 1.)
+    }
+
+    // skin inside a face box is strong evidence of a face and is amplified,
+    // skin elsewhere may just be skin colored and is attenuated
+    fn skin_factor(&self, x: u64, y: u64) -> f64 {
+        if self.faces.is_empty() {
+            1.
+        } else if self.faces.iter().any(|face| face.contains_sample(x, y)) {
+            self.face_skin_factor
+        } else {
+            self.off_face_skin_factor
+        }
+    }
+
+    // importance factor of depth_map at working x, y, with the map already
+    // resized to the working image and stretched by working_options
+    fn depth_factor(&self, x: u64, y: u64) -> f64 {
+        match &self.depth_map {
+            Some(depth) if depth.width() > 0 && depth.height() > 0 => {
+                let x = x.min(depth.width() as u64 - 1) as u32;
+                let y = y.min(depth.height() as u64 - 1) as u32;
+                let far = depth.get_pixel(x, y)[0] as f64 / u16::MAX as f64;
+                (1. + self.depth_weight * (1. - 2. * far)).max(0.)
+            }
+            _ => 1.,
+        }
+    }
+
+    fn importance(&self, crop: &CropSize, x: u64, y: u64) -> f64 {
+        let (crop_x, crop_y) = (crop.x as u64, crop.y as u64);
+        let (crop_width, crop_height) = (crop.width as u64, crop.height as u64);
+        if crop_x > x || x >= crop_x + crop_width || crop_y > y || y >= crop_y + crop_height {
+            return self.outside_importance;
+        }
+        let tx = (x - crop_x) as f64 / crop_width as f64;
+        let ty = (y - crop_y) as f64 / crop_height as f64;
+        if self.overlay_zones.iter().any(|zone| zone.contains(tx, ty)) {
+            // content under an overlay is as good as cropped away
+            return self.outside_importance;
+        }
+        let px = (0.5 - tx).abs() * 2.;
+        let py = (0.5 - ty).abs() * 2.;
+        // distance from edge
+        let (dx, dy) = match self.output_shape {
+            OutputShape::Rect => (
+                f64::max(px - 1.0 + self.edge_radius, 0.),
+                f64::max(py - 1.0 + self.edge_radius, 0.),
+            ),
+            OutputShape::Circle | OutputShape::Ellipse => {
+                let (rx, ry) = if self.output_shape == OutputShape::Circle {
+                    let side = u64::min(crop_width, crop_height) as f64;
+                    (
+                        px * crop_width as f64 / side,
+                        py * crop_height as f64 / side,
+                    )
+                } else {
+                    (px, py)
+                };
+                let r = (rx * rx + ry * ry).sqrt();
+                if r >= 1.0 {
+                    // clipped away by the round output
+                    return self.outside_importance;
+                }
+                (f64::max(r - 1.0 + self.edge_radius, 0.), 0.)
+            }
+        };
+        let d = (dx * dx + dy * dy) * self.edge_weight;
+        let mut s = 1.41 - (px * px + py * py).sqrt();
+        if self.rule_of_thirds {
+            s += (f64::max(0., s + d + 0.5) * 1.2) * (thirds(px) + thirds(py));
+        }
+        s + d
+    }
+
+    fn get_score(&self, img: &image::DynamicImage, crop: &CropSize) -> CropScore {
+        if self.f32_scoring {
+            let compensated = self.compensated_sum;
+            self.sum_score(img, crop, || Accumulator::new(compensated))
+        } else {
+            self.sum_score(img, crop, || 0.)
+        }
+    }
+
+    // get_score with the channels summed in accumulators made by `sum`
+    fn sum_score<S, F>(&self, img: &image::DynamicImage, crop: &CropSize, sum: F) -> CropScore
+    where
+        S: ScoreSum,
+        F: Fn() -> S,
+    {
+        let (mut detail, mut skin, mut saturation, mut boost) = (sum(), sum(), sum(), sum());
+        let (w, h) = img.dimensions();
+        // score map pixels are sampled at their top-left corner in working
+        // coordinates, which can exceed u32 for large score_down_sample
+        let downsample = self.score_down_sample as u64;
+
+        for downsample_y in 0..h {
+            for downsample_x in 0..w {
+                let x = downsample_x as u64 * downsample;
+                let y = downsample_y as u64 * downsample;
+                let importance = self.importance(crop, x, y) * self.depth_factor(x, y);
+                let pixel = img.get_pixel(downsample_x, downsample_y);
+                let d = pixel[1] as f64 / 255.;
+                skin.add(
+                    (pixel[0] as f64) / 255.
+                        * (d + self.skin_bias)
+                        * importance
+                        * self.skin_factor(x, y),
+                );
+                detail.add(d * importance);
+                saturation.add((pixel[2] as f64) / 255. * (d + self.saturation_bias) * importance);
+                boost.add(self.boost(x, y) * importance);
+            }
+        }
+
+        let (detail, skin, saturation, boost) = (
+            detail.value(),
+            skin.value(),
+            saturation.value(),
+            boost.value(),
+        );
+        let (detail_weight, skin_weight, saturation_weight, boost_weight) = self.weights();
+        let total = (detail * detail_weight
+            + self.capped(skin, self.skin_cap, crop) * skin_weight
+            + self.capped(saturation, self.saturation_cap, crop) * saturation_weight
+            + boost * boost_weight)
+            / crop.width as f64
+            / crop.height as f64
+            / self.opaque_fraction(img, crop);
+        CropScore {
+            total,
+            detail,
+            skin,
+            saturation,
+            boost,
+        }
+    }
+
+    // Sum of a channel over the crop limited to plus or minus cap times the
+    // number of score map samples it covers
+    fn capped(&self, sum: f64, cap: Option<f64>, crop: &CropSize) -> f64 {
+        match cap {
+            Some(cap) => {
+                let downsample = self.score_down_sample as f64;
+                let samples = crop.width as f64 * crop.height as f64 / (downsample * downsample);
+                sum.max(-cap * samples).min(cap * samples)
+            }
+            None => sum,
+        }
+    }
+
+    // Fraction of the crop that is opaque, from the alpha channel detect adds to
+    // the score map with mask_transparent. Scores are divided by it so that
+    // transparent areas don't dilute the density of the content.
+    fn opaque_fraction(&self, img: &image::DynamicImage, crop: &CropSize) -> f64 {
+        if !self.mask_transparent || !img.color().has_alpha() {
+            return 1.;
+        }
+        let downsample = self.score_down_sample as u64;
+        let (mut inside, mut opaque) = (0., 0.);
+        for (x, y, pixel) in img.pixels() {
+            if crop.contains_sample(x as u64 * downsample, y as u64 * downsample) {
+                inside += 1.;
+                opaque += pixel[3] as f64 / 255.;
+            }
+        }
+        if opaque > 0. {
+            opaque / inside
+        } else {
+            1.
+        }
+    }
+
+    // score_down_sample, 1 with full_resolution_scoring, or step * min_scale
+    // rounded down to a power of two with auto_score_down_sample
+    fn configured_score_down_sample(&self) -> u32 {
+        if self.full_resolution_scoring {
+            return 1;
+        }
+        if !self.auto_score_down_sample {
+            return self.score_down_sample;
+        }
+        let target = u32::max((self.step as f64 * self.min_scale) as u32, 1);
+        1 << (31 - target.leading_zeros())
+    }
+
+    // Row with the most edge energy, if it stands out enough from the average
+    // row to be a horizon line spanning the image.
+    fn detect_horizon(&self, output: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Option<u32> {
+        let (w, h) = output.dimensions();
+        if w < 3 || h < 3 {
+            return None;
+        }
+        // detect_edge keeps the plain lightness on the image border, skip it
+        let energy: Vec<f64> = (0..h)
+            .map(|y| {
+                if y == 0 || y == h - 1 {
+                    return 0.;
+                }
+                (1..w - 1).map(|x| output.get_pixel(x, y)[1] as f64).sum()
+            })
+            .collect();
+        let mean = energy.iter().sum::<f64>() / h as f64;
+        let (row, peak) =
+            energy.iter().enumerate().fold(
+                (0, 0.),
+                |best, (y, &e)| if e > best.1 { (y, e) } else { best },
+            );
+        // a few strong edges don't make a line across the whole image
+        if peak >= mean * HORIZON_DOMINANCE && peak >= w as f64 * 255. * 0.1 {
+            Some(row as u32)
+        } else {
+            None
+        }
+    }
+
+    // value maximized when choosing the top crop; NaN ranks below everything
+    fn objective(&self, analysis: &Analysis, crop: &CropInfo) -> f64 {
+        let value = match &self.objective_fn {
+            Some(Objective(f)) => {
+                let size = crop
+                    .size
+                    .unscale(analysis.prescale)
+                    .clamp(analysis.width, analysis.height);
+                f(&crop.score, &size)
+            }
+            None => crop.score.total,
+        };
+        if value.is_nan() {
+            f64::NEG_INFINITY
+        } else {
+            value
+        }
+    }
+
+    // scoring terms that need the full resolution analysis rather than the
+    // score map; `peak` is negative_space_peak of the same analysis
+    fn composition_score(&self, analysis: &Analysis, crop: &CropSize, peak: f64) -> f64 {
+        let mut score = self.horizon_score(analysis.horizon, crop);
+        if self.seam_penalty != 0. {
+            score -= self.seam_penalty * seam_energy(&analysis.output, crop);
+        }
+        if self.symmetry_weight != 0. {
+            score += self.symmetry_weight * self.symmetry(&analysis.score_map, crop);
+        }
+        if self.center_bias != 0. {
+            score += self.center_bias * centeredness(&analysis.output, crop);
+        }
+        if self.negative_space_weight != 0. {
+            score +=
+                self.negative_space_weight * self.negative_space(&analysis.score_map, crop, peak);
+        }
+        score
+    }
+
+    // highest saliency in the score map
+    fn saliency_peak(&self, score_output: &image::DynamicImage) -> f64 {
+        score_output
+            .pixels()
+            .map(|(_, _, pixel)| self.saliency(pixel))
+            .fold(0., f64::max)
+    }
+
+    // the saliency peak negative_space measures against, computed once per
+    // analysis and only when negative space is scored
+    fn negative_space_peak(&self, analysis: &Analysis) -> f64 {
+        if self.negative_space_weight == 0. {
+            return 0.;
+        }
+        self.saliency_peak(&analysis.score_map)
+    }
+
+    // 1 when half of `crop` is calm (below the saliency Fit mode counts as
+    // subject, relative to the map's `peak`), down to 0 when it is all
+    // subject or all calm
+    fn negative_space(
+        &self,
+        score_output: &image::DynamicImage,
+        crop: &CropSize,
+        peak: f64,
+    ) -> f64 {
+        let downsample = self.score_down_sample as u64;
+        let (mut inside, mut calm) = (0., 0.);
+        for (x, y, pixel) in score_output.pixels() {
+            if crop.contains_sample(x as u64 * downsample, y as u64 * downsample) {
+                inside += 1.;
+                if self.saliency(pixel) < peak * FIT_SALIENCY_THRESHOLD {
+                    calm += 1.;
+                }
+            }
+        }
+        if inside == 0. {
+            return 0.;
+        }
+        let calm = calm / inside;
+        4. * calm * (1. - calm)
+    }
+
+    // 1 when the saliency inside `crop` is split evenly between its left and
+    // right halves, down to 0 when it is all on one side
+    fn symmetry(&self, score_output: &image::DynamicImage, crop: &CropSize) -> f64 {
+        let downsample = self.score_down_sample as u64;
+        let center = crop.x as f64 + crop.width as f64 / 2.;
+        let (mut left, mut right) = (0., 0.);
+        for (x, y, pixel) in score_output.pixels() {
+            let (x, y) = (x as u64 * downsample, y as u64 * downsample);
+            if crop.contains_sample(x, y) {
+                let saliency = f64::max(self.saliency(pixel), 0.);
+                if (x as f64 + downsample as f64 / 2.) < center {
+                    left += saliency;
+                } else {
+                    right += saliency;
+                }
+            }
+        }
+        if left + right > 0. {
+            1. - (left - right).abs() / (left + right)
+        } else {
+            0.
+        }
+    }
+
+    // reward for a crop that puts the horizon close to one of its third lines
+    fn horizon_score(&self, horizon: Option<u32>, crop: &CropSize) -> f64 {
+        match horizon {
+            Some(row) if self.horizon_bias != 0. && crop.y <= row && row < crop.y + crop.height => {
+                let t = (row - crop.y) as f64 / crop.height as f64;
+                let distance = f64::min((t - 1. / 3.).abs(), (t - 2. / 3.).abs());
+                self.horizon_bias * f64::max(1. - distance * 6., 0.)
+            }
+            _ => 0.,
+        }
+    }
+
+    // skin (r), detail (g) and saturation (b) channels for every pixel of `img`
+    fn detect_channels<I>(&self, img: &I) -> ImageBuffer<Rgb<u8>, Vec<u8>>
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        let (size_x, size_y) = img.dimensions();
+        let mut output = ImageBuffer::new(size_x, size_y);
+        self.detect_channels_into(img, &mut output);
+        output
+    }
+
+    // detect_channels into `output`, which has the size of `img`; every
+    // channel of every pixel is overwritten
+    fn detect_channels_into<I>(&self, img: &I, output: &mut ImageBuffer<Rgb<u8>, Vec<u8>>)
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        if self.alpha_shape {
+            for (x, y, output_pixel) in output.enumerate_pixels_mut() {
+                *output_pixel = Rgb([0, img.get_pixel(x, y)[3], 0]);
+            }
+            return;
+        }
+        // skin and saturation wouldn't contribute to any score, so only
+        // lightness is needed and the color channels are left at zero
+        let luma_only = self.skin_weight == 0. && self.saturation_weight == 0.;
+        if luma_only || self.bilateral_spatial_sigma > 0. {
+            let mut luma = if self.pre_blur_sigma > 0. {
+                luma_samples(
+                    &image::imageops::blur(img, self.pre_blur_sigma as f32),
+                    self.linear_edges,
+                )
+            } else {
+                luma_samples(img, self.linear_edges)
+            };
+            if self.bilateral_spatial_sigma > 0. {
+                let (width, height) = output.dimensions();
+                luma = bilateral(
+                    &luma,
+                    width,
+                    height,
+                    self.bilateral_spatial_sigma,
+                    self.bilateral_range_sigma,
+                );
+            }
+            self.detect_edge_luma(&luma, output);
+        } else if self.pre_blur_sigma > 0. {
+            let blurred = image::imageops::blur(img, self.pre_blur_sigma as f32);
+            self.detect_edge(&blurred, output);
+        } else {
+            self.detect_edge(img, output);
+        }
+        if !luma_only {
+            self.detect_skin(img, output);
+            self.detect_saturation(img, output);
+        }
+    }
+
+    fn detect<I>(&self, img: &I) -> Analysis
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        self.detect_reusing(img, None)
+    }
+
+    // detect, filling the channel and score map buffers of `previous` (the
+    // analysis of an earlier image) when their sizes match
+    fn detect_reusing<I>(&self, img: &I, previous: Option<Analysis>) -> Analysis
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        let (size_x, size_y) = img.dimensions();
+        let (channels, score_map) = match previous {
+            Some(previous) if previous.output.dimensions() == (size_x, size_y) => {
+                (Some(previous.output), Some(previous.score_map))
+            }
+            _ => (None, None),
+        };
+        let mut output = if self.detection_octave > 0 {
+            // detect on a reduced copy so that the detectors see subjects at a
+            // more uniform scale, then bring the channels back to working size
+            let octave = u32::min(self.detection_octave, 31);
+            let reduced = box_resize(
+                img,
+                u32::max(size_x >> octave, 1),
+                u32::max(size_y >> octave, 1),
+            );
+            image::imageops::resize(
+                &self.detect_channels(&reduced),
+                size_x,
+                size_y,
+                image::imageops::FilterType::Triangle,
+            )
+        } else {
+            let mut output = channels.unwrap_or_else(|| ImageBuffer::new(size_x, size_y));
+            self.detect_channels_into(img, &mut output);
+            output
+        };
+        if self.mask_transparent {
+            // transparent pixels carry no content
+            for (x, y, pixel) in output.enumerate_pixels_mut() {
+                let alpha = img.get_pixel(x, y)[3] as f64 / 255.;
+                for c in pixel.0.iter_mut() {
+                    *c = (*c as f64 * alpha).round() as u8;
+                }
+            }
+        }
+
+        let score_down_sample = u32::min(
+            self.configured_score_down_sample(),
+            u32::max(u32::min(size_x, size_y) / MIN_SCORE_MAP_SIZE, 1),
+        );
+        if self.debug && score_down_sample != self.configured_score_down_sample() {
+            eprintln!(
+                "smartcrop: {}x{} image is too small for score_down_sample {}, using {}",
+                size_x,
+                size_y,
+                self.configured_score_down_sample(),
+                score_down_sample
+            );
+        }
+        let score_width = (size_x as f64 / score_down_sample as f64).ceil() as u32;
+        let score_height = (size_y as f64 / score_down_sample as f64).ceil() as u32;
+        let box_score_map = self.stable_resize || self.box_score_map;
+        let score_output = if self.mask_transparent {
+            // keep alpha alongside the channels for opaque_fraction
+            let output = ImageRgba8(ImageBuffer::from_fn(size_x, size_y, |x, y| {
+                let pixel = output.get_pixel(x, y);
+                Rgba([pixel[0], pixel[1], pixel[2], img.get_pixel(x, y)[3]])
+            }));
+            if score_down_sample == 1 {
+                output
+            } else if box_score_map {
+                ImageRgba8(box_resize(&output, score_width, score_height))
+            } else {
+                output.resize(
+                    score_width,
+                    score_height,
+                    image::imageops::FilterType::Lanczos3,
+                )
+            }
+        } else if score_down_sample == 1 {
+            // score the detection channels as they are
+            match score_map {
+                Some(ImageRgb8(mut map)) if map.dimensions() == (size_x, size_y) => {
+                    map.copy_from_slice(&output);
+                    ImageRgb8(map)
+                }
+                _ => ImageRgb8(output.clone()),
+            }
+        } else if box_score_map {
+            let mut map = match score_map {
+                Some(ImageRgba8(map)) if map.dimensions() == (score_width, score_height) => map,
+                _ => ImageBuffer::new(score_width, score_height),
+            };
+            box_resize_into(&output, &mut map);
+            ImageRgba8(map)
+        } else {
+            ImageRgb8(output.clone()).resize(
+                score_width,
+                score_height,
+                image::imageops::FilterType::Lanczos3,
+            )
+        };
+
+        let horizon = self.detect_horizon(&output);
+        Analysis {
+            output,
+            horizon,
+            score_map: score_output,
+            score_down_sample,
+            prescale: 1.,
+            width: size_x,
+            height: size_y,
+        }
+    }
+
+    // `unscaled` maps a working crop to the rectangle that will be returned
+    // for it, which is what the constraints are checked against
+    fn score_crops(
+        &self,
+        analysis: &Analysis,
+        mut crops: Vec<CropInfo>,
+        unscaled: &dyn Fn(&CropSize) -> CropSize,
+    ) -> CropResult {
+        let score_output = &analysis.score_map;
+        let (size_x, size_y) = analysis.output.dimensions();
+
+        let mut top_score = i32::MIN as f64;
+        let mut top_crop: Option<CropInfo> = None;
+
+        // Fit scores the one crop around the salient region instead of the
+        // candidates, so the requirements below apply to it
+        let mut padding = Padding::default();
+        if self.mode == CropMode::Fit {
+            let (size, fit_padding) = self.fit_crop(score_output, size_x, size_y);
+            crops = vec![CropInfo {
+                size,
+                score: CropScore::default(),
+            }];
+            padding = fit_padding;
+        }
+
+        // only candidates meeting every constraint compete, unless there are none
+        let meets_constraints = |crop: &CropInfo| {
+            self.constraints
+                .satisfied_by(&unscaled(&crop.size), analysis.width, analysis.height)
+        };
+        let constraints_unmet =
+            self.constraints != Constraints::default() && !crops.iter().any(meets_constraints);
+        if !constraints_unmet {
+            crops.retain(meets_constraints);
+        }
+
+        let required_saliency = if self.min_saliency_coverage > 0. {
+            let image = CropSize {
+                x: 0,
+                y: 0,
+                width: size_x,
+                height: size_y,
+            };
+            self.saliency_inside(score_output, &image) * self.min_saliency_coverage
+        } else {
+            0.
+        };
+        let mut order: Vec<usize> = (0..crops.len()).collect();
+        if self.time_budget.is_some() || self.candidate_budget.is_some() {
+            // score a coarse sample spread over all positions and scales
+            // first, so that the best crop is decent when the budget runs out
+            let stride = (crops.len() as f64).sqrt().ceil().max(1.) as usize;
+            order = (0..stride)
+                .flat_map(|offset| (offset..crops.len()).step_by(stride))
+                .collect();
+        }
+        let peak = self.negative_space_peak(analysis);
+        let start = Instant::now();
+        let mut scored = crops.len();
+        let mut top_index = None;
+        for (i, &index) in order.iter().enumerate() {
+            // at least one candidate is scored, so there is a crop to return
+            if self
+                .candidate_budget
+                .is_some_and(|budget| i >= budget.max(1))
+            {
+                scored = i;
+                break;
+            }
+            if let Some(budget) = self.time_budget {
+                if i > 0 && i % TIME_BUDGET_CHECK_INTERVAL == 0 && start.elapsed() > budget {
+                    scored = i;
+                    break;
+                }
+            }
+            let crop = &mut crops[index];
+            crop.score = self.get_score(score_output, &crop.size);
+            crop.score.total += self.composition_score(analysis, &crop.size, peak);
+            let value = self.objective(analysis, crop);
+            // ties go to the earlier candidate, whatever order they were scored in
+            let better = top_crop.is_none()
+                || value > top_score
+                || (value == top_score && top_index.is_some_and(|top| index < top));
+            if better
+                && (required_saliency <= 0.
+                    || self.saliency_inside(score_output, &crop.size) >= required_saliency)
+            {
+                top_crop = Some(crop.clone());
+                top_score = value;
+                top_index = Some(index);
+            }
+        }
+
+        // candidates left unscored by a budget aren't reported
+        let partial = scored < crops.len();
+        if partial {
+            let mut kept = vec![false; crops.len()];
+            for &index in &order[..scored] {
+                kept[index] = true;
+            }
+            let mut kept = kept.into_iter();
+            crops.retain(|_| kept.next().unwrap());
+        }
+
+        // when no candidate covers enough saliency, fall back to the best one
+        let saliency_coverage_fallback = top_crop.is_none();
+        let top_crop = top_crop.unwrap_or_else(|| {
+            crops
+                .iter()
+                .map(|crop| (self.objective(analysis, crop), crop))
+                .max_by(|a, b| a.0.total_cmp(&b.0))
+                .unwrap()
+                .1
+                .clone()
+        });
+
+        // how far the winner is ahead of the best alternative
+        let top_value = self.objective(analysis, &top_crop);
+        let runner_up = crops
+            .iter()
+            .filter(|crop| crop.size.iou(&top_crop.size) < AMBIGUITY_MAX_IOU)
+            .map(|crop| self.objective(analysis, crop))
+            .fold(None, |best: Option<f64>, value| {
+                Some(best.map_or(value, |best| best.max(value)))
+            });
+        let confidence_margin = match runner_up {
+            Some(second) if top_value > 0. => ((top_value - second) / top_value).clamp(0., 1.),
+            Some(_) => 0.,
+            None => 1.,
+        };
+
+        CropResult {
+            crops,
+            prescaled_crop: top_crop.size.clone(),
+            top_crop,
+            prescale: 1.,
+            padding,
+            upscaled: false,
+            upscale_factor: 1.,
+            score_down_sample: self.score_down_sample,
+            image_width: size_x,
+            image_height: size_y,
+            saliency_coverage_fallback,
+            constraints_unmet,
+            aspect: 1.,
+            confidence_margin,
+            ambiguous: confidence_margin < self.ambiguity_threshold,
+            partial,
+            coordinate_origin: CoordinateOrigin::TopLeft,
+        }
+    }
+
+    // total saliency of the score map pixels sampled inside `crop`
+    fn saliency_inside(&self, score_output: &image::DynamicImage, crop: &CropSize) -> f64 {
+        let downsample = self.score_down_sample as u64;
+        score_output
+            .pixels()
+            .filter(|&(x, y, _)| crop.contains_sample(x as u64 * downsample, y as u64 * downsample))
+            .map(|(_, _, pixel)| f64::max(self.saliency(pixel), 0.))
+            .sum()
+    }
+
+    // combined weighted saliency of a score map pixel, as accumulated by get_score
+    fn saliency(&self, pixel: Rgba<u8>) -> f64 {
+        let (detail_weight, skin_weight, saturation_weight, _) = self.weights();
+        let d = pixel[1] as f64 / 255.;
+        d * detail_weight
+            + (pixel[0] as f64) / 255. * (d + self.skin_bias) * skin_weight
+            + (pixel[2] as f64) / 255. * (d + self.saturation_bias) * saturation_weight
+    }
+
+    // Tightest crop at the target aspect around the salient part of the score
+    // map, along with the padding needed where it overflows the image.
+    fn fit_crop(&self, score_output: &image::DynamicImage, w: u32, h: u32) -> (CropSize, Padding) {
+        let (crop_width, crop_height) = self.crop_dimensions(w, h);
+        let aspect = crop_width as f64 / crop_height as f64;
+        let peak = self.saliency_peak(score_output);
+
+        let (mut x0, mut y0, mut x1, mut y1) = (u32::MAX, u32::MAX, 0, 0);
+        for (x, y, pixel) in score_output.pixels() {
+            if peak > 0. && self.saliency(pixel) >= peak * FIT_SALIENCY_THRESHOLD {
+                x0 = u32::min(x0, x);
+                y0 = u32::min(y0, y);
+                x1 = u32::max(x1, x + 1);
+                y1 = u32::max(y1, y + 1);
+            }
+        }
+        let (x0, y0, x1, y1) = if x0 < x1 {
+            let downsample = self.score_down_sample;
+            (
+                u32::min(x0 * downsample, w - 1),
+                u32::min(y0 * downsample, h - 1),
+                u32::min(x1 * downsample, w),
+                u32::min(y1 * downsample, h),
+            )
+        } else {
+            (0, 0, w, h)
+        };
+
+        let (bounds_width, bounds_height) = ((x1 - x0) as f64, (y1 - y0) as f64);
+        let (fit_width, fit_height) = if bounds_width / bounds_height > aspect {
+            (bounds_width, bounds_width / aspect)
+        } else {
+            (bounds_height * aspect, bounds_height)
+        };
+        // round the size before positioning so that x + width stays within w
+        let width = f64::min(fit_width, w as f64).round();
+        let height = f64::min(fit_height, h as f64).round();
+        let x = ((x0 + x1) as f64 / 2. - width / 2.).clamp(0., w as f64 - width);
+        let y = ((y0 + y1) as f64 / 2. - height / 2.).clamp(0., h as f64 - height);
+
+        // split the padding so that the subject stays centered, putting more
+        // of it on the side of an edge the subject is close to
+        let pad_x = (fit_width - width).round() as u32;
+        let pad_y = (fit_height - height).round() as u32;
+        let left = (fit_width / 2. - (x0 + x1) as f64 / 2.)
+            .round()
+            .clamp(0., pad_x as f64) as u32;
+        let top = (fit_height / 2. - (y0 + y1) as f64 / 2.)
+            .round()
+            .clamp(0., pad_y as f64) as u32;
+        (
+            CropSize {
+                x: x.floor() as u32,
+                y: y.floor() as u32,
+                width: width as u32,
+                height: height as u32,
+            },
+            Padding {
+                top,
+                right: pad_x - left,
+                bottom: pad_y - top,
+                left,
+            },
+        )
+    }
+
+    // base crop size at scale 1.0 in working coordinates
+    fn crop_dimensions(&self, w: u32, h: u32) -> (u32, u32) {
+        let min_dimension = if w > h { h } else { w };
+        let crop_width = if self.crop_width != 0 {
+            self.crop_width as u32
+        } else {
+            min_dimension
+        };
+        let crop_height = if self.crop_height != 0 {
+            self.crop_height as u32
+        } else {
+            min_dimension
+        };
+        (crop_width, crop_height)
+    }
+
+    // Rounds `size` down to dimension_multiple and origin_multiple. The height
+    // follows the rounded width where it can, to keep the aspect.
+    fn align(&self, size: CropSize) -> CropSize {
+        let mut size = size;
+        if let Some(m) = self.dimension_multiple.filter(|&m| m > 1) {
+            if size.width >= m && size.height >= m {
+                let width = size.width / m * m;
+                let height = (width as f64 * size.height as f64 / size.width as f64 / m as f64)
+                    .round() as u32
+                    * m;
+                size.height = height.clamp(m, size.height / m * m);
+                size.width = width;
+            }
+        }
+        if let Some(m) = self.origin_multiple.filter(|&m| m > 1) {
+            size.x = size.x / m * m;
+            size.y = size.y / m * m;
+        }
+        size
+    }
+
+    // crop_dimensions, shrunk to fit a crop larger than the image while
+    // keeping its aspect
+    fn fitted_crop_dimensions(&self, w: u32, h: u32) -> (u32, u32) {
+        let (crop_width, crop_height) = self.crop_dimensions(w, h);
+        let fit = f64::min(
+            1.,
+            f64::min(w as f64 / crop_width as f64, h as f64 / crop_height as f64),
+        );
+        (
+            ((crop_width as f64 * fit) as u32).max(1),
+            ((crop_height as f64 * fit) as u32).max(1),
+        )
+    }
+
+    // candidate spacing along x and y, at least a pixel even with step 0
+    fn steps(&self) -> (u32, u32) {
+        let step_x = if self.step_x != 0 {
+            self.step_x
+        } else {
+            self.step
+        };
+        let step_y = if self.step_y != 0 {
+            self.step_y
+        } else {
+            self.step
+        };
+        (step_x.max(1), step_y.max(1))
+    }
+
+    // widths stretched within aspect_tolerance of the target aspect
+    fn stretches(&self) -> Vec<f64> {
+        let tolerance = self.aspect_tolerance;
+        if tolerance > 0. {
+            vec![
+                1.,
+                1. - tolerance / 2.,
+                1. + tolerance / 2.,
+                1. - tolerance,
+                1. + tolerance,
+            ]
+        } else {
+            vec![1.]
+        }
+    }
+
+    // steps() widened along the axis with more positions until crops() yields
+    // at most max_candidates for a w x h image
+    fn adaptive_steps(&self, w: u32, h: u32) -> (u32, u32) {
+        let (base_x, base_y) = self.steps();
+        let (mut step_x, mut step_y) = (base_x, base_y);
+        if self.max_candidates == 0 || self.anchor.is_some() {
+            return (step_x, step_y);
+        }
+        let (crop_width, crop_height) = self.fitted_crop_dimensions(w, h);
+        let scales = scales(self.min_scale, self.max_scale, self.scale_step);
+        let stretches = self.stretches().len();
+        let positions = |len: u32, crop: u32, step: u32| match len.checked_sub(crop) {
+            Some(room) => (room / step) as usize + 1,
+            None => 0,
+        };
+        loop {
+            let (mut count, mut along_x, mut along_y) = (0, 0, 0);
+            for scale in scales.iter() {
+                let x = positions(w, (crop_width as f64 * scale) as u32, step_x);
+                let y = positions(h, (crop_height as f64 * scale) as u32, step_y);
+                count += x * y * stretches;
+                along_x = along_x.max(x);
+                along_y = along_y.max(y);
+            }
+            if count <= self.max_candidates || (along_x <= 1 && along_y <= 1) {
+                return (step_x, step_y);
+            }
+            if along_x >= along_y {
+                step_x += base_x;
+            } else {
+                step_y += base_y;
+            }
+        }
+    }
+
+    fn crops(&self, w: u32, h: u32) -> Vec<CropInfo> {
+        let mut crops = Vec::new();
+        // neighbouring scales can round to the same rectangle
+        let mut seen = HashSet::new();
+        let (crop_width, crop_height) = self.fitted_crop_dimensions(w, h);
+        let scales = scales(self.min_scale, self.max_scale, self.scale_step);
+        let (step_x, step_y) = self.adaptive_steps(w, h);
+        let stretches = self.stretches();
+
+        for scale in scales.iter() {
+            for stretch in stretches.iter() {
+                let width = (crop_width as f64 * scale * stretch) as u32;
+                let height = (crop_height as f64 * scale) as u32;
+                // with an anchor, the position follows from the size
+                let (xs, ys): (Vec<u32>, Vec<u32>) = match self.anchor {
+                    Some((x, y, rel_x, rel_y)) => (
+                        vec![anchored(x as f64, rel_x, width, w)],
+                        vec![anchored(y as f64, rel_y, height, h)],
+                    ),
+                    None => (
+                        (0..w).step_by(step_x as usize).collect(),
+                        (0..h).step_by(step_y as usize).collect(),
+                    ),
+                };
+                for &y in ys.iter() {
+                    if y + height > h {
+                        break;
+                    }
+                    for &x in xs.iter() {
+                        if x + width > w {
+                            break;
+                        }
+                        let size = self.align(CropSize {
+                            x,
+                            y,
+                            width,
+                            height,
+                        });
+                        if !seen.insert(size.clone()) {
+                            continue;
+                        }
+                        crops.push(CropInfo {
+                            size,
+                            score: CropScore {
+                                ..CropScore::default()
+                            },
+                        });
+                    }
+                }
+            }
+        }
+        // only sizes that can place the anchor exactly, unless none can
+        if let Some((x, y, rel_x, rel_y)) = self.anchor {
+            let fits = |crop: &CropInfo| {
+                anchor_fits(x as f64, rel_x, crop.size.width, w)
+                    && anchor_fits(y as f64, rel_y, crop.size.height, h)
+            };
+            if crops.iter().any(fits) {
+                crops.retain(fits);
+            }
+        }
+        // no scale fits (e.g. min_scale > 1), so fall back to the largest crop
+        // that does, centered
+        if crops.is_empty() && w > 0 && h > 0 {
+            let (width, height) = (crop_width.min(w), crop_height.min(h));
+            crops.push(CropInfo {
+                size: CropSize {
+                    x: (w - width) / 2,
+                    y: (h - height) / 2,
+                    width,
+                    height,
+                },
+                score: CropScore::default(),
+            });
+        }
+        crops
+    }
+}
+
+// Buffers of an earlier image kept by SmartCropSession
+#[derive(Default)]
+struct Scratch {
+    // prescaled copy of the image
+    resized: RgbaImage,
+    analysis: Option<Analysis>,
+}
+
+// What a candidate list depends on besides the session's configuration
+#[derive(PartialEq)]
+struct CandidateKey {
+    width: u32,
+    height: u32,
+    crop_width: i32,
+    crop_height: i32,
+    min_scale: f64,
+    anchor: Option<(u32, u32, f64, f64)>,
+}
+
+/// Crops a stream of images with one configuration. The detection buffers,
+/// the score map and the candidate list of the previous image are reused for
+/// the next one when its working size matches, instead of being allocated
+/// for every image.
+pub struct SmartCropSession {
+    sc: SmartCrop,
+    scratch: Scratch,
+    // candidates in working coordinates
+    candidates: Option<(CandidateKey, Vec<CropInfo>)>,
+}
+
+impl SmartCropSession {
+    pub fn new(sc: SmartCrop) -> SmartCropSession {
+        SmartCropSession {
+            sc,
+            scratch: Scratch::default(),
+            candidates: None,
+        }
+    }
+
+    /// Same as `crop_image` of the session's configuration.
+    pub fn crop_into<I>(&mut self, img: &I, opts: &SmartCrop) -> CropResult
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        if opts.rotate != Rotation::None || self.sc.composite_background.is_some() {
+            // these analyze a transformed copy of the image anyway
+            return self.sc.crop_image(img, opts);
+        }
+        let analysis = self.sc.prepare_reusing(img, opts, &mut self.scratch);
+        let working = self.sc.scoring_options(&analysis, opts);
+        let (width, height) = analysis.output.dimensions();
+        let key = CandidateKey {
+            width,
+            height,
+            crop_width: working.crop_width,
+            crop_height: working.crop_height,
+            min_scale: working.min_scale,
+            anchor: working.anchor,
+        };
+        let crops = match &self.candidates {
+            Some((cached, crops)) if *cached == key => crops.clone(),
+            _ => {
+                let crops = working.crops(width, height);
+                self.candidates = Some((key, crops.clone()));
+                crops
+            }
+        };
+        let result = self
+            .sc
+            .score_working(&analysis, opts, &working, crops, None);
+        self.scratch.analysis = Some(analysis);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::DynamicImage;
+    use std::fs::File;
+    use std::path::Path;
+
+    #[test]
+    fn it_works() {
+        let sc = SmartCrop::new();
+        let path = Path::new("test.jpg");
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let result = sc.crop(path, &opts).unwrap();
+        let mut img = image::open(path).unwrap();
+        let size = result.top_crop.size;
+
+        let output_img = img.crop(size.x, size.y, size.width, size.height);
+        let mut fout = File::create(Path::new("out.jpg")).unwrap();
+        let _ = output_img.write_to(&mut fout, image::ImageFormat::Jpeg);
+    }
+
+    #[test]
+    fn normalized_weights_are_scale_invariant() {
+        let img = image::open(Path::new("test.jpg")).unwrap();
+        let unit = SmartCrop {
+            detail_weight: 1.,
+            skin_weight: 1.,
+            saturation_weight: 1.,
+            normalize_weights: true,
+            ..SmartCrop::default()
+        };
+        let doubled = SmartCrop {
+            detail_weight: 2.,
+            skin_weight: 2.,
+            saturation_weight: 2.,
+            ..unit.clone()
+        };
+        let a = unit.crop_image(&img, &SmartCrop::default());
+        let b = doubled.crop_image(&img, &SmartCrop::default());
+        assert_eq!(a.top_crop.size, b.top_crop.size);
+        assert_eq!(a.top_crop.score.total, b.top_crop.score.total);
+
+        // boost_weight is rescaled along with the others
+        let boosts = vec![Boost {
+            x: 0,
+            y: 0,
+            width: 200,
+            height: 200,
+            weight: 1.,
+        }];
+        let unit = SmartCrop {
+            boosts,
+            boost_weight: 1.,
+            ..unit
+        };
+        let doubled = SmartCrop {
+            detail_weight: 2.,
+            skin_weight: 2.,
+            saturation_weight: 2.,
+            boost_weight: 2.,
+            ..unit.clone()
+        };
+        let a = unit.crop_image(&img, &SmartCrop::default());
+        let b = doubled.crop_image(&img, &SmartCrop::default());
+        assert_eq!(a.top_crop.size, b.top_crop.size);
+        assert_eq!(a.top_crop.score.total, b.top_crop.score.total);
+        assert!(a.top_crop.score.boost > 0.);
+    }
+
+    #[test]
+    fn candidate_rects_cover_scale_range() {
+        let sc = SmartCrop::new();
+        let rects = sc.candidate_rects(200, 100, &SmartCrop::default());
+        // scale 1.0: 100x100 at y = 0 and x = 0, 8, ..., 96
+        // scale 0.9: 90x90 at y = 0, 8 and x = 0, 8, ..., 104
+        assert_eq!(rects.len(), 13 + 2 * 14);
+        assert_eq!(rects.iter().filter(|r| r.width == 100).count(), 13);
+        assert_eq!(rects.iter().filter(|r| r.width == 90).count(), 28);
+        for r in rects.iter() {
+            assert_eq!(r.width, r.height);
+            assert_eq!(r.x % 8, 0);
+            assert_eq!(r.y % 8, 0);
+            assert!(r.x + r.width <= 200);
+            assert!(r.y + r.height <= 100);
+        }
+    }
+
+    #[test]
+    fn get_score_does_not_overflow_with_large_downsample() {
+        // 3 * 2^31 overflows u32, so the old inflated loop bounds wrapped
+        let sc = SmartCrop {
+            score_down_sample: 1 << 31,
+            ..SmartCrop::default()
+        };
+        let img = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(3, 3, Rgb([0, 255, 0])));
+        let crop = CropSize {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+        };
+        let score = sc.get_score(&img, &crop);
+        // only the sample at the origin falls inside the crop
+        let expected = sc.importance(&crop, 0, 0) + 8. * sc.outside_importance;
+        assert!((score.detail - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn prescaled_crop_matches_top_crop() {
+        let sc = SmartCrop::new();
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let result = sc.crop(Path::new("test.jpg"), &opts).unwrap();
+        assert!(result.prescale < 1.);
+        assert_eq!(
+            result.prescaled_crop.unscale(result.prescale),
+            result.top_crop.size
+        );
+    }
+
+    #[test]
+    fn circle_output_deprioritizes_corners() {
+        let mut score_map = ImageBuffer::new(100, 100);
+        for y in 75..90 {
+            for x in 75..90 {
+                score_map.put_pixel(x, y, Rgb([0, 255, 0]));
+            }
+        }
+        let score_map = DynamicImage::ImageRgb8(score_map);
+        let crop = CropSize {
+            x: 0,
+            y: 0,
+            width: 100,
+            height: 100,
+        };
+        let rect = SmartCrop {
+            score_down_sample: 1,
+            ..SmartCrop::default()
+        };
+        let circle = SmartCrop {
+            output_shape: OutputShape::Circle,
+            ..rect.clone()
+        };
+        let rect_score = rect.get_score(&score_map, &crop);
+        let circle_score = circle.get_score(&score_map, &crop);
+        assert!(circle_score.total < rect_score.total);
+    }
+
+    #[test]
+    fn box_resize_averages_area() {
+        let img = ImageBuffer::from_fn(4, 2, |x, _| {
+            if x < 2 {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([200, 100, 50, 255])
+            }
+        });
+        let resized = box_resize(&img, 2, 1);
+        assert_eq!(resized.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+        assert_eq!(resized.get_pixel(1, 0), &Rgba([200, 100, 50, 255]));
+        let resized = box_resize(&img, 1, 1);
+        assert_eq!(resized.get_pixel(0, 0), &Rgba([100, 50, 25, 255]));
+    }
+
+    #[test]
+    fn stable_resize_golden() {
+        let sc = SmartCrop {
+            stable_resize: true,
+            ..SmartCrop::default()
+        };
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let result = sc.crop(Path::new("test.jpg"), &opts).unwrap();
+        assert_eq!(
+            result.top_crop.size,
+            CropSize {
+                x: 161,
+                y: 23,
+                width: 285,
+                height: 285,
+            }
+        );
+    }
+
+    #[test]
+    fn outside_importance_sweep() {
+        let img = image::open(Path::new("test.jpg")).unwrap();
+        let areas: Vec<u32> = [-1.0, -0.5, 0.0, 0.5, 1.0]
+            .iter()
+            .map(|&outside_importance| {
+                let sc = SmartCrop {
+                    min_scale: 0.5,
+                    outside_importance,
+                    ..SmartCrop::default()
+                };
+                let size = sc.crop_image(&img, &SmartCrop::default()).top_crop.size;
+                size.width * size.height
+            })
+            .collect();
+        assert!(areas.windows(2).all(|w| w[0] >= w[1]));
+        assert!(areas[0] > areas[4]);
+    }
+
+    #[test]
+    fn crop_image_accepts_sub_image_views() {
+        let img = image::open(Path::new("test.jpg")).unwrap();
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop::new();
+        let view = img.view(100, 50, 300, 250);
+        let extracted = img.crop_imm(100, 50, 300, 250);
+        assert_eq!(
+            sc.crop_image(&view, &opts).top_crop.size,
+            sc.crop_image(&extracted, &opts).top_crop.size
+        );
+    }
+
+    #[test]
+    fn small_skin_regions_are_suppressed() {
+        // isolated skin-colored specks (like wood grain) on the left, a
+        // textured face on the right
+        let img = ImageBuffer::from_fn(240, 120, |x, y| {
+            let (dx, dy) = (x as f64 - 190., y as f64 - 60.);
+            if dx * dx + dy * dy < 25. * 25. {
+                if (x + y) % 2 == 0 {
+                    Rgba([200, 160, 135, 255])
+                } else {
+                    Rgba([150, 120, 101, 255])
+                }
+            } else if x < 120 && (x + y) % 2 == 0 {
+                Rgba([200, 160, 135, 255])
+            } else {
+                Rgba([100, 100, 100, 255])
+            }
+        });
+        let contains_face = |size: &CropSize| size.x <= 190 && 190 < size.x + size.width;
+
+        let sc = SmartCrop::default();
+        assert!(!contains_face(
+            &sc.crop_image(&img, &SmartCrop::default()).top_crop.size
+        ));
+
+        let sc = SmartCrop {
+            skin_min_region: 20,
+            ..SmartCrop::default()
+        };
+        assert!(contains_face(
+            &sc.crop_image(&img, &SmartCrop::default()).top_crop.size
+        ));
+    }
+
+    #[test]
+    fn fit_mode_keeps_subject_and_letterboxes() {
+        // small textured subject centered in a large flat frame
+        let img = ImageBuffer::from_fn(300, 200, |x, y| {
+            if (135..165).contains(&x) && (85..115).contains(&y) && (x / 4 + y / 4) % 2 == 0 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([40, 40, 40, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let fill = SmartCrop::new().crop_image(&img, &opts);
+        let fit = SmartCrop {
+            mode: CropMode::Fit,
+            ..SmartCrop::default()
+        }
+        .crop_image(&img, &opts);
+        let (fill, fit) = (fill.top_crop.size, fit);
+        assert!(fit.top_crop.size.width < fill.width);
+        assert_eq!(fit.top_crop.size.width, fit.top_crop.size.height);
+        assert!(fit.top_crop.size.x <= 135 && fit.top_crop.size.x + fit.top_crop.size.width >= 165);
+        assert!(fit.top_crop.size.y <= 85 && fit.top_crop.size.y + fit.top_crop.size.height >= 115);
+        assert_eq!(fit.padding, Padding::default());
+        // the fitted crop is the only one scored
+        assert_eq!(fit.ranked().len(), 1);
+        assert_eq!(fit.ranked()[0].size, fit.top_crop.size);
+        assert_eq!(fit.confidence_margin, 1.);
+        assert!(!fit.constraints_unmet);
+
+        // and constraints are checked against it rather than the candidates
+        let corner = CropSize {
+            x: 5,
+            y: 5,
+            width: 10,
+            height: 10,
+        };
+        let constrained = SmartCrop {
+            mode: CropMode::Fit,
+            constraints: Constraints {
+                must_contain: vec![corner],
+                ..Constraints::default()
+            },
+            ..SmartCrop::default()
+        }
+        .crop_image(&img, &opts);
+        assert!(constrained.constraints_unmet);
+        assert_eq!(constrained.top_crop.size, fit.top_crop.size);
+
+        // a subject spanning the full width can't fit a square without letterboxing
+        let img = ImageBuffer::from_fn(300, 200, |x, y| {
+            if (90..110).contains(&y) && (x / 4 + y / 4) % 2 == 0 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([40, 40, 40, 255])
+            }
+        });
+        let fit = SmartCrop {
+            mode: CropMode::Fit,
+            ..SmartCrop::default()
+        }
+        .crop_image(&img, &opts);
+        let size = fit.top_crop.size;
+        assert!(size.width > 280);
+        assert!(size.height >= 199);
+        assert_eq!(fit.padding.left + fit.padding.right, 0);
+        // the letterboxed output is square
+        let padded_height = size.height + fit.padding.top + fit.padding.bottom;
+        assert!((padded_height as i64 - size.width as i64).abs() <= 2);
+        // and split evenly around a band across the middle
+        assert!((fit.padding.top as i64 - fit.padding.bottom as i64).abs() <= 2);
+
+        // a band near the bottom keeps the letterbox centered on it, with all
+        // of the padding below the image
+        let img = ImageBuffer::from_fn(300, 200, |x, y| {
+            if (170..190).contains(&y) && (x / 4 + y / 4) % 2 == 0 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([40, 40, 40, 255])
+            }
+        });
+        let fit = SmartCrop {
+            mode: CropMode::Fit,
+            ..SmartCrop::default()
+        }
+        .crop_image(&img, &opts);
+        let size = fit.top_crop.size;
+        let padded_height = size.height + fit.padding.top + fit.padding.bottom;
+        assert!((padded_height as i64 - size.width as i64).abs() <= 2);
+        assert_eq!(fit.padding.top, 0, "{:?}", fit.padding);
+        assert!(fit.padding.bottom > 80, "{:?}", fit.padding);
+    }
+
+    fn checker(x: u32, y: u32, size: u32, a: [u8; 3], b: [u8; 3]) -> Rgba<u8> {
+        let c = if (x / size + y / size) % 2 == 1 { b } else { a };
+        Rgba([c[0], c[1], c[2], 255])
+    }
+
+    // the preset picks the subject at x = 60 while the default prefers the
+    // distractor at the right
+    fn assert_preset_beats_default(img: &RgbaImage, preset: Preset) {
+        let contains_subject = |size: &CropSize| size.x <= 60 && 60 < size.x + size.width;
+        assert!(!contains_subject(
+            &SmartCrop::default()
+                .crop_image(img, &SmartCrop::default())
+                .top_crop
+                .size
+        ));
+        assert!(contains_subject(
+            &SmartCrop::preset(preset)
+                .crop_image(img, &SmartCrop::default())
+                .top_crop
+                .size
+        ));
+    }
+
+    #[test]
+    fn portrait_preset() {
+        // smooth face next to a grey textured backdrop
+        let img = ImageBuffer::from_fn(240, 120, |x, y| {
+            let (dx, dy) = ((x as f64 - 60.) / 30., (y as f64 - 60.) / 40.);
+            if dx * dx + dy * dy < 1. {
+                let eye = (48..54).contains(&x) || (66..72).contains(&x);
+                if (y == 50 || y == 51) && eye {
+                    Rgba([50, 30, 30, 255])
+                } else {
+                    Rgba([200, 160, 135, 255])
+                }
+            } else if x > 150 && (20..100).contains(&y) {
+                checker(x, y, 3, [90, 90, 90], [170, 170, 170])
+            } else {
+                Rgba([120, 120, 120, 255])
+            }
+        });
+        assert_preset_beats_default(&img, Preset::Portrait);
+    }
+
+    #[test]
+    fn landscape_preset() {
+        // sand-colored rock on the left, denser foliage on the right
+        let img = ImageBuffer::from_fn(240, 120, |x, y| {
+            if x < 110 {
+                checker(x, y, 3, [200, 160, 135], [170, 136, 115])
+            } else if x > 130 {
+                checker(x, y, 2, [50, 90, 45], [80, 125, 70])
+            } else {
+                Rgba([120, 120, 120, 255])
+            }
+        });
+        let img = ImageBuffer::from_fn(240, 120, |x, y| *img.get_pixel(239 - x, y));
+        assert_preset_beats_default(&img, Preset::Landscape);
+    }
+
+    #[test]
+    fn product_preset() {
+        // red product on white next to a busy grey backdrop
+        let img = ImageBuffer::from_fn(240, 120, |x, y| {
+            let (dx, dy) = (x as f64 - 60., y as f64 - 60.);
+            if dx * dx + dy * dy < 30. * 30. {
+                Rgba([220, 30, 40, 255])
+            } else if x > 140 {
+                checker(x, y, 2, [80, 80, 80], [230, 230, 230])
+            } else {
+                Rgba([235, 235, 235, 255])
+            }
+        });
+        assert_preset_beats_default(&img, Preset::Product);
+    }
+
+    #[test]
+    fn text_preset() {
+        // lines of dark glyph strokes next to a colorful graphic
+        let img = ImageBuffer::from_fn(240, 120, |x, y| {
+            let (dx, dy) = (x as f64 - 180., y as f64 - 60.);
+            let glyph = y % 6 < 4 && (x * 7 + y / 6 * 13) % 17 < 2;
+            if (20..100).contains(&x) && (30..90).contains(&y) && glyph {
+                Rgba([0, 0, 0, 255])
+            } else if dx * dx + dy * dy < 45. * 45. {
+                checker(x, y, 6, [220, 40, 40], [40, 60, 220])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        });
+        assert_preset_beats_default(&img, Preset::Text);
+    }
+
+    #[test]
+    fn rescore_matches_full_analysis() {
+        let img = image::open(Path::new("test.jpg")).unwrap();
+        let opts = SmartCrop {
+            width: 100,
+            height: 50,
+            ..SmartCrop::default()
+        };
+        let analysis = SmartCrop::new().prepare(&img, &opts);
+        let tuned = SmartCrop {
+            detail_weight: 1.0,
+            skin_weight: 0.5,
+            saturation_weight: 2.0,
+            ..SmartCrop::default()
+        };
+        let rescored = tuned.rescore(&analysis, &opts);
+        let fresh = tuned.crop_image(&img, &opts);
+        assert_eq!(rescored.top_crop.size, fresh.top_crop.size);
+        assert_eq!(rescored.top_crop.score.total, fresh.top_crop.score.total);
+        assert_eq!(rescored.crops.len(), fresh.crops.len());
+    }
+
+    #[test]
+    fn cmyk_jpeg_is_converted_to_rgb() {
+        // CMYK encoding of a skin-colored face at x = 110 on a blue-grey background
+        let path = Path::new("test_cmyk.jpg");
+        let img = load_image(path, false, None).unwrap();
+        assert_eq!(img.color(), ColorType::Rgb8);
+        let background = img.get_pixel(10, 10);
+        for (c, expected) in [90, 110, 140].iter().enumerate() {
+            assert!((background[c] as i32 - expected).abs() <= 4);
+        }
+
+        let opts = SmartCrop {
+            width: 60,
+            height: 60,
+            ..SmartCrop::default()
+        };
+        let result = SmartCrop::preset(Preset::Portrait)
+            .crop(path, &opts)
+            .unwrap();
+        let size = result.top_crop.size;
+        assert!(size.x <= 110 && 110 < size.x + size.width);
+    }
+
+    #[test]
+    fn back_transformed_crop_is_clamped() {
+        // a crop touching the right edge of a 75px wide working image scales
+        // back one pixel past a 149px wide original
+        let working = CropSize {
+            x: 43,
+            y: 0,
+            width: 32,
+            height: 32,
+        };
+        let naive = working.unscale(0.5);
+        assert_eq!(naive.x + naive.width, 150);
+
+        let clamped = naive.clamp(149, 100);
+        assert_eq!(
+            clamped,
+            CropSize {
+                x: 86,
+                y: 0,
+                width: 63,
+                height: 64,
+            }
+        );
+
+        let outside = CropSize {
+            x: 200,
+            y: 120,
+            width: 10,
+            height: 10,
+        };
+        let clamped = outside.clamp(149, 100);
+        assert!(clamped.x + clamped.width <= 149);
+        assert!(clamped.y + clamped.height <= 100);
+    }
+
+    #[test]
+    fn saliency_centroid_lands_on_subject() {
+        let img = ImageBuffer::from_fn(320, 160, |x, y| {
+            let (dx, dy) = (x as f64 - 240., y as f64 - 60.);
+            if dx * dx + dy * dy < 20. * 20. {
+                checker(x, y, 4, [255, 255, 255], [60, 60, 60])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+        let sc = SmartCrop::new();
+        let opts = SmartCrop {
+            width: 50,
+            height: 50,
+            ..SmartCrop::default()
+        };
+        let analysis = sc.prepare(&img, &opts);
+        assert!(analysis.prescale < 1.);
+        let (x, y) = sc.saliency_centroid(&analysis);
+        assert!((x - 240.).abs() < 10., "x = {}", x);
+        assert!((y - 60.).abs() < 10., "y = {}", y);
+    }
+
+    #[test]
+    fn fine_scale_steps() {
+        let sc = SmartCrop {
+            scale_step: 0.025,
+            ..SmartCrop::default()
+        };
+        let mut widths: Vec<u32> = sc
+            .candidate_rects(400, 400, &SmartCrop::default())
+            .iter()
+            .map(|r| r.width)
+            .collect();
+        widths.dedup();
+        // scales 1.0, 0.975, 0.95, 0.925 and 0.9
+        assert_eq!(widths, vec![400, 390, 380, 370, 360]);
+    }
+
+    #[test]
+    fn upscaling_lets_smaller_crops_win() {
+        let img = ImageBuffer::from_fn(200, 200, |x, y| {
+            if (140..180).contains(&x) && (40..80).contains(&y) {
+                checker(x, y, 4, [255, 255, 255], [60, 60, 60])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+        let sc = SmartCrop {
+            min_scale: 0.3,
+            ..SmartCrop::default()
+        };
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let result = sc.crop_image(&img, &opts);
+        assert!(result.top_crop.size.width >= 100);
+        assert!(!result.upscaled);
+        assert_eq!(result.upscale_factor, 1.);
+
+        let opts = SmartCrop {
+            allow_upscale: true,
+            ..opts
+        };
+        let result = sc.crop_image(&img, &opts);
+        let size = &result.top_crop.size;
+        assert!(size.width < 100);
+        assert!(size.x <= 140 && 180 <= size.x + size.width);
+        assert!(result.upscaled);
+        assert_eq!(result.upscale_factor, 100. / size.width as f64);
+    }
+
+    #[test]
+    fn annotate_draws_top_crop() {
+        let img = image::open(Path::new("test.jpg")).unwrap();
+        let sc = SmartCrop {
+            annotate_top_n: 3,
+            ..SmartCrop::default()
+        };
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let result = sc.crop_image(&img, &opts);
+        let annotated = sc.annotate(&img, &result);
+        assert_eq!(annotated.dimensions(), img.dimensions());
+
+        let size = &result.top_crop.size;
+        let source = img.to_rgb8();
+        for &(x, y) in [
+            (size.x, size.y),
+            (size.x + size.width - 1, size.y + size.height / 2),
+            (size.x + size.width / 2, size.y + size.height - 1),
+        ]
+        .iter()
+        {
+            assert_eq!(annotated.get_pixel(x, y), &Rgb([255, 0, 0]));
+            assert_ne!(annotated.get_pixel(x, y), source.get_pixel(x, y));
+        }
+    }
+
+    #[test]
+    fn tiny_image_reduces_score_down_sample() {
+        let img = ImageBuffer::from_fn(5, 5, |x, y| {
+            if x >= 3 && y >= 3 {
+                Rgba([255u8, 0, 0, 255])
+            } else {
+                Rgba([128u8, 128, 128, 255])
+            }
+        });
+        let sc = SmartCrop::default();
+        let opts = SmartCrop {
+            width: 2,
+            height: 2,
+            ..SmartCrop::default()
+        };
+        let result = sc.crop_image(&img, &opts);
+        assert_eq!(result.score_down_sample, 1);
+
+        let size = &result.top_crop.size;
+        assert!(size.width > 0 && size.height > 0);
+        assert!(size.x + size.width <= 5 && size.y + size.height <= 5);
+        // the crop should reach into the saturated corner
+        assert!(size.x + size.width > 3 && size.y + size.height > 3);
+    }
+
+    #[test]
+    fn f32_scoring_matches_f64() {
+        let photo = image::open(Path::new("test.jpg")).unwrap().to_rgba8();
+        let synthetic = ImageBuffer::from_fn(240, 160, |x, y| {
+            if (150..200).contains(&x) && (40..100).contains(&y) {
+                checker(x, y, 4, [220, 40, 40], [40, 40, 220])
+            } else {
+                Rgba([100, 110, 100, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let fast = SmartCrop {
+            f32_scoring: true,
+            ..SmartCrop::default()
+        };
+        for img in [photo, synthetic].iter() {
+            let expected = SmartCrop::default().crop_image(img, &opts);
+            let result = fast.crop_image(img, &opts);
+            assert_eq!(result.top_crop.size, expected.top_crop.size);
+            let (a, b) = (result.top_crop.score.total, expected.top_crop.score.total);
+            assert!((a - b).abs() <= b.abs() * 1e-4, "{} vs {}", a, b);
+        }
+    }
+
+    #[test]
+    fn row_resizer_matches_box_resize() {
+        let img = ImageBuffer::from_fn(37, 23, |x, y| {
+            Rgba([(x * 7) as u8, (y * 11) as u8, ((x * y) % 256) as u8, 255])
+        });
+        for &(width, height) in [(10, 7), (37, 23), (3, 20)].iter() {
+            let mut resizer = RowResizer::new(37, 23, width, height);
+            for y in 0..23 {
+                let row: Vec<Rgba<u8>> = (0..37).map(|x| *img.get_pixel(x, y)).collect();
+                resizer.push_row(y, &row);
+            }
+            assert!(resizer.pending.is_empty());
+            assert_eq!(resizer.output, box_resize(&img, width, height));
+        }
+    }
+
+    #[test]
+    fn duplicate_candidates_are_dropped() {
+        // 0.01 steps on a 40px crop truncate pairs of scales to the same width
+        let sc = SmartCrop {
+            width: 40,
+            height: 40,
+            crop_width: 40,
+            crop_height: 40,
+            scale_step: 0.01,
+            ..SmartCrop::default()
+        };
+        let crops = sc.crops(40, 40);
+        let unique: HashSet<&CropSize> = crops.iter().map(|crop| &crop.size).collect();
+        assert_eq!(unique.len(), crops.len());
+        // 11 scales from 1.0 to 0.9, but only 5 distinct widths (40, 39, 38, 37, 36)
+        let positions: usize = (36..=40)
+            .map(|side| (40 - side) / 8 + 1)
+            .map(|n| n * n)
+            .sum();
+        assert_eq!(crops.len(), positions);
+
+        let img = ImageBuffer::from_fn(40, 40, |x, y| {
+            checker(x, y, 3, [200, 40, 40], [40, 40, 200])
+        });
+        let result = sc.crop_image(&img, &sc);
+        let best = result.crops.iter().fold(&result.crops[0], |best, crop| {
+            if crop.score.total > best.score.total {
+                crop
+            } else {
+                best
+            }
+        });
+        assert_eq!(result.top_crop.size, best.size);
+    }
+
+    #[test]
+    fn top_crop_uv_maps_to_pixels() {
+        let img = image::open(Path::new("test.jpg")).unwrap();
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let result = SmartCrop::new().crop_image(&img, &opts);
+        let [u0, v0, u1, v1] = result.top_crop_uv();
+        let size = &result.top_crop.size;
+        assert_eq!((u0 * 480.).round() as u32, size.x);
+        assert_eq!((v0 * 320.).round() as u32, size.y);
+        assert_eq!(((u1 - u0) * 480.).round() as u32, size.width);
+        assert_eq!(((v1 - v0) * 320.).round() as u32, size.height);
+
+        // the default target is the largest square, so a square image is cropped whole
+        let img = ImageBuffer::from_fn(64, 64, |x, y| checker(x, y, 4, [0, 0, 0], [255, 255, 255]));
+        let result = SmartCrop::new().crop_image(&img, &SmartCrop::default());
+        assert_eq!(result.top_crop_uv(), [0., 0., 1., 1.]);
+    }
+
+    #[test]
+    fn pre_blur_ignores_sensor_noise() {
+        // grainy flat region on the left, blocky subject on the right
+        let mut seed = 12345u32;
+        let img = ImageBuffer::from_fn(240, 120, |x, y| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let noise = (seed >> 16) % 81;
+            if x < 100 {
+                let v = (88 + noise) as u8;
+                Rgba([v, v, v, 255])
+            } else if (170..220).contains(&x) && (30..90).contains(&y) {
+                checker(x, y, 10, [40, 40, 40], [220, 220, 220])
+            } else {
+                Rgba([128, 128, 128, 255])
+            }
+        });
+        let contains_subject = |size: &CropSize| size.x <= 170 && 220 <= size.x + size.width;
+
+        let sc = SmartCrop {
+            min_scale: 0.5,
+            ..SmartCrop::default()
+        };
+        let opts = SmartCrop::default();
+        assert!(!contains_subject(&sc.crop_image(&img, &opts).top_crop.size));
+
+        let sc = SmartCrop {
+            pre_blur_sigma: 1.5,
+            ..sc
+        };
+        assert!(contains_subject(&sc.crop_image(&img, &opts).top_crop.size));
+    }
+
+    #[test]
+    fn ranked_candidates_are_sorted_and_complete() {
+        let img = image::open(Path::new("test.jpg")).unwrap();
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let result = SmartCrop::default().crop_image(&img, &opts);
+        let ranked = result.ranked_candidates();
+        assert_eq!(ranked.len(), result.crops.len());
+        assert!(ranked.windows(2).all(|pair| pair[0].total >= pair[1].total));
+        assert_eq!(ranked[0].total, result.top_crop.score.total);
+
+        let json = serde_json::to_string(&ranked).unwrap();
+        let parsed: Vec<RankedCrop> = serde_json::from_str(&json).unwrap();
+        for (parsed, crop) in parsed.iter().zip(ranked.iter()) {
+            assert_eq!(parsed.size, crop.size);
+            assert!((parsed.total - crop.total).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn crop_result_iterates_by_score() {
+        let img = image::open(Path::new("test.jpg")).unwrap();
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let result = SmartCrop::default().crop_image(&img, &opts);
+        let mut count = 0;
+        let mut previous = f64::INFINITY;
+        for crop in &result {
+            if count == 0 {
+                assert_eq!(crop.size, result.top_crop.size);
+            }
+            assert!(crop.score.total <= previous);
+            previous = crop.score.total;
+            count += 1;
+        }
+        assert_eq!(count, result.crops.len());
+    }
+
+    #[test]
+    fn diverse_crops_do_not_overlap() {
+        let img = image::open(Path::new("test.jpg")).unwrap();
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop {
+            min_scale: 0.5,
+            ..SmartCrop::default()
+        };
+        let result = sc.crop_image(&img, &opts);
+        let crops = result.diverse_crops(4, 0.3);
+        assert_eq!(crops.len(), 4);
+        assert_eq!(crops[0].size, result.top_crop.size);
+        for (i, a) in crops.iter().enumerate() {
+            for b in crops[i + 1..].iter() {
+                assert!(a.size.iou(&b.size) < 0.3);
+            }
+        }
+        assert!(crops
+            .windows(2)
+            .skip(1)
+            .all(|w| w[0].score.total >= w[1].score.total));
+
+        // a gap past 1 or NaN never hands back the same crop twice
+        for gap in &[1.5, f64::NAN] {
+            let crops = result.diverse_crops(4, *gap);
+            assert_eq!(crops.len(), 4);
+            for (i, a) in crops.iter().enumerate() {
+                assert!(crops[i + 1..].iter().all(|b| b.size != a.size));
+            }
+        }
+    }
+
+    #[test]
+    fn crop_to_slot_fills_wide_slot() {
+        let img = ImageBuffer::from_fn(200, 200, |x, y| {
+            if (60..140).contains(&y) {
+                checker(x, y, 4, [220, 40, 40], [40, 40, 220])
+            } else {
+                Rgba([120, 120, 120, 255])
+            }
+        });
+        let sc = SmartCrop::new();
+        let filled = sc.crop_to_slot(&img, 160, 90, false);
+        assert_eq!(filled.dimensions(), (160, 90));
+
+        // a slot larger than the source is only filled when upscaling
+        let filled = sc.crop_to_slot(&img, 320, 180, true);
+        assert_eq!(filled.dimensions(), (320, 180));
+        let clamped = sc.crop_to_slot(&img, 320, 180, false);
+        let (width, height) = clamped.dimensions();
+        assert!(width <= 200 && height <= 200);
+        assert!((width as f64 / height as f64 - 16. / 9.).abs() < 0.02);
+
+        // the configured rotation applies, like it does with crop_image
+        let img = ImageBuffer::from_fn(300, 150, |x, y| {
+            if x < 100 {
+                checker(x, y, 4, [220, 40, 40], [40, 40, 220])
+            } else {
+                Rgba([120, 120, 120, 255])
+            }
+        });
+        let rotated = SmartCrop {
+            rotate: Rotation::Cw90,
+            ..SmartCrop::new()
+        };
+        let expected = sc.crop_to_slot(&image::imageops::rotate90(&img), 90, 160, false);
+        assert_eq!(rotated.crop_to_slot(&img, 90, 160, false), expected);
+    }
+
+    #[test]
+    fn auto_score_down_sample_follows_step() {
+        let sc = SmartCrop {
+            auto_score_down_sample: true,
+            ..SmartCrop::default()
+        };
+        // 8 * 0.9 = 7.2 rounds down to 4, not the hardcoded 8
+        assert_eq!(sc.configured_score_down_sample(), 4);
+        let coarse = SmartCrop {
+            step: 16,
+            min_scale: 1.0,
+            ..sc.clone()
+        };
+        assert_eq!(coarse.configured_score_down_sample(), 16);
+
+        let img = image::open(Path::new("test.jpg")).unwrap();
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let result = sc.crop_image(&img, &opts);
+        assert_eq!(result.score_down_sample, 4);
+        // the face at (330, 150) is still picked
+        let size = result.top_crop.size;
+        assert!(size.x <= 330 && 330 < size.x + size.width);
+        assert!(size.y <= 150 && 150 < size.y + size.height);
+    }
+
+    #[test]
+    fn boost_json_shifts_crop() {
+        let path = Path::new("test.jpg");
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop {
+            min_scale: 0.5,
+            ..SmartCrop::default()
+        };
+        let contains_boost = |size: &CropSize| size.x <= 40 && 120 <= size.x + size.width;
+        let size = sc.crop(path, &opts).unwrap().top_crop.size;
+        assert!(!contains_boost(&size), "{:?}", size);
+
+        let json = r#"[{"x": 40, "y": 120, "width": 80, "height": 80, "weight": 1.0}]"#;
+        let size = sc
+            .crop_with_boost_json(path, json, &opts)
+            .unwrap()
+            .top_crop
+            .size;
+        assert!(contains_boost(&size), "{:?}", size);
+
+        let malformed = r#"[{"x": 0, "y": 260, "width": -60}]"#;
+        match sc.crop_with_boost_json(path, malformed, &opts) {
+            Err(SmartCropError::InvalidBoosts(_)) => {}
+            other => panic!(
+                "expected InvalidBoosts, got {:?}",
+                other.map(|r| r.top_crop)
+            ),
+        }
+    }
+
+    #[test]
+    fn edge_only_analysis_uses_luma() {
+        let img = image::open(Path::new("test.jpg")).unwrap().to_rgba8();
+        let edge_only = SmartCrop {
+            skin_weight: 0.,
+            saturation_weight: 0.,
+            ..SmartCrop::default()
+        };
+        let luma = edge_only.detect(&img).output;
+        let full = SmartCrop::default().detect(&img).output;
+        for (a, b) in luma.pixels().zip(full.pixels()) {
+            assert_eq!(a[1], b[1]);
+            assert_eq!((a[0], a[2]), (0, 0));
+        }
+    }
+
+    #[test]
+    fn rotation_moves_crop_with_subject() {
+        // subject on the right of a landscape image
+        let img = ImageBuffer::from_fn(300, 150, |x, y| {
+            if (220..280).contains(&x) && (45..105).contains(&y) {
+                checker(x, y, 4, [255, 255, 255], [60, 60, 60])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop::new();
+        let result = sc.crop_image(&img, &opts);
+        assert_eq!((result.image_width, result.image_height), (300, 150));
+        assert!(result.top_crop.size.x + result.top_crop.size.width >= 280);
+
+        // rotated clockwise the subject ends up at the bottom of a portrait frame
+        let opts = SmartCrop {
+            rotate: Rotation::Cw90,
+            ..opts
+        };
+        let result = sc.crop_image(&img, &opts);
+        assert_eq!((result.image_width, result.image_height), (150, 300));
+        let size = result.top_crop.size;
+        assert!(size.y <= 220 && 280 <= size.y + size.height, "{:?}", size);
+        assert!(size.x + size.width <= 150);
+    }
+
+    #[test]
+    fn saliency_coverage_keeps_both_subjects() {
+        // a larger subject on the left and a smaller one on the right
+        let img = ImageBuffer::from_fn(300, 150, |x, y| {
+            if (30..90).contains(&x) && (45..105).contains(&y) {
+                checker(x, y, 4, [255, 255, 255], [40, 40, 40])
+            } else if (210..260).contains(&x) && (50..100).contains(&y) {
+                checker(x, y, 4, [220, 220, 220], [60, 60, 60])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 300,
+            height: 150,
+            allow_upscale: true,
+            ..SmartCrop::default()
+        };
+        let contains_both = |size: &CropSize| size.x <= 60 && 235 < size.x + size.width;
+        let sc = SmartCrop {
+            min_scale: 0.3,
+            ..SmartCrop::default()
+        };
+        let size = sc.crop_image(&img, &opts).top_crop.size;
+        assert!(!contains_both(&size), "{:?}", size);
+        let sc = SmartCrop {
+            min_saliency_coverage: 0.8,
+            ..sc
+        };
+        let size = sc.crop_image(&img, &opts).top_crop.size;
+        assert!(contains_both(&size), "{:?}", size);
+    }
+
+    #[test]
+    fn batch_report_serializes_all_fields() {
+        let opts = SmartCrop {
+            width: 60,
+            height: 60,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop::new();
+        let reports: Vec<Report> = ["test.jpg", "test_cmyk.jpg"]
+            .iter()
+            .map(|path| sc.report(Path::new(path), &opts).unwrap())
+            .collect();
+        assert!(reports
+            .iter()
+            .all(|r| r.candidates > 0 && r.fallbacks.is_empty()));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&Report::to_json(&reports).unwrap()).unwrap();
+        let entries = json.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1]["path"], "test_cmyk.jpg");
+        for field in [
+            "path",
+            "crop",
+            "detail",
+            "saturation",
+            "skin",
+            "boost",
+            "total",
+            "candidates",
+            "prepare_ms",
+            "score_ms",
+            "fallbacks",
+        ]
+        .iter()
+        {
+            assert!(entries[0].get(field).is_some(), "missing {}", field);
+        }
+        for field in ["x", "y", "width", "height"].iter() {
+            assert!(
+                entries[0]["crop"].get(field).is_some(),
+                "missing crop.{}",
+                field
+            );
+        }
+
+        let csv = Report::to_csv(&reports);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("path,x,y,width,height,"));
+        assert!(lines[1].starts_with("\"test.jpg\","));
+        assert!(lines
+            .iter()
+            .all(|line| line.split(',').count() == lines[0].split(',').count()));
+    }
+
+    #[test]
+    fn full_resolution_scoring() {
+        let img = image::open(Path::new("test.jpg")).unwrap();
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop {
+            full_resolution_scoring: true,
+            ..SmartCrop::default()
+        };
+        let analysis = sc.prepare(&img, &opts);
+        assert_eq!(analysis.score_down_sample, 1);
+        assert_eq!(analysis.score_map.to_rgb8(), analysis.output);
+
+        let full = sc.rescore(&analysis, &opts);
+        assert_eq!(full.score_down_sample, 1);
+        let totals: Vec<f64> = full.crops.iter().map(|crop| crop.score.total).collect();
+        assert!(totals.iter().all(|total| total.is_finite()));
+        assert!(totals.iter().any(|&total| total != totals[0]));
+
+        let downsampled = SmartCrop::new().crop_image(&img, &opts);
+        assert!(full.top_crop.size.iou(&downsampled.top_crop.size) > 0.8);
+    }
+
+    #[test]
+    fn horizon_bias_moves_horizon_to_a_third() {
+        // bright sky over a slightly choppy dark sea, horizon at y = 100
+        let img = ImageBuffer::from_fn(300, 200, |x, y| {
+            if y < 100 {
+                Rgba([200, 210, 230, 255])
+            } else {
+                let v = if (x / 6 + y / 3) % 2 == 1 { 50 } else { 62 };
+                Rgba([20, 40, v, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 300,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let horizon_position = |size: &CropSize| (100 - size.y) as f64 / size.height as f64;
+        let analysis = SmartCrop::new().prepare(&img, &opts);
+        assert_eq!(analysis.horizon, Some(99));
+
+        let plain = SmartCrop::new().rescore(&analysis, &opts).top_crop.size;
+        let sc = SmartCrop {
+            horizon_bias: 1.0,
+            ..SmartCrop::default()
+        };
+        let biased = sc.rescore(&analysis, &opts).top_crop.size;
+        assert_ne!(plain, biased);
+        let t = horizon_position(&biased);
+        assert!(
+            (t - 1. / 3.).abs() < 0.1 || (t - 2. / 3.).abs() < 0.1,
+            "{}",
+            t
+        );
+    }
+
+    #[test]
+    fn display_summarizes_top_crop() {
+        let img = image::open(Path::new("test.jpg")).unwrap();
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let result = SmartCrop::new().crop_image(&img, &opts);
+        let size = &result.top_crop.size;
+        let summary = result.top_crop.to_string();
+        assert!(summary.starts_with(&format!(
+            "crop {},{} {}x{} ",
+            size.x, size.y, size.width, size.height
+        )));
+        assert!(summary.contains(&format!("score={:.4}", result.top_crop.score.total)));
+        assert_eq!(
+            result.to_string(),
+            format!("{} of {} candidates", summary, result.crops.len())
+        );
+    }
+
+    #[test]
+    fn transparent_pixels_do_not_dilute_score() {
+        // opaque subject in the middle of a transparent canvas
+        let img = ImageBuffer::from_fn(200, 200, |x, y| {
+            if (70..130).contains(&x) && (70..130).contains(&y) {
+                checker(x, y, 4, [255, 255, 255], [40, 40, 40])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+        // only the full frame is a candidate
+        let sc = SmartCrop {
+            min_scale: 1.0,
+            ..SmartCrop::default()
+        };
+        let plain = sc.crop_image(&img, &SmartCrop::default());
+        let masked = SmartCrop {
+            mask_transparent: true,
+            ..sc
+        }
+        .crop_image(&img, &SmartCrop::default());
+        assert_eq!(plain.top_crop.size, masked.top_crop.size);
+
+        // the score is taken over the opaque 60x60 instead of the whole 200x200
+        let ratio = masked.top_crop.score.total / plain.top_crop.score.total;
+        let expected = (200. * 200.) / (60. * 60.);
+        assert!(
+            (ratio / expected - 1.).abs() < 0.15,
+            "{} vs {}",
+            ratio,
+            expected
+        );
+    }
+
+    #[test]
+    fn best_among_ranks_given_rectangles() {
+        let img = image::open(Path::new("test.jpg")).unwrap();
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let rect = |x, y| CropSize {
+            x,
+            y,
+            width: 150,
+            height: 150,
+        };
+        // empty top-left corner, the face at (330, 150) and the bottom edge
+        let candidates = [rect(0, 0), rect(255, 75), rect(100, 170)];
+        let result = SmartCrop::new()
+            .best_among(&img, &candidates, &opts)
+            .unwrap();
+        assert_eq!(result.top_crop.size, candidates[1]);
+        let sizes: Vec<&CropSize> = result.crops.iter().map(|crop| &crop.size).collect();
+        assert_eq!(sizes, candidates.iter().collect::<Vec<_>>());
+
+        match SmartCrop::new().best_among(&img, &[rect(400, 0)], &opts) {
+            Err(SmartCropError::InvalidCandidate(size)) => assert_eq!(size, rect(400, 0)),
+            other => panic!(
+                "expected InvalidCandidate, got {:?}",
+                other.map(|r| r.top_crop)
+            ),
+        }
+    }
+
+    #[test]
+    fn separate_axis_steps() {
+        let sc = SmartCrop {
+            crop_width: 50,
+            crop_height: 50,
+            step_x: 4,
+            step_y: 16,
+            scale_step: 0.,
+            ..SmartCrop::default()
+        };
+        let crops = sc.crops(300, 100);
+        let mut xs: Vec<u32> = crops.iter().map(|crop| crop.size.x).collect();
+        let mut ys: Vec<u32> = crops.iter().map(|crop| crop.size.y).collect();
+        xs.sort_unstable();
+        xs.dedup();
+        ys.sort_unstable();
+        ys.dedup();
+        assert_eq!(xs, (0..=250).step_by(4).collect::<Vec<_>>());
+        assert_eq!(ys, vec![0, 16, 32, 48]);
+
+        // unset axes fall back to step
+        let sc = SmartCrop { step_x: 0, ..sc };
+        let mut xs: Vec<u32> = sc.crops(300, 100).iter().map(|crop| crop.size.x).collect();
+        xs.sort_unstable();
+        xs.dedup();
+        assert!(xs.windows(2).all(|w| w[1] - w[0] == 8));
+    }
+
+    #[test]
+    fn seam_penalty_prefers_clean_borders() {
+        // the right subject is larger, but a textured band runs down the left
+        // border of the crop around it
+        let img = ImageBuffer::from_fn(400, 200, |x, y| {
+            let left = (50..110).contains(&x) && (70..130).contains(&y);
+            let right = (240..320).contains(&x) && (60..140).contains(&y);
+            let band = (216..224).contains(&x);
+            if left || right || band {
+                checker(x, y, 2, [255, 255, 255], [40, 40, 40])
+            } else {
+                Rgba([90, 90, 90, 255])
+            }
+        });
+        let img = image::DynamicImage::ImageRgba8(img);
+        let rect = |x| CropSize {
+            x,
+            y: 40,
+            width: 120,
+            height: 120,
+        };
+        let candidates = [rect(20), rect(220)];
+        let opts = SmartCrop::default();
+        let plain = SmartCrop::new()
+            .best_among(&img, &candidates, &opts)
+            .unwrap();
+        assert_eq!(plain.top_crop.size, candidates[1]);
+
+        // crop totals are per pixel densities, so a small weight goes a long way
+        let sc = SmartCrop {
+            seam_penalty: 0.01,
+            ..SmartCrop::default()
+        };
+        let result = sc.best_among(&img, &candidates, &opts).unwrap();
+        assert_eq!(result.top_crop.size, candidates[0]);
+    }
+
+    #[test]
+    fn detection_octave_is_more_scale_consistent() {
+        // the same checkered subject at 2px and at 4px per square
+        let img = ImageBuffer::from_fn(240, 120, |x, y| {
+            if (20..60).contains(&x) && (40..80).contains(&y) {
+                checker(x, y, 2, [255, 255, 255], [40, 40, 40])
+            } else if (120..200).contains(&x) && (20..100).contains(&y) {
+                checker(x, y, 4, [255, 255, 255], [40, 40, 40])
+            } else {
+                Rgba([90, 90, 90, 255])
+            }
+        });
+        let mean_detail = |output: &ImageBuffer<Rgb<u8>, Vec<u8>>, x0, y0, size| {
+            let mut sum = 0.;
+            for y in y0..y0 + size {
+                for x in x0..x0 + size {
+                    sum += output.get_pixel(x, y)[1] as f64;
+                }
+            }
+            sum / (size * size) as f64
+        };
+        let inconsistency = |octave| {
+            let sc = SmartCrop {
+                detection_octave: octave,
+                ..SmartCrop::default()
+            };
+            let output = sc.detect(&img).output;
+            // inner parts of each subject, clear of their outlines
+            let small = mean_detail(&output, 28, 48, 24);
+            let large = mean_detail(&output, 136, 36, 48);
+            (small / large - 1.).abs()
+        };
+        assert!(inconsistency(1) < inconsistency(0));
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn shared_across_threads() {
+        assert_send_sync::<SmartCrop>();
+        assert_send_sync::<Analysis>();
+        assert_send_sync::<CropResult>();
+
+        let sc = std::sync::Arc::new(SmartCrop::new());
+        let handles: Vec<_> = [40u32, 180]
+            .iter()
+            .map(|&subject_x| {
+                let sc = sc.clone();
+                std::thread::spawn(move || {
+                    let img = ImageBuffer::from_fn(240, 120, |x, y| {
+                        if (subject_x..subject_x + 20).contains(&x) && (50..70).contains(&y) {
+                            checker(x, y, 4, [255, 255, 255], [40, 40, 40])
+                        } else {
+                            Rgba([0, 0, 0, 255])
+                        }
+                    });
+                    let opts = SmartCrop {
+                        width: 50,
+                        height: 50,
+                        ..SmartCrop::default()
+                    };
+                    let size = sc.crop_image(&img, &opts).top_crop.size;
+                    size.x <= subject_x && subject_x + 20 <= size.x + size.width
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.join().unwrap());
+        }
+    }
+
+    #[test]
+    fn face_size_weight_prefers_large_face() {
+        let img: RgbaImage = ImageBuffer::from_pixel(300, 150, Rgba([90, 90, 90, 255]));
+        let faces = vec![
+            CropSize {
+                x: 20,
+                y: 35,
+                width: 80,
+                height: 80,
+            },
+            CropSize {
+                x: 230,
+                y: 60,
+                width: 20,
+                height: 20,
+            },
+        ];
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let centered_on =
+            |size: &CropSize, x: u32| ((size.x + size.width / 2) as i64 - x as i64).abs() < 30;
+        let sc = SmartCrop {
+            faces,
+            ..SmartCrop::default()
+        };
+        let size = sc.crop_image(&img, &opts).top_crop.size;
+        assert!(centered_on(&size, 240), "{:?}", size);
+
+        let sc = SmartCrop {
+            face_size_weight: 2.0,
+            ..sc
+        };
+        let size = sc.crop_image(&img, &opts).top_crop.size;
+        assert!(centered_on(&size, 60), "{:?}", size);
+    }
+
+    #[test]
+    fn overlay_zone_keeps_face_clear() {
+        // textured face in the middle of a tall image, with a title bar over
+        // the bottom 40% of the output
+        let img = ImageBuffer::from_fn(150, 300, |x, y| {
+            let (dx, dy) = (x as f64 - 75., y as f64 - 150.);
+            if dx * dx + dy * dy < 25. * 25. {
+                checker(x, y, 2, [200, 160, 135], [150, 120, 101])
+            } else {
+                Rgba([100, 100, 100, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let face_position = |size: &CropSize| (150 - size.y) as f64 / size.height as f64;
+
+        let plain = SmartCrop::new().crop_image(&img, &opts).top_crop.size;
+        let sc = SmartCrop {
+            overlay_zones: vec![OverlayZone {
+                x: 0.,
+                y: 0.6,
+                width: 1.,
+                height: 0.4,
+            }],
+            ..SmartCrop::default()
+        };
+        let clear = sc.crop_image(&img, &opts).top_crop.size;
+        assert!(
+            face_position(&clear) < face_position(&plain),
+            "{:?} {:?}",
+            clear,
+            plain
+        );
+        assert!(face_position(&clear) < 0.45, "{:?}", clear);
+    }
+
+    #[test]
+    fn objective_fn_changes_the_winner() {
+        // detail on the left, a saturated patch on the right
+        let img = ImageBuffer::from_fn(160, 80, |x, y| {
+            if x < 80 {
+                checker(x, y, 2, [30, 30, 30], [220, 220, 220])
+            } else {
+                Rgba([230, 20, 20, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 80,
+            height: 80,
+            ..SmartCrop::default()
+        };
+
+        let default = SmartCrop::new().crop_image(&img, &opts);
+        assert!(default.top_crop.size.x < 40, "{:?}", default.top_crop.size);
+
+        let sc = SmartCrop {
+            objective_fn: Some(Objective::new(|score, _| score.saturation - score.detail)),
+            ..SmartCrop::default()
+        };
+        let custom = sc.crop_image(&img, &opts);
+        assert!(custom.top_crop.size.x > 40, "{:?}", custom.top_crop.size);
+
+        // an objective that is NaN everywhere still yields a crop
+        let sc = SmartCrop {
+            objective_fn: Some(Objective::new(|_, _| f64::NAN)),
+            ..SmartCrop::default()
+        };
+        let nan = sc.crop_image(&img, &opts);
+        assert!(!nan.saliency_coverage_fallback);
+        assert_eq!(nan.ranked().len(), nan.crops.len());
+    }
+
+    #[test]
+    fn lenient_decode_analyzes_truncated_jpeg() {
+        let img = ImageBuffer::from_fn(200, 160, |x, y| {
+            checker(x, y, 5, [40, 90, 160], [220, 200, 120])
+        });
+        let mut data = Vec::new();
+        ImageRgba8(img)
+            .write_to(&mut data, image::ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        let path = std::env::temp_dir().join("smartcrop_truncated.jpg");
+        std::fs::write(&path, &data[..data.len() / 2]).unwrap();
+
+        let opts = SmartCrop {
+            width: 40,
+            height: 40,
+            ..SmartCrop::default()
+        };
+        match SmartCrop::new().crop(&path, &opts) {
+            Err(SmartCropError::Image(_)) => {}
+            other => panic!("expected a decode error, got {:?}", other),
+        }
+
+        let sc = SmartCrop {
+            lenient_decode: true,
+            ..SmartCrop::default()
+        };
+        let result = sc.crop(&path, &opts).unwrap();
+        assert_eq!(result.image_width, 200);
+        assert!(result.image_height > 0 && result.image_height < 160);
+        let crop = &result.top_crop.size;
+        assert!(crop.y + crop.height <= result.image_height);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn score_surface_peaks_at_top_crop() {
+        let img = ImageBuffer::from_fn(120, 80, |x, y| {
+            let (dx, dy) = (x as f64 - 80., y as f64 - 30.);
+            if dx * dx + dy * dy < 15. * 15. {
+                checker(x, y, 2, [200, 160, 135], [170, 136, 115])
+            } else {
+                Rgba([120, 120, 120, 255])
+            }
+        });
+        let sc = SmartCrop {
+            step: 1,
+            min_scale: 1.,
+            max_scale: 1.,
+            ..SmartCrop::default()
+        };
+        let opts = SmartCrop {
+            width: 40,
+            height: 40,
+            ..SmartCrop::default()
+        };
+        let analysis = sc.prepare(&img, &opts);
+        let result = sc.rescore(&analysis, &opts);
+        let surface = sc.score_surface(&analysis, &opts, 1.);
+
+        assert_eq!(surface.dimensions(), analysis.output.dimensions());
+        let top = &result.prescaled_crop;
+        assert_eq!(surface.get_pixel(top.x, top.y)[0], 255);
+        let (w, h) = surface.dimensions();
+        assert_eq!(surface.get_pixel(w - 1, h - 1)[0], 0);
+    }
+
+    #[test]
+    fn dimension_multiple_aligns_candidates() {
+        let img = ImageBuffer::from_fn(300, 200, |x, y| {
+            checker(x, y, 3, [90, 90, 90], [170, 170, 170])
+        });
+        let sc = SmartCrop {
+            dimension_multiple: Some(16),
+            origin_multiple: Some(16),
+            ..SmartCrop::default()
+        };
+        let opts = SmartCrop {
+            width: 160,
+            height: 90,
+            ..SmartCrop::default()
+        };
+        let result = sc.crop_image(&img, &opts);
+
+        assert!(!result.crops.is_empty());
+        for crop in result.crops.iter().chain(Some(&result.top_crop)) {
+            let size = &crop.size;
+            assert_eq!(size.width % 16, 0, "{:?}", size);
+            assert_eq!(size.height % 16, 0, "{:?}", size);
+            assert_eq!(size.x % 16, 0, "{:?}", size);
+            assert_eq!(size.y % 16, 0, "{:?}", size);
+            assert!(size.x + size.width <= 300 && size.y + size.height <= 200);
+        }
+        let size = &result.top_crop.size;
+        let aspect = size.width as f64 / size.height as f64;
+        assert!((aspect - 160. / 90.).abs() < 0.2, "{:?}", size);
+    }
+
+    #[test]
+    fn off_face_skin_factor_prefers_skin_in_faces() {
+        // small face on the left, a larger skin colored object on the right
+        let img = ImageBuffer::from_fn(300, 150, |x, y| {
+            let (dx, dy) = (x as f64 - 60., y as f64 - 75.);
+            let (ox, oy) = (x as f64 - 220., y as f64 - 75.);
+            if dx * dx + dy * dy < 20. * 20. || ox * ox + oy * oy < 40. * 40. {
+                checker(x, y, 2, [200, 160, 135], [170, 136, 115])
+            } else {
+                Rgba([90, 90, 90, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop {
+            faces: vec![CropSize {
+                x: 40,
+                y: 55,
+                width: 40,
+                height: 40,
+            }],
+            boost_weight: 0.,
+            ..SmartCrop::preset(Preset::Portrait)
+        };
+        let size = sc.crop_image(&img, &opts).top_crop.size;
+        assert!(size.x + size.width / 2 > 150, "{:?}", size);
+
+        let amplified = SmartCrop {
+            face_skin_factor: 5.,
+            ..sc.clone()
+        };
+        let size = amplified.crop_image(&img, &opts).top_crop.size;
+        assert!(size.x + size.width / 2 < 150, "{:?}", size);
+
+        let sc = SmartCrop {
+            off_face_skin_factor: 0.2,
+            ..sc
+        };
+        let size = sc.crop_image(&img, &opts).top_crop.size;
+        assert!(size.x + size.width / 2 < 150, "{:?}", size);
+    }
+
+    #[test]
+    fn best_crop_region_is_full_resolution() {
+        let img = ImageRgb8(ImageBuffer::from_fn(600, 400, |x, y| {
+            let (dx, dy) = (x as f64 - 450., y as f64 - 200.);
+            if dx * dx + dy * dy < 75. * 75. {
+                Rgb([200, 160, 135])
+            } else {
+                Rgb([90, 90, 90])
+            }
+        }));
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop::new();
+        let region = sc.best_crop_region(&img, &opts).unwrap();
+        let result = sc.crop_image(&img, &opts);
+
+        assert!(result.prescale < 1.);
+        let size = result.top_crop.size;
+        assert_eq!(region.dimensions(), (size.width, size.height));
+        assert_eq!(
+            size,
+            result
+                .prescaled_crop
+                .unscale(result.prescale)
+                .clamp(600, 400)
+        );
+        assert_eq!(region.get_pixel(0, 0), img.get_pixel(size.x, size.y));
+    }
+
+    #[test]
+    fn aspect_tolerance_trades_aspect_for_composition() {
+        // detail 110 wide, just too wide for a square crop of the full height
+        let img = ImageBuffer::from_fn(300, 100, |x, y| {
+            if (100..210).contains(&x) {
+                checker(x, y, 3, [40, 40, 40], [220, 220, 220])
+            } else {
+                Rgba([128, 128, 128, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop {
+            step: 1,
+            ..SmartCrop::default()
+        };
+        let strict = sc.crop_image(&img, &opts);
+        assert_eq!(strict.aspect, 1.);
+
+        let sc = SmartCrop {
+            aspect_tolerance: 0.15,
+            ..sc
+        };
+        let result = sc.crop_image(&img, &opts);
+        let size = &result.top_crop.size;
+        assert!(result.top_crop.score.total > strict.top_crop.score.total);
+        assert!(size.width > size.height, "{:?}", size);
+        assert!((result.aspect - size.width as f64 / size.height as f64).abs() < 1e-9);
+        assert!(result.aspect <= 1.15 + 1e-9);
+    }
+
+    #[test]
+    fn from_config_applies_config_file() {
+        let json = r#"{
+            "version": 1,
+            "skin_weight": 0.0,
+            "step": 4,
+            "scale_step": 0.0,
+            "mode": "Fit"
+        }"#;
+        let cfg: CropConfig = serde_json::from_str(json).unwrap();
+        let sc = SmartCrop::from_config(&cfg);
+        assert_eq!(sc.skin_weight, 0.);
+        assert_eq!(sc.mode, CropMode::Fit);
+        assert_eq!(sc.detail_weight, SmartCrop::default().detail_weight);
+        assert_eq!(sc.to_config(), cfg);
+
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let rects = sc.candidate_rects(200, 100, &opts);
+        assert!(rects
+            .iter()
+            .all(|rect| rect.x % 4 == 0 && rect.width == 100));
+
+        assert!(serde_json::from_str::<CropConfig>(r#"{"skin_wieght": 0.0}"#).is_err());
+
+        // a zero step searches every pixel rather than panicking
+        let cfg: CropConfig = serde_json::from_str(r#"{"version": 1, "step": 0}"#).unwrap();
+        let rects = SmartCrop::from_config(&cfg).candidate_rects(110, 100, &opts);
+        assert!(rects.iter().any(|rect| rect.x == 1), "{:?}", rects);
+    }
+
+    #[test]
+    fn crop_larger_than_image_still_yields_a_crop() {
+        let img = ImageBuffer::from_fn(100, 80, |x, y| {
+            checker(x, y, 3, [90, 90, 90], [170, 170, 170])
+        });
+        let opts = SmartCrop {
+            crop_width: 300,
+            crop_height: 150,
+            ..SmartCrop::default()
+        };
+        let result = SmartCrop::new().crop_image(&img, &opts);
+        let size = &result.top_crop.size;
+        assert!(size.width > 0 && size.x + size.width <= 100, "{:?}", size);
+        assert!(size.height > 0 && size.y + size.height <= 80, "{:?}", size);
+        assert_eq!(size.width / size.height, 2);
+
+        let sc = SmartCrop {
+            min_scale: 1.5,
+            max_scale: 1.5,
+            ..SmartCrop::default()
+        };
+        let opts = SmartCrop {
+            width: 50,
+            height: 50,
+            ..SmartCrop::default()
+        };
+        let size = sc.crop_image(&img, &opts).top_crop.size;
+        assert!(
+            size.x + size.width <= 100 && size.y + size.height <= 80,
+            "{:?}",
+            size
+        );
+    }
+
+    #[test]
+    fn symmetry_weight_centers_subject() {
+        let img = ImageBuffer::from_fn(300, 150, |x, y| {
+            let (dx, dy) = (x as f64 - 110., y as f64 - 75.);
+            if dx * dx + dy * dy < 25. * 25. {
+                checker(x, y, 2, [200, 160, 135], [150, 120, 101])
+            } else {
+                Rgba([100, 100, 100, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let offset = |size: &CropSize| (size.x as f64 + size.width as f64 / 2. - 110.).abs();
+
+        let sc = SmartCrop::preset(Preset::Portrait);
+        let default = sc.crop_image(&img, &opts).top_crop.size;
+        let sc = SmartCrop {
+            symmetry_weight: 0.01,
+            ..sc
+        };
+        let symmetric = sc.crop_image(&img, &opts).top_crop.size;
+        assert!(
+            offset(&symmetric) < offset(&default),
+            "{:?} {:?}",
+            symmetric,
+            default
+        );
+        assert!(offset(&symmetric) < 10., "{:?}", symmetric);
+    }
+
+    #[test]
+    fn analyze_full_touches_no_files() {
+        let debug_files = ["debug.thumb.jpg", "edge.jpg", "skin.jpg", "sat.jpg"];
+        let modified = || {
+            debug_files
+                .iter()
+                .map(|file| {
+                    std::fs::metadata(file)
+                        .and_then(|meta| meta.modified())
+                        .ok()
+                })
+                .collect::<Vec<_>>()
+        };
+        let before = modified();
+
+        let img = ImageRgb8(ImageBuffer::from_fn(400, 200, |x, y| {
+            let (dx, dy) = (x as f64 - 300., y as f64 - 100.);
+            if dx * dx + dy * dy < 40. * 40. {
+                Rgb([200, 160, 135])
+            } else {
+                Rgb([90, 90, 90])
+            }
+        }));
+        let sc = SmartCrop {
+            debug: true,
+            ..SmartCrop::default()
+        };
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let output = sc.analyze_full(&img, &opts);
+
+        assert_eq!(modified(), before);
+        let size = output.result.top_crop.size;
+        assert!(size.x + size.width > 300 && size.x < 300, "{:?}", size);
+        let dimensions = output.edge.dimensions();
+        assert!(dimensions.0 < 400);
+        assert_eq!(output.skin.dimensions(), dimensions);
+        assert_eq!(output.saturation.dimensions(), dimensions);
+        let (x, y) = (
+            (300. * output.result.prescale) as u32,
+            (100. * output.result.prescale) as u32,
+        );
+        assert!(output.skin.get_pixel(x, y)[0] > 0);
+    }
+
+    #[test]
+    fn scales_cover_range_from_max() {
+        let close = |a: Vec<f64>, b: &[f64]| {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-9)
+        };
+        // the default range includes both ends
+        assert!(close(scales(0.9, 1.0, 0.1), &[1.0, 0.9]));
+        assert!(close(
+            scales(0.5, 1.0, 0.1),
+            &[1.0, 0.9, 0.8, 0.7, 0.6, 0.5]
+        ));
+        // a single scale
+        assert!(close(scales(1.0, 1.0, 0.1), &[1.0]));
+        // a step larger than the range
+        assert!(close(scales(0.9, 1.0, 0.5), &[1.0]));
+        // an uneven step stops above min_scale
+        assert!(close(scales(0.5, 1.0, 0.2), &[1.0, 0.8, 0.6]));
+        // no step, or an empty range
+        assert!(close(scales(0.5, 1.0, 0.), &[1.0]));
+        assert!(close(scales(1.0, 0.8, 0.1), &[0.8]));
+    }
+
+    #[test]
+    fn crop_to_slot_trims_instead_of_stretching() {
+        // a detail band wider than tall, so that a loose aspect picks a wide crop
+        let img = ImageBuffer::from_fn(300, 100, |x, y| {
+            if (80..220).contains(&x) {
+                checker(x, y, 10, [40, 40, 40], [220, 220, 220])
+            } else {
+                Rgba([128, 128, 128, 255])
+            }
+        });
+        let sc = SmartCrop {
+            aspect_tolerance: 0.5,
+            ..SmartCrop::default()
+        };
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let size = sc.crop_image(&img, &opts).top_crop.size;
+        assert!(size.width as f64 > size.height as f64 * 1.2, "{:?}", size);
+
+        let output = sc.crop_to_slot(&img, 100, 100, false);
+        assert_eq!(output.dimensions(), (100, 100));
+        // checker squares stay square: as many edges across as down
+        let edges = |pixels: Vec<Rgba<u8>>| {
+            pixels
+                .windows(2)
+                .filter(|pair| (pair[0][0] as i32 - pair[1][0] as i32).abs() > 90)
+                .count() as i32
+        };
+        let across = edges((0..100).map(|x| *output.get_pixel(x, 50)).collect());
+        let down = edges((0..100).map(|y| *output.get_pixel(50, y)).collect());
+        assert!((across - down).abs() <= 1, "{} {}", across, down);
+    }
+
+    #[test]
+    fn best_per_scale_has_each_scale_best() {
+        let img = ImageBuffer::from_fn(300, 200, |x, y| {
+            let (dx, dy) = (x as f64 - 200., y as f64 - 90.);
+            if dx * dx + dy * dy < 40. * 40. {
+                checker(x, y, 2, [200, 160, 135], [150, 120, 101])
+            } else {
+                checker(x, y, 7, [90, 90, 90], [110, 110, 110])
+            }
+        });
+        let sc = SmartCrop {
+            min_scale: 0.6,
+            scale_step: 0.1,
+            ..SmartCrop::default()
+        };
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let analysis = sc.prepare(&img, &opts);
+        let best = sc.best_per_scale(&analysis, &opts);
+        let all = sc.rescore(&analysis, &opts);
+
+        let expected = [1.0, 0.9, 0.8, 0.7, 0.6];
+        assert_eq!(best.len(), expected.len());
+        for ((scale, crop), expected) in best.iter().zip(expected.iter()) {
+            assert!((scale - expected).abs() < 1e-9);
+            let group: Vec<&CropInfo> = all
+                .crops
+                .iter()
+                .filter(|other| {
+                    other.size.width == crop.size.width && other.size.height == crop.size.height
+                })
+                .collect();
+            assert!(!group.is_empty(), "{:?}", crop.size);
+            assert!(group
+                .iter()
+                .all(|other| other.score.total <= crop.score.total));
+            assert!(group.iter().any(|other| other.size == crop.size));
+        }
+        assert!(best
+            .windows(2)
+            .all(|pair| pair[0].1.size.width > pair[1].1.size.width));
+    }
+
+    #[test]
+    fn center_bias_keeps_centered_subject() {
+        // a product in the middle and a busier, more colorful object at the edge
+        let img = ImageBuffer::from_fn(400, 150, |x, y| {
+            let (dx, dy) = (x as f64 - 200., y as f64 - 75.);
+            let (ox, oy) = (x as f64 - 350., y as f64 - 75.);
+            if dx * dx + dy * dy < 30. * 30. {
+                Rgba([60, 90, 160, 255])
+            } else if ox * ox + oy * oy < 40. * 40. {
+                checker(x, y, 2, [230, 30, 30], [30, 200, 40])
+            } else {
+                Rgba([240, 240, 240, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let center = |size: &CropSize| size.x as f64 + size.width as f64 / 2.;
+
+        let size = SmartCrop::new().crop_image(&img, &opts).top_crop.size;
+        assert!(center(&size) > 275., "{:?}", size);
+
+        let sc = SmartCrop {
+            center_bias: 0.01,
+            ..SmartCrop::default()
+        };
+        let size = sc.crop_image(&img, &opts).top_crop.size;
+        assert!((center(&size) - 200.).abs() < 20., "{:?}", size);
+    }
+
+    #[test]
+    fn negative_space_weight_leaves_room_above_subject() {
+        // textured ground along the bottom under a plain sky
+        let img = ImageBuffer::from_fn(300, 300, |x, y| {
+            if y >= 200 {
+                checker(x, y, 3, [60, 110, 50], [120, 170, 90])
+            } else {
+                Rgba([150, 190, 230, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 200,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sky = |size: &CropSize| 200u32.saturating_sub(size.y) as f64 / size.height as f64;
+
+        let packed = SmartCrop::new().crop_image(&img, &opts).top_crop.size;
+        let sc = SmartCrop {
+            negative_space_weight: 0.01,
+            ..SmartCrop::default()
+        };
+        let airy = sc.crop_image(&img, &opts).top_crop.size;
+        assert!(sky(&airy) > sky(&packed) + 0.1, "{:?} {:?}", airy, packed);
+    }
+
+    #[test]
+    fn crop_size_intersect_and_iou() {
+        let rect = |x, y, width, height| CropSize {
+            x,
+            y,
+            width,
+            height,
+        };
+        let a = rect(0, 0, 100, 50);
+        assert_eq!(a.to_tuple(), (0, 0, 100, 50));
+        assert!(a.contains(0, 0) && a.contains(99, 49));
+        assert!(!a.contains(100, 0) && !a.contains(0, 50));
+
+        // overlapping
+        let b = rect(50, 25, 100, 50);
+        assert_eq!(a.intersect(&b), Some(rect(50, 25, 50, 25)));
+        assert_eq!(b.intersect(&a), a.intersect(&b));
+        assert!((a.iou(&b) - 1250. / 8750.).abs() < 1e-12);
+        // nested
+        let c = rect(10, 10, 20, 20);
+        assert_eq!(a.intersect(&c), Some(c.clone()));
+        assert!((a.iou(&c) - 400. / 5000.).abs() < 1e-12);
+        assert_eq!(a.iou(&a), 1.);
+        // touching along an edge
+        let d = rect(100, 0, 20, 50);
+        assert_eq!(a.intersect(&d), None);
+        assert_eq!(a.iou(&d), 0.);
+        // disjoint
+        let e = rect(200, 200, 10, 10);
+        assert_eq!(a.intersect(&e), None);
+        assert_eq!(a.iou(&e), 0.);
+    }
+
+    #[test]
+    fn crop_thumbnail_returns_full_resolution_crop() {
+        let original = ImageBuffer::from_fn(1000, 600, |x, y| {
+            let (dx, dy) = (x as f64 - 750., y as f64 - 300.);
+            if dx * dx + dy * dy < 120. * 120. {
+                checker(x, y, 20, [200, 160, 135], [150, 120, 101])
+            } else {
+                Rgba([90, 90, 90, 255])
+            }
+        });
+        let thumbnail = box_resize(&original, 100, 60);
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop::new();
+        let result = sc.crop_thumbnail(&thumbnail, 1000, 600, &opts);
+
+        assert!((result.prescale - 0.1).abs() < 1e-9);
+        assert_eq!((result.image_width, result.image_height), (1000, 600));
+        let size = &result.top_crop.size;
+        assert!(size.x + size.width <= 1000 && size.y + size.height <= 600);
+        assert!(size.width >= 500 && size.height >= 500, "{:?}", size);
+        assert!(size.x <= 750 && 750 < size.x + size.width, "{:?}", size);
+        assert_eq!(size, &result.prescaled_crop.unscale(0.1).clamp(1000, 600));
+    }
+
+    #[test]
+    fn must_contain_restricts_candidates() {
+        // a busy subject on the left; the logo in the top right corner is plain
+        let img = ImageBuffer::from_fn(300, 150, |x, y| {
+            let (dx, dy) = (x as f64 - 60., y as f64 - 75.);
+            if dx * dx + dy * dy < 40. * 40. {
+                checker(x, y, 2, [200, 160, 135], [150, 120, 101])
+            } else {
+                Rgba([100, 100, 100, 255])
+            }
+        });
+        let logo = CropSize {
+            x: 270,
+            y: 5,
+            width: 20,
+            height: 20,
+        };
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop {
+            constraints: Constraints {
+                must_contain: vec![logo.clone()],
+                ..Constraints::default()
+            },
+            ..SmartCrop::default()
+        };
+        let result = sc.crop_image(&img, &opts);
+        assert!(!result.constraints_unmet);
+        assert!(!result.crops.is_empty());
+        for crop in result.crops.iter().chain(Some(&result.top_crop)) {
+            assert_eq!(
+                crop.size.intersect(&logo),
+                Some(logo.clone()),
+                "{:?}",
+                crop.size
+            );
+        }
+        let best = result.ranked()[0];
+        assert_eq!(best.size, result.top_crop.size);
+
+        // a box no crop at the target aspect can contain along with another
+        let sc = SmartCrop {
+            constraints: Constraints {
+                must_contain: vec![
+                    logo,
+                    CropSize {
+                        x: 5,
+                        y: 5,
+                        width: 20,
+                        height: 20,
+                    },
+                ],
+                ..Constraints::default()
+            },
+            ..SmartCrop::default()
+        };
+        assert!(sc.crop_image(&img, &opts).constraints_unmet);
+        let img = ImageRgba8(img);
+        match sc.best_crop_region(&img, &opts) {
+            Err(SmartCropError::ConstraintsUnmet) => {}
+            other => panic!("expected ConstraintsUnmet, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn constraints_hold_for_aligned_crops() {
+        let img = image::open(Path::new("test.jpg")).unwrap();
+        let opts = SmartCrop {
+            width: 160,
+            height: 90,
+            ..SmartCrop::default()
+        };
+        let constrained = |required: &CropSize| SmartCrop {
+            dimension_multiple: Some(64),
+            constraints: Constraints {
+                must_contain: vec![required.clone()],
+                ..Constraints::default()
+            },
+            ..SmartCrop::default()
+        };
+        let required = CropSize {
+            x: 200,
+            y: 160,
+            width: 10,
+            height: 10,
+        };
+        let result = constrained(&required).crop_image(&img, &opts);
+        assert!(!result.constraints_unmet);
+        for crop in result.crops.iter().chain(Some(&result.top_crop)) {
+            assert_eq!(crop.size.width % 64, 0, "{:?}", crop.size);
+            assert_eq!(
+                crop.size.intersect(&required),
+                Some(required.clone()),
+                "{:?}",
+                crop.size
+            );
+        }
+
+        // candidates reaching the box lose it when aligning shrinks them
+        let required = CropSize { x: 360, ..required };
+        let result = constrained(&required).crop_image(&img, &opts);
+        assert!(result.constraints_unmet);
+    }
+
+    #[test]
+    fn composite_background_changes_edges() {
+        // a white square on a transparent background
+        let img = ImageRgba8(ImageBuffer::from_fn(200, 200, |x, y| {
+            if (60..140).contains(&x) && (60..140).contains(&y) {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        }));
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let edges = |background: Option<Rgb<u8>>| {
+            let sc = SmartCrop {
+                composite_background: background,
+                ..SmartCrop::default()
+            };
+            let edge = sc.analyze_full(&img, &opts).edge;
+            // the detector passes the image border through as lightness
+            let (w, h) = edge.dimensions();
+            edge.enumerate_pixels()
+                .filter(|&(x, y, _)| x > 0 && y > 0 && x < w - 1 && y < h - 1)
+                .map(|(_, _, p)| p[0] as u64)
+                .sum::<u64>()
+        };
+        let black = edges(Some(Rgb([0, 0, 0])));
+        let white = edges(Some(Rgb([255, 255, 255])));
+        assert!(white * 10 < black, "{} {}", white, black);
+    }
+
+    #[test]
+    fn crop_confidence_reflects_gain_over_center_crop() {
+        let subject_at = |cx: f64| {
+            ImageBuffer::from_fn(300, 150, move |x, y| {
+                let (dx, dy) = (x as f64 - cx, y as f64 - 75.);
+                if dx * dx + dy * dy < 50. * 50. {
+                    checker(x, y, 2, [200, 160, 135], [150, 120, 101])
+                } else {
+                    Rgba([100, 100, 100, 255])
+                }
+            })
+        };
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop::new();
+
+        let centered = sc.prepare(&subject_at(150.), &opts);
+        let confidence = sc.crop_confidence(&centered, &opts);
+        assert!(confidence < 0.1, "{}", confidence);
+
+        let off_center = sc.prepare(&subject_at(55.), &opts);
+        let confidence = sc.crop_confidence(&off_center, &opts);
+        assert!(confidence > 0.5, "{}", confidence);
+    }
+
+    #[test]
+    fn crop_to_file_round_trips_formats() {
+        let img = ImageRgb8(ImageBuffer::from_fn(300, 200, |x, y| {
+            let (dx, dy) = (x as f64 - 200., y as f64 - 100.);
+            if dx * dx + dy * dy < 50. * 50. {
+                Rgb([200, 160, 135])
+            } else {
+                Rgb([90, 90, 90])
+            }
+        }));
+        let opts = SmartCrop {
+            width: 120,
+            height: 80,
+            ..SmartCrop::default()
+        };
+        let dir = std::env::temp_dir();
+        for (format, extension) in [
+            (OutputFormat::Jpeg { quality: 80 }, "jpg"),
+            (OutputFormat::Png, "png"),
+        ]
+        .iter()
+        {
+            let sc = SmartCrop {
+                output_format: *format,
+                ..SmartCrop::default()
+            };
+            let path = dir.join(format!(
+                "smartcrop-out-{}.{}",
+                std::process::id(),
+                extension
+            ));
+            sc.crop_to_file(&img, &path, &opts).unwrap();
+            let saved = image::open(&path).unwrap();
+            assert_eq!(saved.dimensions(), (120, 80), "{:?}", format);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn box_score_map_averages_channels() {
+        // hard edged blocks of detail, whose Lanczos downsample rings
+        let img = ImageBuffer::from_fn(256, 128, |x, y| {
+            if (x / 24 + y / 24) % 2 == 0 {
+                checker(x, y, 1, [0, 0, 0], [255, 255, 255])
+            } else {
+                Rgba([128, 128, 128, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 128,
+            height: 128,
+            ..SmartCrop::default()
+        };
+        // largest deviation of a score map sample from the mean of the
+        // detail channel in its block
+        let deviation = |sc: &SmartCrop| {
+            let analysis = sc.prepare(&img, &opts);
+            let ds = analysis.score_down_sample;
+            assert_eq!(analysis.output.dimensions(), (256, 128));
+            analysis
+                .score_map
+                .pixels()
+                .map(|(x, y, pixel)| {
+                    let mut sum = 0.;
+                    for by in y * ds..(y + 1) * ds {
+                        for bx in x * ds..(x + 1) * ds {
+                            sum += analysis.output.get_pixel(bx, by)[1] as f64;
+                        }
+                    }
+                    (pixel[1] as f64 - sum / (ds * ds) as f64).abs()
+                })
+                .fold(0., f64::max)
+        };
+
+        let boxed = deviation(&SmartCrop {
+            box_score_map: true,
+            ..SmartCrop::default()
+        });
+        let lanczos = deviation(&SmartCrop::default());
+        assert!(boxed <= 1., "{}", boxed);
+        assert!(lanczos > 10., "{}", lanczos);
+    }
+
+    #[test]
+    fn load_image_accepts_bmp_and_rejects_animation() {
+        let frame = |shade: u8| {
+            ImageBuffer::from_fn(120, 80, move |x, y| {
+                checker(x, y, 4, [shade, shade, shade], [200, 160, 135])
+            })
+        };
+        let opts = SmartCrop {
+            width: 40,
+            height: 40,
+            ..SmartCrop::default()
+        };
+        let dir = std::env::temp_dir();
+
+        let bmp = dir.join("smartcrop_load.bmp");
+        ImageRgb8(ImageRgba8(frame(30)).to_rgb8())
+            .save(&bmp)
+            .unwrap();
+        let result = SmartCrop::new().crop(&bmp, &opts).unwrap();
+        assert_eq!((result.image_width, result.image_height), (120, 80));
+        std::fs::remove_file(&bmp).unwrap();
+
+        let gif = dir.join("smartcrop_load.gif");
+        {
+            let file = File::create(&gif).unwrap();
+            let mut encoder = image::codecs::gif::GifEncoder::new(file);
+            encoder
+                .encode_frames(vec![
+                    image::Frame::new(frame(30)),
+                    image::Frame::new(frame(90)),
+                ])
+                .unwrap();
+        }
+        match SmartCrop::new().crop(&gif, &opts) {
+            Err(SmartCropError::UnsupportedForAnalysis(_)) => {}
+            other => panic!("expected UnsupportedForAnalysis, got {:?}", other),
+        }
+        std::fs::remove_file(&gif).unwrap();
+    }
+
+    #[test]
+    fn low_key_detects_dark_skin() {
+        // a dimly lit face on the left and some dark texture on the right
+        let img = ImageRgba8(ImageBuffer::from_fn(300, 150, |x, y| {
+            let (dx, dy) = (x as f64 - 70., y as f64 - 75.);
+            if dx * dx + dy * dy < 35. * 35. {
+                Rgba([52, 38, 30, 255])
+            } else if x > 200 {
+                checker(x, y, 3, [15, 15, 18], [45, 45, 50])
+            } else {
+                Rgba([12, 12, 15, 255])
+            }
+        }));
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop::preset(Preset::Portrait);
+        let skin_at_face = |output: &AnalysisOutput| {
+            let prescale = output.result.prescale;
+            output
+                .skin
+                .get_pixel((70. * prescale) as u32, (75. * prescale) as u32)[0]
+        };
+
+        let output = sc.analyze_full(&img, &opts);
+        assert_eq!(skin_at_face(&output), 0);
+        let size = &output.result.top_crop.size;
+        assert!(size.x + size.width / 2 > 150, "{:?}", size);
+
+        let sc = SmartCrop {
+            low_key: true,
+            ..sc
+        };
+        let output = sc.analyze_full(&img, &opts);
+        assert!(skin_at_face(&output) > 0);
+        let size = &output.result.top_crop.size;
+        assert!(size.x + size.width / 2 < 150, "{:?}", size);
+    }
+
+    #[test]
+    fn saliency_grid_peaks_at_subject() {
+        let img = ImageBuffer::from_fn(300, 200, |x, y| {
+            let (dx, dy) = (x as f64 - 250., y as f64 - 40.);
+            if dx * dx + dy * dy < 25. * 25. {
+                checker(x, y, 2, [200, 160, 135], [150, 120, 101])
+            } else {
+                Rgba([100, 100, 100, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop::new();
+        let analysis = sc.prepare(&img, &opts);
+        let grid = sc.saliency_grid(&analysis, 3, 2);
+
+        assert_eq!(grid.len(), 2);
+        assert!(grid.iter().all(|row| row.len() == 3));
+        let peak = grid[0][2];
+        for (row, values) in grid.iter().enumerate() {
+            for (col, &value) in values.iter().enumerate() {
+                if (row, col) != (0, 2) {
+                    assert!(value < peak, "{:?}", grid);
+                }
+            }
+        }
+
+        assert!(sc.saliency_grid(&analysis, 0, 2).is_empty());
+        assert!(sc.saliency_grid(&analysis, 3, 0).is_empty());
+    }
+
+    #[test]
+    fn local_contrast_detects_locally_vivid_patch() {
+        // a muted image: a mildly colorful patch on the left, grey texture on
+        // the right
+        let img = ImageRgba8(ImageBuffer::from_fn(300, 150, |x, y| {
+            if (40..100).contains(&x) && (45..105).contains(&y) {
+                Rgba([170, 100, 90, 255])
+            } else if x > 200 {
+                checker(x, y, 3, [105, 105, 108], [135, 135, 138])
+            } else {
+                Rgba([120, 115, 112, 255])
+            }
+        }));
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop::preset(Preset::Product);
+        let saturation_at_patch = |output: &AnalysisOutput| {
+            let prescale = output.result.prescale;
+            output
+                .saturation
+                .get_pixel((70. * prescale) as u32, (75. * prescale) as u32)[0]
+        };
+
+        let output = sc.analyze_full(&img, &opts);
+        assert_eq!(saturation_at_patch(&output), 0);
+        let size = &output.result.top_crop.size;
+        assert!(size.x + size.width / 2 > 150, "{:?}", size);
+
+        let sc = SmartCrop {
+            local_contrast_radius: 40,
+            ..sc
+        };
+        let output = sc.analyze_full(&img, &opts);
+        assert!(saturation_at_patch(&output) > 0);
+        let size = &output.result.top_crop.size;
+        assert!(size.x + size.width / 2 < 150, "{:?}", size);
+    }
+
+    #[test]
+    fn search_plan_matches_candidate_rects() {
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop {
+            min_scale: 0.6,
+            ..SmartCrop::default()
+        };
+        let plan = sc.search_plan(640, 480, &opts);
+        assert_eq!(plan.candidates, sc.candidate_rects(640, 480, &opts).len());
+        assert_eq!(plan.scales.len(), 5);
+        assert_eq!((plan.step_x, plan.step_y), (8, 8));
+        assert_eq!(plan.crop_width, plan.crop_height);
+        assert!(plan.prescale < 1.);
+
+        let sc = SmartCrop { step_x: 4, ..sc };
+        let plan = sc.search_plan(640, 480, &opts);
+        assert_eq!(plan.candidates, sc.candidate_rects(640, 480, &opts).len());
+        assert_eq!((plan.step_x, plan.step_y), (4, 8));
+    }
+
+    #[test]
+    fn must_exclude_removes_overlapping_candidates() {
+        // a large busy subject on the left and a smaller one on the right
+        let img = ImageBuffer::from_fn(300, 150, |x, y| {
+            let (dl, dr) = (
+                (x as f64 - 60.).powi(2) + (y as f64 - 75.).powi(2),
+                (x as f64 - 230.).powi(2) + (y as f64 - 75.).powi(2),
+            );
+            if dl < 45. * 45. || dr < 25. * 25. {
+                checker(x, y, 2, [200, 160, 135], [150, 120, 101])
+            } else {
+                Rgba([100, 100, 100, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let result = SmartCrop::default().crop_image(&img, &opts);
+        assert!(
+            result.top_crop.size.contains(60, 75),
+            "{:?}",
+            result.top_crop.size
+        );
+
+        let competitor = CropSize {
+            x: 10,
+            y: 20,
+            width: 100,
+            height: 110,
+        };
+        let sc = SmartCrop {
+            constraints: Constraints {
+                must_exclude: vec![competitor.clone()],
+                ..Constraints::default()
+            },
+            ..SmartCrop::default()
+        };
+        let result = sc.crop_image(&img, &opts);
+        assert!(!result.constraints_unmet);
+        assert!(!result.crops.is_empty());
+        for crop in result.crops.iter().chain(Some(&result.top_crop)) {
+            assert_eq!(crop.size.intersect(&competitor), None, "{:?}", crop.size);
+        }
+        assert!(
+            result.top_crop.size.contains(230, 75),
+            "{:?}",
+            result.top_crop.size
+        );
+
+        // a box across the middle every square crop overlaps
+        let sc = SmartCrop {
+            constraints: Constraints {
+                must_exclude: vec![CropSize {
+                    x: 50,
+                    y: 0,
+                    width: 200,
+                    height: 150,
+                }],
+                ..Constraints::default()
+            },
+            ..SmartCrop::default()
+        };
+        assert!(sc.crop_image(&img, &opts).constraints_unmet);
+        let img = ImageRgba8(img);
+        match sc.best_crop_region(&img, &opts) {
+            Err(SmartCropError::ConstraintsUnmet) => {}
+            other => panic!("expected ConstraintsUnmet, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn band_mapping_selects_analyzed_bands() {
+        use tiff::encoder::{colortype, TiffEncoder};
+
+        // four bands: texture on the left in band 0 and on the right in band 3
+        let (width, height) = (200, 100);
+        let mut samples = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let texture = if (x / 2 + y / 2) % 2 == 0 { 220 } else { 30 };
+                let band0 = if x < 80 { texture } else { 120 };
+                let band3 = if x >= 120 { texture } else { 120 };
+                samples.extend_from_slice(&[band0, 120, 120, band3]);
+            }
+        }
+        let path = std::env::temp_dir().join(format!("smartcrop-bands-{}.tif", std::process::id()));
+        let mut encoder = TiffEncoder::new(File::create(&path).unwrap()).unwrap();
+        encoder
+            .write_image::<colortype::CMYK8>(width, height, &samples)
+            .unwrap();
+
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let crop_x = |mapping| {
+            let sc = SmartCrop {
+                band_mapping: Some(mapping),
+                ..SmartCrop::default()
+            };
+            sc.crop(&path, &opts).unwrap().top_crop.size.x
+        };
+        let left = crop_x([0, 0, 0]);
+        let right = crop_x([3, 3, 3]);
+        assert!(left < 20, "{}", left);
+        assert!(right > 80, "{}", right);
+
+        let sc = SmartCrop {
+            band_mapping: Some([0, 1, 4]),
+            ..SmartCrop::default()
+        };
+        match sc.crop(&path, &opts) {
+            Err(SmartCropError::InvalidBandMapping([0, 1, 4])) => {}
+            other => panic!("expected InvalidBandMapping, got {:?}", other.map(|_| ())),
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn near_tied_subjects_are_ambiguous() {
+        let subjects_at = |centers: &'static [f64]| {
+            ImageBuffer::from_fn(400, 150, move |x, y| {
+                let inside = centers.iter().any(|cx| {
+                    let (dx, dy) = (x as f64 - cx, y as f64 - 75.);
+                    dx * dx + dy * dy < 40. * 40.
+                });
+                if inside {
+                    checker(x, y, 2, [200, 160, 135], [150, 120, 101])
+                } else {
+                    Rgba([100, 100, 100, 255])
+                }
+            })
+        };
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop::default();
+
+        let result = sc.crop_image(&subjects_at(&[75., 325.]), &opts);
+        assert!(result.ambiguous, "{}", result.confidence_margin);
+        assert!(result.confidence_margin < sc.ambiguity_threshold);
+
+        let result = sc.crop_image(&subjects_at(&[75.]), &opts);
+        assert!(!result.ambiguous, "{}", result.confidence_margin);
+        assert!(
+            result.confidence_margin > 0.5,
+            "{}",
+            result.confidence_margin
+        );
+    }
+
+    #[test]
+    fn anchor_places_point_at_relative_position() {
+        let img = ImageBuffer::from_fn(900, 600, |x, y| {
+            let (dx, dy) = (x as f64 - 700., y as f64 - 150.);
+            if dx * dx + dy * dy < 80. * 80. {
+                checker(x, y, 4, [200, 160, 135], [150, 120, 101])
+            } else {
+                Rgba([100, 100, 100, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop {
+            min_scale: 0.6,
+            anchor: Some((333, 271, 1. / 3., 1. / 3.)),
+            ..SmartCrop::default()
+        };
+        let result = sc.crop_image(&img, &opts);
+        for crop in result.crops.iter().chain(Some(&result.top_crop)) {
+            // offset of the anchor from the upper-left third intersection
+            let size = &crop.size;
+            let dx = 333. - size.x as f64 - size.width as f64 / 3.;
+            let dy = 271. - size.y as f64 - size.height as f64 / 3.;
+            assert!(dx.abs() <= 1. && dy.abs() <= 1., "{:?}", size);
+        }
+        assert!(SmartCrop::default().crop_image(&img, &opts).top_crop.size.x > 333);
+    }
+
+    #[test]
+    fn sobel_responds_to_soft_diagonal_edges() {
+        // dark to light over a 20 pixel wide diagonal ramp
+        let img = ImageBuffer::from_fn(80, 80, |x, y| {
+            let t = ((x + y) as f64 - 70.) / 20.;
+            let v = (t.clamp(0., 1.) * 255.) as u8;
+            Rgba([v, v, v, 255])
+        });
+        let detail = |edge_operator| {
+            let sc = SmartCrop {
+                edge_operator,
+                ..SmartCrop::default()
+            };
+            let mut output = ImageBuffer::new(80, 80);
+            sc.detect_edge(&img, &mut output);
+            // leave out the border, which carries lightness instead of edges
+            output
+                .enumerate_pixels()
+                .filter(|(x, y, _)| (1..79).contains(x) && (1..79).contains(y))
+                .map(|(_, _, pixel)| pixel[1] as u64)
+                .sum::<u64>()
+        };
+        let laplacian = detail(EdgeOperator::Laplacian);
+        let sobel = detail(EdgeOperator::Sobel);
+        assert!(sobel > 5 * laplacian, "{} {}", sobel, laplacian);
+    }
+
+    #[test]
+    fn time_budget_returns_partial_result() {
+        let img = ImageBuffer::from_fn(400, 300, |x, y| {
+            let (dx, dy) = (x as f64 - 250., y as f64 - 150.);
+            if dx * dx + dy * dy < 50. * 50. {
+                checker(x, y, 4, [200, 160, 135], [150, 120, 101])
+            } else {
+                Rgba([100, 100, 100, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            prescale: false,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop {
+            min_scale: 0.5,
+            ..SmartCrop::default()
+        };
+        let analysis = sc.prepare(&img, &opts);
+        let full = sc.rescore(&analysis, &opts);
+        assert!(!full.partial);
+
+        let budgeted = SmartCrop {
+            candidate_budget: Some(60),
+            ..sc.clone()
+        };
+        let result = budgeted.rescore(&analysis, &opts);
+        assert!(result.partial);
+        assert_eq!(result.crops.len(), 60);
+        // the coarse sample scored first already finds the subject
+        assert!(
+            result.top_crop.size.contains(250, 150),
+            "{:?}",
+            result.top_crop.size
+        );
+
+        // a budget that doesn't run out changes nothing, not even the order
+        // of the candidates or which of tied ones wins
+        let generous = SmartCrop {
+            candidate_budget: Some(usize::MAX),
+            time_budget: Some(Duration::from_secs(3600)),
+            ..sc.clone()
+        };
+        let result = generous.rescore(&analysis, &opts);
+        assert!(!result.partial);
+        assert_eq!(result.top_crop.size, full.top_crop.size);
+        let sizes = |result: &CropResult| -> Vec<CropSize> {
+            result.crops.iter().map(|crop| crop.size.clone()).collect()
+        };
+        assert_eq!(sizes(&result), sizes(&full));
+
+        // a spent time budget stops at the first check
+        let spent = SmartCrop {
+            time_budget: Some(Duration::from_secs(0)),
+            ..sc
+        };
+        let result = spent.rescore(&analysis, &opts);
+        assert!(result.partial);
+        assert_eq!(result.crops.len(), TIME_BUDGET_CHECK_INTERVAL);
+
+        // a zero candidate budget still scores one
+        let empty = SmartCrop {
+            candidate_budget: Some(0),
+            time_budget: None,
+            ..spent
+        };
+        let result = empty.rescore(&analysis, &opts);
+        assert!(result.partial);
+        assert_eq!(result.crops.len(), 1);
+        assert!(!result.saliency_coverage_fallback);
+    }
+
+    #[test]
+    fn yuv420_frame_crops_like_rgb() {
+        let rgb = ImageBuffer::from_fn(320, 180, |x, y| {
+            let (dx, dy) = (x as f64 - 220., y as f64 - 90.);
+            if dx * dx + dy * dy < 45. * 45. {
+                checker(x, y, 2, [200, 160, 135], [150, 120, 101])
+            } else {
+                Rgba([60, 90, 140, 255])
+            }
+        });
+        // BT.601 video range, chroma averaged over 2x2 blocks
+        let (width, height) = rgb.dimensions();
+        let channels = |pixel: &Rgba<u8>| {
+            let (r, g, b) = (pixel[0] as f64, pixel[1] as f64, pixel[2] as f64);
+            (
+                16. + 0.257 * r + 0.504 * g + 0.098 * b,
+                128. - 0.148 * r - 0.291 * g + 0.439 * b,
+                128. + 0.439 * r - 0.368 * g - 0.071 * b,
+            )
+        };
+        let y_plane: Vec<u8> = rgb.pixels().map(|p| channels(p).0.round() as u8).collect();
+        let (mut u_plane, mut v_plane) = (Vec::new(), Vec::new());
+        for cy in 0..height / 2 {
+            for cx in 0..width / 2 {
+                let (mut u, mut v) = (0., 0.);
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+                    let (_, pu, pv) = channels(rgb.get_pixel(cx * 2 + dx, cy * 2 + dy));
+                    u += pu / 4.;
+                    v += pv / 4.;
+                }
+                u_plane.push(u.round() as u8);
+                v_plane.push(v.round() as u8);
+            }
+        }
+        let uv_plane: Vec<u8> = u_plane
+            .iter()
+            .zip(v_plane.iter())
+            .flat_map(|(&u, &v)| vec![u, v])
+            .collect();
+
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop::default();
+        let expected = sc.crop_image(&rgb, &opts).top_crop.size;
+        let strides = (width as usize, width as usize / 2);
+        let planar = sc
+            .crop_yuv420(&y_plane, &u_plane, &v_plane, strides, width, height, &opts)
+            .unwrap()
+            .top_crop
+            .size;
+        assert!(planar.iou(&expected) > 0.8, "{:?} {:?}", planar, expected);
+        let nv12 = sc
+            .crop_nv12(
+                &y_plane,
+                &uv_plane,
+                (width as usize, width as usize),
+                width,
+                height,
+                &opts,
+            )
+            .unwrap()
+            .top_crop
+            .size;
+        assert_eq!(nv12, planar);
+
+        // planes short of the frame are rejected rather than read past
+        let short = &y_plane[..y_plane.len() - 1];
+        match sc.crop_yuv420(short, &u_plane, &v_plane, strides, width, height, &opts) {
+            Err(SmartCropError::InvalidFrame(_)) => {}
+            other => panic!("expected InvalidFrame, got {:?}", other.map(|_| ())),
+        }
+        let nv12_strides = (width as usize, width as usize);
+        match sc.crop_nv12(&y_plane, &[], nv12_strides, width, height, &opts) {
+            Err(SmartCropError::InvalidFrame(_)) => {}
+            other => panic!("expected InvalidFrame, got {:?}", other.map(|_| ())),
+        }
+        match sc.crop_nv12(
+            &y_plane,
+            &uv_plane,
+            (width as usize, 4),
+            width,
+            height,
+            &opts,
+        ) {
+            Err(SmartCropError::InvalidFrame(_)) => {}
+            other => panic!("expected InvalidFrame, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn skin_strictness_ignores_brick_wall() {
+        // a face on the left and a brick wall, warm enough to pass as skin, on
+        // the right
+        let img = ImageBuffer::from_fn(400, 200, |x, y| {
+            let (dx, dy) = (x as f64 - 70., y as f64 - 100.);
+            if dx * dx + dy * dy < 40. * 40. {
+                checker(x, y, 3, [200, 160, 135], [170, 136, 115])
+            } else if x >= 220 {
+                let row = y / 20;
+                let mortar = y % 20 < 3 || (x + row % 2 * 20) % 40 < 3;
+                if mortar {
+                    Rgba([190, 190, 185, 255])
+                } else {
+                    Rgba([185, 110, 85, 255])
+                }
+            } else {
+                Rgba([90, 90, 90, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let lenient = SmartCrop::default().crop_image(&img, &opts).top_crop.size;
+        assert!(!lenient.contains(70, 100), "{:?}", lenient);
+
+        let sc = SmartCrop {
+            skin_strictness: 1.,
+            ..SmartCrop::default()
+        };
+        let strict = sc.crop_image(&img, &opts).top_crop.size;
+        assert!(strict.contains(70, 100), "{:?}", strict);
+    }
+
+    #[test]
+    fn bottom_left_origin_measures_y_from_bottom() {
+        // a subject near the top of a tall image
+        let img = ImageBuffer::from_fn(150, 300, |x, y| {
+            let (dx, dy) = (x as f64 - 75., y as f64 - 60.);
+            if dx * dx + dy * dy < 40. * 40. {
+                checker(x, y, 2, [200, 160, 135], [150, 120, 101])
+            } else {
+                Rgba([100, 100, 100, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let top_left = SmartCrop::default().crop_image(&img, &opts);
+        let sc = SmartCrop {
+            coordinate_origin: CoordinateOrigin::BottomLeft,
+            ..SmartCrop::default()
+        };
+        let bottom_left = sc.crop_image(&img, &opts);
+        let (a, b) = (&top_left.top_crop.size, &bottom_left.top_crop.size);
+        assert!(a.y < 30, "{:?}", a);
+        assert_eq!(b.y, 300 - a.y - a.height);
+        assert!(b.y > 100, "{:?}", b);
+        assert_eq!((b.x, b.width, b.height), (a.x, a.width, a.height));
+        assert_eq!(bottom_left.top_left(b), *a);
+        assert_eq!(bottom_left.top_crop_uv(), top_left.top_crop_uv());
+
+        let img = ImageRgba8(img);
+        let regions = (
+            SmartCrop::default().best_crop_region(&img, &opts).unwrap(),
+            sc.best_crop_region(&img, &opts).unwrap(),
+        );
+        assert_eq!(regions.0.to_rgba8(), regions.1.to_rgba8());
+    }
+
+    #[test]
+    fn crop_containing_holds_every_box() {
+        // three busy objects spread over the middle of a wide image, and one
+        // more at the right edge that no crop holds along with them
+        let objects = [(120., 60.), (180., 140.), (240., 80.), (560., 100.)];
+        let img = ImageBuffer::from_fn(600, 200, |x, y| {
+            let inside = objects.iter().any(|(cx, cy)| {
+                let (dx, dy) = (x as f64 - cx, y as f64 - cy);
+                dx * dx + dy * dy < 15. * 15.
+            });
+            if inside {
+                checker(x, y, 2, [200, 160, 135], [150, 120, 101])
+            } else {
+                Rgba([100, 100, 100, 255])
+            }
+        });
+        let boxes: Vec<CropSize> = objects[..3]
+            .iter()
+            .map(|(cx, cy)| CropSize {
+                x: *cx as u32 - 15,
+                y: *cy as u32 - 15,
+                width: 30,
+                height: 30,
+            })
+            .collect();
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop::default();
+        let result = sc.crop_containing(&img, &boxes, &opts).unwrap();
+        for b in boxes.iter() {
+            assert_eq!(result.top_crop.size.intersect(b).as_ref(), Some(b));
+        }
+        // the best of the candidates holding all three, which go down to
+        // smaller crops than the default search
+        assert_eq!(result.ranked()[0].size, result.top_crop.size);
+        assert!(result.crops.iter().any(|crop| crop.size.width < 180));
+        for crop in result.crops.iter() {
+            for b in boxes.iter() {
+                assert_eq!(crop.size.intersect(b).as_ref(), Some(b));
+            }
+        }
+
+        let far_apart = [
+            boxes[0].clone(),
+            CropSize {
+                x: 545,
+                y: 85,
+                width: 30,
+                height: 30,
+            },
+        ];
+        match sc.crop_containing(&img, &far_apart, &opts) {
+            Err(SmartCropError::ConstraintsUnmet) => {}
+            other => panic!("expected ConstraintsUnmet, got {:?}", other.map(|_| ())),
+        }
+
+        // boxes already in must_contain still have to be held
+        let sc = SmartCrop {
+            constraints: Constraints {
+                must_contain: vec![far_apart[1].clone()],
+                ..Constraints::default()
+            },
+            ..SmartCrop::default()
+        };
+        match sc.crop_containing(&img, &boxes[..1], &opts) {
+            Err(SmartCropError::ConstraintsUnmet) => {}
+            other => panic!("expected ConstraintsUnmet, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn skin_cap_lets_composition_through() {
+        // a face filling the frame, with dark features on its right half; only
+        // skin and center_bias count
+        let img = ImageBuffer::from_fn(450, 150, |x, y| {
+            if x < 225 {
+                checker(x, y, 2, [200, 160, 135], [170, 136, 115])
+            } else {
+                checker(x, y, 2, [200, 160, 135], [30, 24, 20])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop {
+            detail_weight: 0.,
+            saturation_weight: 0.,
+            center_bias: 0.01,
+            ..SmartCrop::default()
+        };
+        let uncapped = sc.crop_image(&img, &opts).top_crop.size;
+        let sc = SmartCrop {
+            skin_cap: Some(0.001),
+            ..sc
+        };
+        let capped = sc.crop_image(&img, &opts).top_crop.size;
+        // skin alone pulls the crop off center; capped, every candidate gets
+        // the same skin term and center_bias decides
+        let off_center = |c: &CropSize| (c.x as i64 * 2 + c.width as i64 - 450).abs();
+        assert!(off_center(&uncapped) > 20, "{:?}", uncapped);
+        assert!(off_center(&capped) <= 2, "{:?}", capped);
+    }
+
+    #[test]
+    fn crop_with_debug_returns_every_stage() {
+        let debug_files = ["debug.thumb.jpg", "edge.jpg", "skin.jpg", "sat.jpg"];
+        let modified = || {
+            debug_files
+                .iter()
+                .map(|file| {
+                    std::fs::metadata(file)
+                        .and_then(|meta| meta.modified())
+                        .ok()
+                })
+                .collect::<Vec<_>>()
+        };
+        let before = modified();
+
+        let img = ImageRgb8(ImageBuffer::from_fn(400, 200, |x, y| {
+            let (dx, dy) = (x as f64 - 300., y as f64 - 100.);
+            if dx * dx + dy * dy < 40. * 40. {
+                Rgb([200, 160, 135])
+            } else {
+                Rgb([90, 90, 90])
+            }
+        }));
+        let sc = SmartCrop {
+            debug: true,
+            ..SmartCrop::default()
+        };
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let (result, bundle) = sc.crop_with_debug(&img, &opts);
+
+        assert_eq!(modified(), before);
+        let (w, h) = bundle.edge.dimensions();
+        assert_eq!(
+            (w, h),
+            (
+                (400. * result.prescale).round() as u32,
+                (200. * result.prescale).round() as u32
+            )
+        );
+        assert_eq!(bundle.skin.dimensions(), (w, h));
+        assert_eq!(bundle.saturation.dimensions(), (w, h));
+        let down_sample = result.score_down_sample;
+        assert_eq!(
+            bundle.score_map.dimensions(),
+            (w.div_ceil(down_sample), h.div_ceil(down_sample))
+        );
+        assert!(bundle.skin.pixels().any(|p| p[0] > 0));
+        assert!(bundle.edge.pixels().any(|p| p[0] > 0));
+        assert_eq!(bundle.annotated.dimensions(), (400, 200));
+        let size = &result.top_crop.size;
+        assert_eq!(
+            bundle.annotated.get_pixel(size.x, size.y),
+            &Rgb([255, 0, 0])
+        );
+    }
+
+    #[test]
+    fn skin_subsample_approximates_full_mask() {
+        // soft edged skin blobs on a grey background
+        let img: RgbaImage = ImageBuffer::from_fn(800, 600, |x, y| {
+            let (dx, dy) = (x as f64 % 400. - 200., y as f64 % 300. - 150.);
+            let t = ((dx * dx + dy * dy).sqrt() / 120.).min(1.);
+            let mix = |a: f64, b: f64| (a * (1. - t) + b * t) as u8;
+            Rgba([mix(200., 100.), mix(160., 100.), mix(135., 100.), 255])
+        });
+        let detect = |sc: &SmartCrop| {
+            let mut output = ImageBuffer::new(800, 600);
+            let start = Instant::now();
+            sc.detect_skin(&img, &mut output);
+            (output, start.elapsed())
+        };
+        let (full, full_time) = detect(&SmartCrop::default());
+        let (subsampled, subsampled_time) = detect(&SmartCrop {
+            skin_subsample: 4,
+            ..SmartCrop::default()
+        });
+
+        let error = full
+            .pixels()
+            .zip(subsampled.pixels())
+            .map(|(a, b)| (a[0] as f64 - b[0] as f64).abs())
+            .sum::<f64>()
+            / full.pixels().map(|p| p[0] as f64).sum::<f64>();
+        assert!(error < 0.05, "{}", error);
+        assert!(
+            subsampled_time < full_time,
+            "{:?} {:?}",
+            subsampled_time,
+            full_time
+        );
+    }
+
+    #[test]
+    fn wide_panorama_finds_subject() {
+        // a 10000x500 panorama with a single subject at three quarters of its width
+        let img = ImageBuffer::from_fn(10000, 500, |x, y| {
+            let (dx, dy) = (x as f64 - 7500., y as f64 - 250.);
+            if dx * dx + dy * dy < 150. * 150. {
+                checker(x, y, 8, [200, 160, 135], [150, 120, 101])
+            } else {
+                Rgba([100, 100, 100, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let result = SmartCrop::default().crop_image(&img, &opts);
+        assert!(
+            result.top_crop.size.contains(7500, 250),
+            "{:?}",
+            result.top_crop.size
+        );
+
+        // unprescaled, the step along the panorama widens to keep the search bounded
+        let opts = SmartCrop {
+            prescale: false,
+            ..opts
+        };
+        let plan = SmartCrop::default().search_plan(10000, 500, &opts);
+        assert!(plan.candidates <= 4000, "{:?}", plan);
+        assert!(plan.step_x > 8 && plan.step_y == 8, "{:?}", plan);
+    }
+
+    #[test]
+    fn linear_edges_keep_srgb_brightness_gates() {
+        // a striped skin tone from dark to light, across skin_brightness_min
+        let img: RgbaImage = ImageBuffer::from_fn(64, 8, |x, y| {
+            let level = 0.15 + x as f64 / 64. * 0.5;
+            let pixel = [200. * level, 160. * level, 135. * level];
+            if y % 2 == 0 {
+                Rgba([pixel[0] as u8, pixel[1] as u8, pixel[2] as u8, 255])
+            } else {
+                Rgba([
+                    (pixel[0] * 0.8) as u8,
+                    (pixel[1] * 0.8) as u8,
+                    (pixel[2] * 0.8) as u8,
+                    255,
+                ])
+            }
+        });
+        let detect = |sc: &SmartCrop| {
+            let mut output = ImageBuffer::new(64, 8);
+            sc.detect_channels_into(&img, &mut output);
+            output
+        };
+        let srgb = detect(&SmartCrop::default());
+        let linear = detect(&SmartCrop {
+            linear_edges: true,
+            ..SmartCrop::default()
+        });
+        let passes = |output: &ImageBuffer<Rgb<u8>, Vec<u8>>| {
+            output.pixels().map(|p| p[0] > 0).collect::<Vec<_>>()
+        };
+        // some pixels are too dark for skin and some pass
+        assert!(passes(&srgb).contains(&true) && passes(&srgb).contains(&false));
+        assert_eq!(passes(&linear), passes(&srgb));
+        assert_ne!(
+            linear.pixels().map(|p| p[1]).collect::<Vec<_>>(),
+            srgb.pixels().map(|p| p[1]).collect::<Vec<_>>()
+        );
+
+        // gating in linear light too darkens every pixel, so fewer pass
+        let gated = detect(&SmartCrop {
+            linear_edges: true,
+            linear_brightness_gates: true,
+            ..SmartCrop::default()
+        });
+        let count = |passes: Vec<bool>| passes.into_iter().filter(|&p| p).count();
+        assert!(count(passes(&gated)) < count(passes(&srgb)));
+    }
+
+    #[test]
+    fn crop_delta_measures_config_change() {
+        let img = ImageRgba8(ImageBuffer::from_fn(300, 150, |x, y| {
+            let (dx, dy) = (x as f64 - 220., y as f64 - 75.);
+            if dx * dx + dy * dy < 40. * 40. {
+                checker(x, y, 2, [200, 160, 135], [150, 120, 101])
+            } else {
+                checker(x, y, 4, [110, 110, 110], [90, 90, 90])
+            }
+        }));
+        let a = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let same = SmartCrop::crop_delta(&img, &a, &a.clone());
+        assert_eq!(same.iou, 1.);
+        assert_eq!(same.score_delta, 0.);
+
+        // ignoring skin, the face no longer holds the crop
+        let b = SmartCrop {
+            skin_weight: 0.,
+            ..a.clone()
+        };
+        let delta = SmartCrop::crop_delta(&img, &a, &b);
+        assert!(delta.iou < 1., "{:?}", delta);
+        assert!(delta.score_delta != 0., "{:?}", delta);
+    }
+
+    #[test]
+    fn bilateral_filter_ignores_noise_keeps_edges() {
+        // grain on the left, a sharp edged dark square on the flat right
+        let img: RgbaImage = ImageBuffer::from_fn(240, 120, |x, y| {
+            let noise = (x.wrapping_mul(374_761_393) ^ y.wrapping_mul(668_265_263))
+                .wrapping_mul(1_274_126_177)
+                >> 24;
+            let value = if x < 120 {
+                (100 + noise % 41) as u8 - 20
+            } else if (150..210).contains(&x) && (30..90).contains(&y) {
+                30
+            } else {
+                100
+            };
+            Rgba([value, value, value, 255])
+        });
+        let detail = |sc: &SmartCrop| {
+            let mut output = ImageBuffer::new(240, 120);
+            sc.detect_channels_into(&img, &mut output);
+            output
+        };
+        let plain = SmartCrop {
+            skin_weight: 0.,
+            saturation_weight: 0.,
+            ..SmartCrop::default()
+        };
+        let filtered = SmartCrop {
+            bilateral_spatial_sigma: 2.,
+            bilateral_range_sigma: 30.,
+            ..plain.clone()
+        };
+        let sum = |output: &ImageBuffer<Rgb<u8>, Vec<u8>>, x0: u32, x1: u32| {
+            (0..120)
+                .flat_map(|y| (x0..x1).map(move |x| (x, y)))
+                .map(|(x, y)| output.get_pixel(x, y)[1] as f64)
+                .sum::<f64>()
+        };
+        let (before, after) = (detail(&plain), detail(&filtered));
+        assert!(sum(&after, 1, 119) < sum(&before, 1, 119) / 3.);
+        assert!(sum(&after, 121, 239) > sum(&before, 121, 239) * 0.8);
+
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let img = ImageRgba8(img);
+        let noisy = plain.crop_image(&img, &opts).top_crop.size;
+        assert!(noisy.x < 60, "{:?}", noisy);
+        let clean = filtered.crop_image(&img, &opts).top_crop.size;
+        assert!(clean.contains(180, 60), "{:?}", clean);
+    }
+
+    #[test]
+    fn crop_exports_geometry_and_css() {
+        let img: RgbaImage = ImageBuffer::from_pixel(400, 300, Rgba([100, 100, 100, 255]));
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let mut result = SmartCrop::default().crop_image(&img, &opts);
+        result.top_crop.size = CropSize {
+            x: 75,
+            y: 0,
+            width: 300,
+            height: 300,
+        };
+        assert_eq!(result.imagemagick_geometry(), "300x300+75+0");
+        assert_eq!(result.css_object_fit(), "cover");
+        assert_eq!(result.css_object_position().unwrap(), "75% 50%");
+
+        result.top_crop.size = CropSize {
+            x: 0,
+            y: 50,
+            width: 400,
+            height: 225,
+        };
+        assert_eq!(result.imagemagick_geometry(), "400x225+0+50");
+        assert_eq!(result.css_object_position().unwrap(), "50% 66.67%");
+
+        // cover would show more than a crop smaller on both axes
+        result.top_crop.size = CropSize {
+            x: 10,
+            y: 20,
+            width: 240,
+            height: 180,
+        };
+        assert_eq!(result.imagemagick_geometry(), "240x180+10+20");
+        assert_eq!(result.css_object_position(), None);
+    }
+
+    #[test]
+    fn skin_softness_reduces_threshold_sensitivity() {
+        // total response to similarities around the threshold, and how much of
+        // it a small threshold change takes away
+        let change = |softness: f64| {
+            let total = |skin_threshold: f64| {
+                let sc = SmartCrop {
+                    skin_threshold,
+                    skin_softness: softness,
+                    ..SmartCrop::default()
+                };
+                (0..100)
+                    .map(|i| sc.skin_confidence(0.78 + i as f64 * 0.0008))
+                    .sum::<f64>()
+            };
+            (total(0.8) - total(0.82)) / total(0.8)
+        };
+        assert!(
+            change(0.05) < change(0.) * 0.75,
+            "{} {}",
+            change(0.05),
+            change(0.)
+        );
+
+        // a small clear face on the left, a larger face barely above the
+        // threshold on the right
+        let img = ImageBuffer::from_fn(400, 200, |x, y| {
+            let (dx, dy) = (x as f64 - 80., y as f64 - 100.);
+            let (ex, ey) = (x as f64 - 290., y as f64 - 100.);
+            if dx * dx + dy * dy < 25. * 25. {
+                checker(x, y, 2, [200, 160, 135], [150, 120, 101])
+            } else if ex * ex + ey * ey < 40. * 40. {
+                checker(x, y, 2, [180, 160, 150], [135, 120, 112])
+            } else {
+                Rgba([100, 100, 100, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let pick = |skin_threshold: f64, skin_softness: f64| {
+            let sc = SmartCrop {
+                skin_threshold,
+                skin_softness,
+                ..SmartCrop::default()
+            };
+            sc.crop_image(&img, &opts).top_crop.size.contains(290, 100)
+        };
+        assert_ne!(pick(0.8, 0.), pick(0.82, 0.));
+        assert_eq!(pick(0.8, 0.05), pick(0.82, 0.05));
+    }
+
+    #[test]
+    fn depth_map_prefers_foreground() {
+        // a small subject in front on the left, a larger one in the background
+        // on the right
+        let img = ImageBuffer::from_fn(400, 200, |x, y| {
+            let (dx, dy) = (x as f64 - 80., y as f64 - 100.);
+            let (ex, ey) = (x as f64 - 300., y as f64 - 100.);
+            if dx * dx + dy * dy < 30. * 30. || ex * ex + ey * ey < 45. * 45. {
+                checker(x, y, 2, [200, 160, 135], [150, 120, 101])
+            } else {
+                Rgba([100, 100, 100, 255])
+            }
+        });
+        // at half the image's resolution
+        let depth = ImageBuffer::from_fn(200, 100, |x, y| {
+            let (dx, dy) = (x as f64 - 40., y as f64 - 50.);
+            if dx * dx + dy * dy < 20. * 20. {
+                Luma([1000u16])
+            } else {
+                Luma([9000u16])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let flat = SmartCrop::default().crop_image(&img, &opts).top_crop.size;
+        assert!(flat.contains(300, 100), "{:?}", flat);
+
+        let sc = SmartCrop {
+            depth_map: Some(depth),
+            depth_weight: 0.8,
+            ..SmartCrop::default()
+        };
+        let near = sc.crop_image(&img, &opts).top_crop.size;
+        assert!(near.contains(80, 100), "{:?}", near);
+    }
+
+    #[test]
+    fn warm_start_searches_around_previous_crop() {
+        let img = ImageBuffer::from_fn(400, 200, |x, y| {
+            let (dx, dy) = (x as f64 - 260., y as f64 - 100.);
+            if dx * dx + dy * dy < 40. * 40. {
+                checker(x, y, 2, [200, 160, 135], [150, 120, 101])
+            } else {
+                Rgba([100, 100, 100, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let full = SmartCrop::default().crop_image(&img, &opts);
+
+        // the subject hasn't moved, so the previous crop is still good
+        let sc = SmartCrop {
+            warm_start: Some(full.top_crop.size.clone()),
+            ..SmartCrop::default()
+        };
+        let warm = sc.crop_image(&img, &opts);
+        assert_eq!(warm.top_crop.size, full.top_crop.size);
+        assert!(warm.into_iter().count() * 5 < full.into_iter().count());
+
+        // the subject is far from the previous crop: the full search takes over
+        let sc = SmartCrop {
+            warm_start: Some(CropSize {
+                x: 0,
+                y: 0,
+                width: 200,
+                height: 200,
+            }),
+            ..SmartCrop::default()
+        };
+        let cold = sc.crop_image(&img, &opts);
+        assert_eq!(cold.top_crop.size, full.top_crop.size);
+        assert_eq!(cold.into_iter().count(), full.into_iter().count());
+
+        // a required box far from the previous crop: no crop around it holds
+        // the box, so the full search finds one that does
+        let required = CropSize {
+            x: 20,
+            y: 80,
+            width: 30,
+            height: 30,
+        };
+        let sc = SmartCrop {
+            warm_start: Some(full.top_crop.size.clone()),
+            constraints: Constraints {
+                must_contain: vec![required.clone()],
+                ..Constraints::default()
+            },
+            ..SmartCrop::default()
+        };
+        let result = sc.crop_image(&img, &opts);
+        assert!(!result.constraints_unmet);
+        assert_eq!(result.top_crop.size.intersect(&required), Some(required));
+    }
+
+    #[cfg(feature = "icc")]
+    #[test]
+    fn icc_profile_with_bogus_tag_count_is_rejected() {
+        let mut profile = vec![0; 132];
+        profile[16..20].copy_from_slice(b"RGB ");
+        profile[128..132].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(IccProfile::parse(&profile).is_none());
+    }
+
+    #[cfg(feature = "icc")]
+    #[test]
+    fn adobe_rgb_jpeg_is_converted_to_srgb() {
+        // a minimal Adobe RGB (1998) matrix/TRC profile
+        let s15 = |v: f64| ((v * 65536.).round() as i32).to_be_bytes();
+        let mut tags: Vec<(&[u8], Vec<u8>)> = Vec::new();
+        for (signature, xyz) in [
+            (b"rXYZ", [0.6097, 0.3111, 0.0195]),
+            (b"gXYZ", [0.2053, 0.6257, 0.0609]),
+            (b"bXYZ", [0.1492, 0.0632, 0.7446]),
+        ]
+        .iter()
+        {
+            let mut data = b"XYZ \0\0\0\0".to_vec();
+            xyz.iter().for_each(|&v| data.extend_from_slice(&s15(v)));
+            tags.push((&signature[..], data));
+        }
+        for signature in [b"rTRC", b"gTRC", b"bTRC"].iter() {
+            // gamma 563 / 256
+            let data = [&b"curv\0\0\0\0"[..], &[0, 0, 0, 1, 0x02, 0x33]].concat();
+            tags.push((&signature[..], data));
+        }
+        let mut profile = vec![0u8; 128];
+        profile[16..20].copy_from_slice(b"RGB ");
+        profile.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+        let mut offset = 132 + tags.len() * 12;
+        for (signature, data) in tags.iter() {
+            profile.extend_from_slice(signature);
+            profile.extend_from_slice(&(offset as u32).to_be_bytes());
+            profile.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            offset += data.len();
+        }
+        tags.iter()
+            .for_each(|(_, data)| profile.extend_from_slice(data));
+
+        // a pale skin tone, encoded and tagged in an APP2 segment after SOI
+        let raw = ImageRgb8(ImageBuffer::from_pixel(64, 64, Rgb([180, 160, 150])));
+        let mut jpeg = Vec::new();
+        raw.write_to(&mut jpeg, image::ImageOutputFormat::Jpeg(100))
+            .unwrap();
+        let mut segment = vec![0xFF, 0xE2];
+        segment.extend_from_slice(&((2 + 14 + profile.len()) as u16).to_be_bytes());
+        segment.extend_from_slice(b"ICC_PROFILE\0\x01\x01");
+        segment.extend_from_slice(&profile);
+        jpeg.splice(2..2, segment);
+        let path =
+            std::env::temp_dir().join(format!("smartcrop-adobe-rgb-{}.jpg", std::process::id()));
+        std::fs::write(&path, &jpeg).unwrap();
+
+        let untagged = image::open(&path).unwrap().to_rgba8();
+        let converted = load_image(&path, false, None).unwrap().to_rgba8();
+        std::fs::remove_file(&path).unwrap();
+        let (before, after) = (*untagged.get_pixel(32, 32), *converted.get_pixel(32, 32));
+        assert_ne!(before, after);
+        // Adobe RGB values are more saturated in sRGB, and closer to skin
+        assert!(after[0] > before[0] + 5, "{:?} {:?}", before, after);
+        let sc = SmartCrop::default();
+        assert!(sc.skin_response(after) > sc.skin_response(before) + 10);
+    }
+
+    #[test]
+    fn alpha_shape_frames_cutout() {
+        // an opaque, colorful disk on a transparent background, with noise in
+        // the invisible color of the transparent part
+        let img = ImageBuffer::from_fn(500, 300, |x, y| {
+            let (dx, dy) = (x as f64 - 320., y as f64 - 150.);
+            if dx * dx + dy * dy < 60. * 60. {
+                Rgba([40, 120, 200, 255])
+            } else {
+                let mut pixel = checker(x, y, 3, [200, 160, 135], [20, 20, 20]);
+                pixel[3] = 0;
+                pixel
+            }
+        });
+        let sc = SmartCrop {
+            alpha_shape: true,
+            min_scale: 0.3,
+            ..SmartCrop::default()
+        };
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let size = sc.crop_image(&img, &opts).top_crop.size;
+        // the whole disk, with a margin of at most half its size
+        assert!(size.x <= 260 && size.x + size.width >= 380, "{:?}", size);
+        assert!(size.y <= 90 && size.y + size.height >= 210, "{:?}", size);
+        assert!(size.width <= 180, "{:?}", size);
+    }
+
+    #[test]
+    fn compensated_sum_ranks_near_ties_like_f64() {
+        // two halves of a detail map with nearly the same score: the left one
+        // bright on top, so the f32 sum is large before its many faint samples
+        // are added, the right one bright at the bottom and a hair stronger
+        let (w, h) = (1200, 600);
+        let map = ImageRgb8(ImageBuffer::from_fn(w, h, |x, y| {
+            let detail = if x < w / 2 {
+                if y < h / 2 {
+                    250
+                } else {
+                    3
+                }
+            } else if y < h / 2 {
+                3
+            } else if (y - h / 2) * (w / 2) + x - w / 2 < 4000 {
+                235
+            } else {
+                234
+            };
+            Rgb([0, detail, 0])
+        }));
+        let left = CropSize {
+            x: 0,
+            y: 0,
+            width: w / 2,
+            height: h,
+        };
+        let right = CropSize { x: w / 2, ..left };
+        let sc = SmartCrop {
+            score_down_sample: 1,
+            outside_importance: 0.,
+            skin_weight: 0.,
+            saturation_weight: 0.,
+            ..SmartCrop::default()
+        };
+        let prefers_right =
+            |sc: &SmartCrop| sc.get_score(&map, &right).total > sc.get_score(&map, &left).total;
+        assert!(prefers_right(&sc));
+        let naive = SmartCrop {
+            f32_scoring: true,
+            ..sc.clone()
+        };
+        assert!(!prefers_right(&naive));
+        let compensated = SmartCrop {
+            f32_scoring: true,
+            compensated_sum: true,
+            ..sc.clone()
+        };
+        assert!(prefers_right(&compensated));
+        let (a, b) = (
+            compensated.get_score(&map, &left).total,
+            sc.get_score(&map, &left).total,
+        );
+        assert!((a - b).abs() < b * 1e-7, "{} vs {}", a, b);
+    }
+
+    #[test]
+    fn crop_constrained_meets_every_constraint() {
+        // a large subject on the left and a small one on the right that the
+        // crop is required to hold
+        let img = ImageBuffer::from_fn(400, 300, |x, y| {
+            let near = |cx: f64, cy: f64, r: f64| {
+                let (dx, dy) = (x as f64 - cx, y as f64 - cy);
+                dx * dx + dy * dy < r * r
+            };
+            if near(70., 150., 50.) || near(330., 150., 20.) {
+                checker(x, y, 2, [200, 160, 135], [150, 120, 101])
+            } else {
+                Rgba([100, 100, 100, 255])
+            }
+        });
+        let opts = SmartCrop {
+            width: 160,
+            height: 90,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop {
+            min_scale: 0.5,
+            ..SmartCrop::default()
+        };
+        let subject = CropSize {
+            x: 305,
+            y: 125,
+            width: 50,
+            height: 50,
+        };
+        let constraints = Constraints {
+            min_area: Some(0.3),
+            max_area: Some(0.5),
+            aspect: Some((16. / 9., 0.02)),
+            must_contain: vec![subject.clone()],
+            must_exclude: Vec::new(),
+        };
+        let result = sc.crop_constrained(&img, &constraints, &opts).unwrap();
+        let size = &result.top_crop.size;
+        let area = (size.width * size.height) as f64 / (400. * 300.);
+        assert!((0.3..=0.5).contains(&area), "{:?}", size);
+        let aspect = size.width as f64 / size.height as f64;
+        assert!((aspect / (16. / 9.) - 1.).abs() <= 0.02, "{:?}", size);
+        assert_eq!(size.intersect(&subject), Some(subject));
+        assert!(constraints.satisfied_by(size, 400, 300));
+
+        let impossible = Constraints {
+            max_area: Some(0.01),
+            ..constraints
+        };
+        match sc.crop_constrained(&img, &impossible, &opts) {
+            Err(SmartCropError::ConstraintsUnmet) => {}
+            other => panic!("expected ConstraintsUnmet, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn padded_crop_fills_overflow_at_top() {
+        // a band spanning the full width near the top edge: the ideal square
+        // around it reaches far above the image
+        let img = ImageRgba8(ImageBuffer::from_fn(300, 200, |x, y| {
+            if (10..30).contains(&y) && (x / 4 + y / 4) % 2 == 0 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([40, 40, 40, 255])
+            }
+        }));
+        let opts = SmartCrop {
+            width: 100,
+            height: 100,
+            ..SmartCrop::default()
+        };
+        let sc = SmartCrop {
+            mode: CropMode::Fit,
+            ..SmartCrop::default()
+        };
+        let result = sc.crop_image(&img, &opts);
+        assert!(result.padding.top > 80, "{:?}", result.padding);
+        assert_eq!(result.padding.bottom, 0);
+
+        let row = |output: &image::DynamicImage, y: u32| {
+            let output = output.to_rgba8();
+            (0..100)
+                .map(|x| output.get_pixel(x, y)[0] as f64)
+                .sum::<f64>()
+                / 100.
+        };
+        let fill = Rgba([255, 0, 0, 255]);
+        let filled = sc.padded_crop(&img, PadFill::Color(fill), &opts).unwrap();
+        assert_eq!(filled.dimensions(), (100, 100));
+        assert_eq!(filled.to_rgba8().get_pixel(50, 10), &fill);
+        // the band itself sits about a third of the way down, below the padding
+        assert!(row(&filled, 38) > 100., "{}", row(&filled, 38));
+
+        let reflected = sc.padded_crop(&img, PadFill::Reflect, &opts).unwrap();
+        assert_eq!(reflected.dimensions(), (100, 100));
+        // the band's mirror image shows up in the padding just above the edge
+        assert!(row(&reflected, 24) > 100., "{}", row(&reflected, 24));
+        assert!(row(&reflected, 5) < 50., "{}", row(&reflected, 5));
+        assert_eq!(
+            reflected.to_rgba8().get_pixel(50, 60),
+            filled.to_rgba8().get_pixel(50, 60)
+        );
     }
 }
@@ -0,0 +1,32 @@
+use image::{ImageBuffer, Rgba};
+use smartcrop::SmartCrop;
+
+#[test]
+fn best_per_scale_exposes_scores() {
+    // a skin toned disk on grey, which smaller crops frame more tightly
+    let img = ImageBuffer::from_fn(300, 200, |x, y| {
+        let (dx, dy) = (x as f64 - 200., y as f64 - 100.);
+        if dx * dx + dy * dy < 40. * 40. {
+            Rgba([200, 160, 135, 255])
+        } else {
+            Rgba([100, 100, 100, 255])
+        }
+    });
+    let mut cfg = SmartCrop::new().to_config();
+    cfg.min_scale = 0.6;
+    let sc = SmartCrop::from_config(&cfg);
+    let mut opts = SmartCrop::new();
+    opts.width = 100;
+    opts.height = 100;
+    let analysis = sc.prepare(&img, &opts);
+    let best = sc.best_per_scale(&analysis, &opts);
+    assert!(best.len() > 1);
+    let top = sc.rescore(&analysis, &opts).top_crop;
+    for (_, crop) in best.iter() {
+        assert!(crop.score.total <= top.score.total);
+        assert!(crop.score.skin != 0., "{}", crop);
+    }
+    assert!(best
+        .iter()
+        .any(|(_, crop)| crop.score.total == top.score.total));
+}
@@ -0,0 +1,60 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use image::{ImageBuffer, Rgba};
+use smartcrop::{SmartCrop, SmartCropSession};
+
+// counts the heap bytes allocated; this file holds a single test so that
+// nothing else allocates concurrently
+struct CountingAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+#[test]
+fn session_reuses_buffers_across_images() {
+    let images: Vec<_> = (0..4u32)
+        .map(|i| {
+            ImageBuffer::from_fn(640, 480, move |x, y| {
+                let (dx, dy) = (x as f64 - 100. * (i + 1) as f64, y as f64 - 240.);
+                if dx * dx + dy * dy < 80. * 80. && (x / 4 + y / 4) % 2 == 0 {
+                    Rgba([200, 160, 135, 255])
+                } else {
+                    Rgba([100, 100, 100, 255])
+                }
+            })
+        })
+        .collect();
+    let mut opts = SmartCrop::new();
+    opts.width = 100;
+    opts.height = 100;
+    let mut sc = SmartCrop::new();
+    sc.stable_resize = true;
+
+    let mut session = SmartCropSession::new(sc.clone());
+    let mut allocated = Vec::new();
+    for img in images.iter() {
+        let before = ALLOCATED.load(Ordering::SeqCst);
+        let result = session.crop_into(img, &opts);
+        allocated.push(ALLOCATED.load(Ordering::SeqCst) - before);
+        let fresh = sc.crop_image(img, &opts);
+        assert_eq!(result.top_crop.size, fresh.top_crop.size);
+    }
+    // the buffers and candidates of the first image serve the others
+    for later in allocated[1..].iter() {
+        assert!(*later * 5 < allocated[0] * 4, "{:?}", allocated);
+    }
+}
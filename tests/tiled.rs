@@ -0,0 +1,97 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use smartcrop::SmartCrop;
+use tiff::encoder::{colortype, TiffEncoder};
+
+// tracks the peak number of live heap bytes; this file holds a single test so
+// that nothing else allocates concurrently
+struct PeakAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(current, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAlloc = PeakAlloc;
+
+const WIDTH: u32 = 3000;
+const HEIGHT: u32 = 2000;
+const ROWS_PER_STRIP: u32 = 16;
+
+fn pixel(x: u32, y: u32) -> [u8; 3] {
+    let (dx, dy) = (x as i64 - 2200, y as i64 - 600);
+    if dx * dx + dy * dy < 250 * 250 {
+        // textured subject
+        if (x / 12 + y / 12) % 2 == 1 {
+            [230, 60, 40]
+        } else {
+            [40, 60, 230]
+        }
+    } else {
+        [(x / 20) as u8, 100, (y / 10) as u8]
+    }
+}
+
+fn write_tiff(path: &std::path::Path) {
+    let mut encoder = TiffEncoder::new(BufWriter::new(File::create(path).unwrap())).unwrap();
+    let mut image = encoder.new_image::<colortype::RGB8>(WIDTH, HEIGHT).unwrap();
+    image.rows_per_strip(ROWS_PER_STRIP).unwrap();
+    let mut y = 0;
+    while image.next_strip_sample_count() > 0 {
+        let rows = u32::min(ROWS_PER_STRIP, HEIGHT - y);
+        let mut strip = Vec::with_capacity((WIDTH * rows * 3) as usize);
+        for y in y..y + rows {
+            for x in 0..WIDTH {
+                strip.extend_from_slice(&pixel(x, y));
+            }
+        }
+        image.write_strip(&strip).unwrap();
+        y += rows;
+    }
+    image.finish().unwrap();
+}
+
+#[test]
+fn tiled_crop_bounds_memory() {
+    let path = std::env::temp_dir().join(format!("smartcrop-tiled-{}.tif", std::process::id()));
+    write_tiff(&path);
+
+    let mut opts = SmartCrop::new();
+    opts.width = 100;
+    opts.height = 100;
+    let mut sc = SmartCrop::new();
+    sc.stable_resize = true;
+
+    let baseline = CURRENT.load(Ordering::SeqCst);
+    PEAK.store(baseline, Ordering::SeqCst);
+    let tiled = sc.crop_tiled(&path, &opts).unwrap();
+    let peak = PEAK.load(Ordering::SeqCst) - baseline;
+
+    // decoding the whole image would take WIDTH * HEIGHT * 3 = 18MB
+    assert!(peak < 2 << 20, "peak heap use {} bytes", peak);
+
+    let full = sc.crop(&path, &opts).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(tiled.top_crop.size, full.top_crop.size);
+    assert_eq!(tiled.prescale, full.prescale);
+    let size = tiled.top_crop.size;
+    assert!(size.x <= 2200 && 2200 < size.x + size.width);
+}